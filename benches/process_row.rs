@@ -0,0 +1,54 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use payments_engine::{
+    engine::Engine,
+    models::{TransactionType, TxRow},
+};
+use rust_decimal::Decimal;
+
+const ROWS: u32 = 100_000;
+
+/// Rows spread round robin over `clients` clients: out of every ten, six
+/// deposits, three withdrawals of part of a deposit and one dispute of the
+/// deposit read nine rows earlier.
+fn workload(clients: u32) -> Vec<TxRow> {
+    (0..ROWS)
+        .map(|i| {
+            let (tx_type, tx_id, amount) = match i % 10 {
+                0..=5 => (TransactionType::Deposit, i, Some(Decimal::new(1000, 2))),
+                6..=8 => (TransactionType::Withdrawal, i, Some(Decimal::new(250, 2))),
+                _ => (TransactionType::Dispute, i - 9, None),
+            };
+            TxRow {
+                tx_type,
+                client_id: (tx_id % clients) as u16,
+                tx_id,
+                amount,
+                dest: None,
+                timestamp: None,
+            }
+        })
+        .collect()
+}
+
+fn process_row(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_row");
+    group.throughput(Throughput::Elements(ROWS.into()));
+    for clients in [10, 1_000, 60_000] {
+        let rows = workload(clients);
+        group.bench_with_input(BenchmarkId::from_parameter(clients), &rows, |b, rows| {
+            b.iter(|| {
+                let mut engine = Engine::new();
+                for row in rows {
+                    let _ = engine.process_row(black_box(row));
+                }
+                engine
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process_row);
+criterion_main!(benches);
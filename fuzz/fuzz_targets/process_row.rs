@@ -0,0 +1,56 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{
+    engine::Engine,
+    models::{TransactionType, TxRow},
+};
+use rust_decimal::Decimal;
+
+const TX_TYPES: [TransactionType; 11] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Resolve,
+    TransactionType::Dispute,
+    TransactionType::Chargeback,
+    TransactionType::Reversal,
+    TransactionType::Interest,
+    TransactionType::Transfer,
+    TransactionType::Hold,
+    TransactionType::Capture,
+    TransactionType::Cancel,
+];
+
+#[derive(Arbitrary, Debug)]
+struct FuzzRow {
+    tx_type: u8,
+    client_id: u16,
+    tx_id: u32,
+    // lo, mid, hi, sign and scale of the Decimal
+    amount: Option<(u32, u32, u32, bool, u8)>,
+    dest: Option<u16>,
+}
+
+impl From<FuzzRow> for TxRow {
+    fn from(row: FuzzRow) -> Self {
+        TxRow {
+            tx_type: TX_TYPES[usize::from(row.tx_type) % TX_TYPES.len()],
+            client_id: row.client_id,
+            tx_id: row.tx_id,
+            amount: row.amount.map(|(lo, mid, hi, negative, scale)| {
+                Decimal::from_parts(lo, mid, hi, negative, u32::from(scale) % 29)
+            }),
+            dest: row.dest,
+            timestamp: None,
+        }
+    }
+}
+
+// process_row must fail rows it cannot apply rather than panic
+fuzz_target!(|rows: Vec<FuzzRow>| {
+    let mut engine = Engine::new();
+    for row in rows {
+        let _ = engine.process_row(&row.into());
+    }
+});
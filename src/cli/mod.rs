@@ -0,0 +1,822 @@
+use std::{error, fmt, num::NonZeroU64, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+use log::LevelFilter;
+
+use crate::{
+    engine::{AmountScalePolicy, ConfigError, EngineConfig, Rounding, DEFAULT_PRECISION},
+    models::MAX_MINOR_UNIT_SCALE,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogDestination {
+    File(PathBuf),
+    Stderr,
+}
+
+impl Default for LogDestination {
+    fn default() -> Self {
+        LogDestination::File(PathBuf::from("output.log"))
+    }
+}
+
+/// How log records are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, see `logging::init_logging`.
+    Json,
+}
+
+/// What `--amount-scale` does with amounts finer than the engine's precision,
+/// see `AmountScalePolicy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AmountScale {
+    #[default]
+    Keep,
+    Reject,
+    Round,
+}
+
+/// The order accounts are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccountOrder {
+    #[default]
+    ClientId,
+    /// The order the clients' accounts were opened in.
+    FirstSeen,
+}
+
+/// The format accounts, or in a dry run error counts, are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+/// What happens to rows without a timestamp while `--from` or `--to` is given.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UntimedRowPolicy {
+    /// Fail the row as a `MissingTimestamp` error.
+    #[default]
+    Error,
+    /// Leave the row out like one outside the window.
+    Skip,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Args {
+    pub filepath: String,
+    pub dry_run: bool,
+    pub log_level: LevelFilter,
+    pub log_destination: LogDestination,
+    pub log_format: LogFormat,
+    pub max_errors: Option<usize>,
+    pub has_headers: bool,
+    pub trim: csv::Trim,
+    pub delimiter: u8,
+    // whether amounts use a comma rather than a point as decimal separator
+    pub decimal_comma: bool,
+    pub amount_scale: AmountScale,
+    pub tx_retention: Option<u64>,
+    pub hold_expiry: Option<u64>,
+    pub rounding: Option<Rounding>,
+    pub config: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    // where to write the rows that failed, with their errors, as CSV
+    pub error_output: Option<PathBuf>,
+    pub minor_units: Option<u32>,
+    pub order: AccountOrder,
+    pub output: OutputFormat,
+    // rows are processed if timestamped at or after from and before to
+    pub from: Option<DateTime<FixedOffset>>,
+    pub to: Option<DateTime<FixedOffset>>,
+    pub untimed: UntimedRowPolicy,
+    // whether ending with any locked account fails the run
+    pub fail_on_lock: bool,
+    // whether a TOTAL row follows the accounts
+    pub summary_row: bool,
+    // whether unlocked accounts without funds are left out of the output
+    pub nonzero_only: bool,
+    // whether disputes, resolves and chargebacks of unseen txs are retried
+    // at the end of the file
+    pub defer_unknown: bool,
+    // whether exact duplicates of stored deposits and withdrawals are ignored
+    pub idempotent: bool,
+    // whether to keep reading rows appended to the file until interrupted
+    pub follow: bool,
+    // report progress on stderr every this many rows
+    pub progress: Option<NonZeroU64>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            filepath: String::new(),
+            dry_run: false,
+            log_level: LevelFilter::Debug,
+            log_destination: LogDestination::default(),
+            log_format: LogFormat::default(),
+            max_errors: None,
+            has_headers: true,
+            trim: csv::Trim::All,
+            delimiter: b',',
+            decimal_comma: false,
+            amount_scale: AmountScale::default(),
+            tx_retention: None,
+            hold_expiry: None,
+            rounding: None,
+            config: None,
+            record: None,
+            error_output: None,
+            minor_units: None,
+            order: AccountOrder::default(),
+            output: OutputFormat::default(),
+            from: None,
+            to: None,
+            untimed: UntimedRowPolicy::default(),
+            fail_on_lock: false,
+            summary_row: false,
+            nonzero_only: false,
+            defer_unknown: false,
+            idempotent: false,
+            follow: false,
+            progress: None,
+        }
+    }
+}
+
+impl Args {
+    /// Parses the program arguments, excluding the program name itself.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        let mut filepath = None;
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dry-run" => parsed.dry_run = true,
+                "--no-header" => parsed.has_headers = false,
+                // accounts are always written in order, kept so that existing scripts still run
+                "--sort" | "--no-sort" => {}
+                "--fail-on-lock" => parsed.fail_on_lock = true,
+                "--summary-row" => parsed.summary_row = true,
+                "--nonzero-only" => parsed.nonzero_only = true,
+                "--decimal-comma" => parsed.decimal_comma = true,
+                "--defer-unknown" => parsed.defer_unknown = true,
+                "--idempotent" => parsed.idempotent = true,
+                // without Ctrl-C handling nothing would ever end the run
+                "--follow" if !cfg!(feature = "interrupt") => {
+                    return Err(ArgsError::NeedsFeature {
+                        flag: arg,
+                        feature: "interrupt",
+                    });
+                }
+                "--follow" => parsed.follow = true,
+                "--log-level" => {
+                    parsed.log_level = parse_log_level(&next_value(&mut args, &arg)?)?;
+                }
+                "--log-file" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.log_destination = match value.as_str() {
+                        "-" => LogDestination::Stderr,
+                        _ => LogDestination::File(PathBuf::from(value)),
+                    };
+                }
+                "--log-format" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.log_format = match value.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--trim" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.trim = match value.as_str() {
+                        "none" => csv::Trim::None,
+                        "headers" => csv::Trim::Headers,
+                        "fields" => csv::Trim::Fields,
+                        "all" => csv::Trim::All,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--delimiter" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.delimiter = match value.as_bytes() {
+                        [delimiter] if delimiter.is_ascii() => *delimiter,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--amount-scale" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.amount_scale = match value.as_str() {
+                        "keep" => AmountScale::Keep,
+                        "reject" => AmountScale::Reject,
+                        "round" => AmountScale::Round,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--rounding" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.rounding = Some(match value.as_str() {
+                        "half-up" => Rounding::HalfUp,
+                        "half-even" => Rounding::HalfEven,
+                        "truncate" => Rounding::Truncate,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    });
+                }
+                "--order" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.order = match value.as_str() {
+                        "client-id" => AccountOrder::ClientId,
+                        "first-seen" => AccountOrder::FirstSeen,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--from" => {
+                    parsed.from = Some(parse_timestamp(&arg, next_value(&mut args, &arg)?)?);
+                }
+                "--to" => {
+                    parsed.to = Some(parse_timestamp(&arg, next_value(&mut args, &arg)?)?);
+                }
+                "--untimed" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.untimed = match value.as_str() {
+                        "error" => UntimedRowPolicy::Error,
+                        "skip" => UntimedRowPolicy::Skip,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--output" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.output = match value.as_str() {
+                        "csv" => OutputFormat::Csv,
+                        "ndjson" => OutputFormat::Ndjson,
+                        _ => return Err(ArgsError::InvalidValue { flag: arg, value }),
+                    };
+                }
+                "--config" => {
+                    parsed.config = Some(PathBuf::from(next_value(&mut args, &arg)?));
+                }
+                "--record" => {
+                    parsed.record = Some(PathBuf::from(next_value(&mut args, &arg)?));
+                }
+                "--error-output" => {
+                    parsed.error_output = Some(PathBuf::from(next_value(&mut args, &arg)?));
+                }
+                "--minor-units" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let scale: u32 = parse_value(&arg, value.clone())?;
+                    if scale > MAX_MINOR_UNIT_SCALE {
+                        return Err(ArgsError::InvalidValue { flag: arg, value });
+                    }
+                    parsed.minor_units = Some(scale);
+                }
+                "--tx-retention" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.tx_retention = Some(parse_value(&arg, value)?);
+                }
+                "--hold-expiry" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.hold_expiry = Some(parse_value(&arg, value)?);
+                }
+                "--max-errors" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.max_errors = Some(parse_value(&arg, value)?);
+                }
+                "--progress" => {
+                    let value = next_value(&mut args, &arg)?;
+                    parsed.progress = Some(parse_value(&arg, value)?);
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(ArgsError::UnknownFlag(arg));
+                }
+                _ if filepath.is_none() => filepath = Some(arg),
+                _ => return Err(ArgsError::UnexpectedArgument(arg)),
+            }
+        }
+        parsed.filepath = filepath.ok_or(ArgsError::MissingFilepath)?;
+        Ok(parsed)
+    }
+
+    /// Loads the `--config` file, if any, with the flags given overriding
+    /// the settings it shares with them.
+    pub fn engine_config(&self) -> Result<EngineConfig, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => EngineConfig::load(path)?,
+            None => EngineConfig::default(),
+        };
+        if self.rounding.is_some() {
+            config.rounding = self.rounding;
+        }
+        if self.tx_retention.is_some() {
+            config.tx_retention = self.tx_retention;
+        }
+        Ok(config)
+    }
+
+    /// The `--amount-scale` policy at the precision `config` sets, or the
+    /// default one.
+    pub fn amount_scale_policy(&self, config: &EngineConfig) -> AmountScalePolicy {
+        let precision = config.precision.unwrap_or(DEFAULT_PRECISION);
+        match self.amount_scale {
+            AmountScale::Keep => AmountScalePolicy::Keep,
+            AmountScale::Reject => AmountScalePolicy::Reject(precision),
+            AmountScale::Round => AmountScalePolicy::Round(precision),
+        }
+    }
+
+    /// Whether a row with `timestamp` falls within `--from` and `--to`, or
+    /// `None` for a row without a timestamp while either is given.
+    pub fn in_time_window(&self, timestamp: Option<DateTime<FixedOffset>>) -> Option<bool> {
+        if self.from.is_none() && self.to.is_none() {
+            return Some(true);
+        }
+        let timestamp = timestamp?;
+        Some(
+            self.from.is_none_or(|from| timestamp >= from)
+                && self.to.is_none_or(|to| timestamp < to),
+        )
+    }
+}
+
+fn next_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, ArgsError> {
+    args.next()
+        .ok_or_else(|| ArgsError::MissingValue(flag.to_string()))
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+fn parse_timestamp(flag: &str, value: String) -> Result<DateTime<FixedOffset>, ArgsError> {
+    DateTime::parse_from_rfc3339(&value).map_err(|_| ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+pub fn parse_log_level(value: &str) -> Result<LevelFilter, ArgsError> {
+    match value {
+        "error" => Ok(LevelFilter::Error),
+        "warn" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        "debug" => Ok(LevelFilter::Debug),
+        "trace" => Ok(LevelFilter::Trace),
+        _ => Err(ArgsError::InvalidValue {
+            flag: "--log-level".to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    MissingFilepath,
+    UnknownFlag(String),
+    UnexpectedArgument(String),
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    /// The flag is only supported when built with the feature.
+    NeedsFeature {
+        flag: String,
+        feature: &'static str,
+    },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::MissingFilepath => write!(f, "filepath is missing from arguments"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "unexpected argument {}", arg),
+            ArgsError::MissingValue(flag) => write!(f, "flag {} requires a value", flag),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value {} for flag {}", value, flag)
+            }
+            ArgsError::NeedsFeature { flag, feature } => {
+                write!(
+                    f,
+                    "flag {} requires building with the {} feature",
+                    flag, feature
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for ArgsError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, num::NonZeroU64, path::PathBuf, process};
+
+    use log::LevelFilter;
+
+    use crate::engine::{AmountScalePolicy, EngineConfig, Rounding};
+
+    use super::{
+        parse_log_level, AccountOrder, AmountScale, Args, ArgsError, LogDestination, LogFormat,
+        OutputFormat, UntimedRowPolicy,
+    };
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn should_parse_filepath() {
+        let args = parse(&["transactions.csv"]).unwrap();
+        assert_eq!(args.filepath, "transactions.csv");
+        assert!(!args.dry_run);
+        assert_eq!(args.log_level, LevelFilter::Debug);
+        assert_eq!(
+            args.log_destination,
+            LogDestination::File(PathBuf::from("output.log"))
+        );
+    }
+
+    #[test]
+    fn should_parse_dry_run_flag() {
+        let args = parse(&["--dry-run", "transactions.csv"]).unwrap();
+        assert_eq!(args.filepath, "transactions.csv");
+        assert!(args.dry_run);
+    }
+
+    #[test]
+    fn should_parse_fail_on_lock_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().fail_on_lock);
+        let args = parse(&["--fail-on-lock", "transactions.csv"]).unwrap();
+        assert!(args.fail_on_lock);
+    }
+
+    #[test]
+    fn should_parse_summary_row_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().summary_row);
+        let args = parse(&["--summary-row", "transactions.csv"]).unwrap();
+        assert!(args.summary_row);
+    }
+
+    #[test]
+    fn should_parse_nonzero_only_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().nonzero_only);
+        let args = parse(&["--nonzero-only", "transactions.csv"]).unwrap();
+        assert!(args.nonzero_only);
+    }
+
+    #[test]
+    #[cfg(feature = "interrupt")]
+    fn should_parse_follow_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().follow);
+        let args = parse(&["--follow", "transactions.csv"]).unwrap();
+        assert!(args.follow);
+    }
+
+    #[test]
+    #[cfg(not(feature = "interrupt"))]
+    fn should_not_parse_follow_flag_without_interrupt_feature() {
+        assert_eq!(
+            parse(&["--follow", "transactions.csv"]),
+            Err(ArgsError::NeedsFeature {
+                flag: "--follow".to_string(),
+                feature: "interrupt",
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_defer_unknown_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().defer_unknown);
+        let args = parse(&["--defer-unknown", "transactions.csv"]).unwrap();
+        assert!(args.defer_unknown);
+    }
+
+    #[test]
+    fn should_parse_idempotent_flag() {
+        assert!(!parse(&["transactions.csv"]).unwrap().idempotent);
+        let args = parse(&["--idempotent", "transactions.csv"]).unwrap();
+        assert!(args.idempotent);
+    }
+
+    #[test]
+    fn should_parse_no_header_flag() {
+        assert!(parse(&["transactions.csv"]).unwrap().has_headers);
+        let args = parse(&["transactions.csv", "--no-header"]).unwrap();
+        assert!(!args.has_headers);
+    }
+
+    #[test]
+    fn should_parse_sort_flags() {
+        for flag in ["--sort", "--no-sort"] {
+            assert_eq!(
+                parse(&[flag, "transactions.csv"]),
+                parse(&["transactions.csv"])
+            );
+        }
+    }
+
+    #[test]
+    fn should_not_parse_without_filepath() {
+        assert_eq!(parse(&["--dry-run"]), Err(ArgsError::MissingFilepath));
+    }
+
+    #[test]
+    fn should_not_parse_unknown_flag() {
+        assert_eq!(
+            parse(&["--bogus", "transactions.csv"]),
+            Err(ArgsError::UnknownFlag("--bogus".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_parse_trim() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().trim, csv::Trim::All);
+        let args = parse(&["--trim", "none", "transactions.csv"]).unwrap();
+        assert_eq!(args.trim, csv::Trim::None);
+        let args = parse(&["--trim", "headers", "transactions.csv"]).unwrap();
+        assert_eq!(args.trim, csv::Trim::Headers);
+        let args = parse(&["--trim", "fields", "transactions.csv"]).unwrap();
+        assert_eq!(args.trim, csv::Trim::Fields);
+        assert!(parse(&["--trim", "both", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_delimiter_and_decimal_comma() {
+        let args = parse(&["transactions.csv"]).unwrap();
+        assert_eq!(args.delimiter, b',');
+        assert!(!args.decimal_comma);
+        let args = parse(&["--delimiter", ";", "--decimal-comma", "transactions.csv"]).unwrap();
+        assert_eq!(args.delimiter, b';');
+        assert!(args.decimal_comma);
+        assert!(parse(&["--delimiter", ";;", "transactions.csv"]).is_err());
+        assert!(parse(&["--delimiter", "§", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_amount_scale() {
+        assert_eq!(
+            parse(&["transactions.csv"]).unwrap().amount_scale,
+            AmountScale::Keep
+        );
+        let args = parse(&["--amount-scale", "round", "transactions.csv"]).unwrap();
+        assert_eq!(args.amount_scale, AmountScale::Round);
+        let args = parse(&["--amount-scale", "reject", "transactions.csv"]).unwrap();
+        assert_eq!(args.amount_scale, AmountScale::Reject);
+        assert_eq!(
+            parse(&["--amount-scale", "truncate", "transactions.csv"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--amount-scale".to_string(),
+                value: "truncate".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_time_window() {
+        let args = parse(&["transactions.csv"]).unwrap();
+        assert_eq!((args.from, args.to), (None, None));
+        assert_eq!(args.untimed, UntimedRowPolicy::Error);
+        let args = parse(&[
+            "--from",
+            "2024-01-01T00:00:00Z",
+            "--to",
+            "2024-02-01T00:00:00+01:00",
+            "--untimed",
+            "skip",
+            "transactions.csv",
+        ])
+        .unwrap();
+        assert_eq!(args.from.unwrap().to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(args.to.unwrap().to_rfc3339(), "2024-02-01T00:00:00+01:00");
+        assert_eq!(args.untimed, UntimedRowPolicy::Skip);
+        assert!(parse(&["--from", "2024-01-01", "transactions.csv"]).is_err());
+        assert!(parse(&["--untimed", "keep", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_check_time_window() {
+        let at = |value: &str| Some(value.parse().unwrap());
+        let args = Args::default();
+        assert_eq!(args.in_time_window(None), Some(true));
+        let args = parse(&["--from", "2024-01-01T00:00:00Z", "transactions.csv"]).unwrap();
+        assert_eq!(args.in_time_window(at("2024-01-01T00:00:00Z")), Some(true));
+        assert_eq!(
+            args.in_time_window(at("2024-01-01T00:30:00+01:00")),
+            Some(false)
+        );
+        assert_eq!(args.in_time_window(None), None);
+        let args = parse(&["--to", "2024-01-01T00:00:00Z", "transactions.csv"]).unwrap();
+        assert_eq!(args.in_time_window(at("2024-01-01T00:00:00Z")), Some(false));
+        assert_eq!(args.in_time_window(at("2023-12-31T23:59:59Z")), Some(true));
+    }
+
+    #[test]
+    fn should_parse_log_format() {
+        assert_eq!(
+            parse(&["transactions.csv"]).unwrap().log_format,
+            LogFormat::Text
+        );
+        let args = parse(&["--log-format", "json", "transactions.csv"]).unwrap();
+        assert_eq!(args.log_format, LogFormat::Json);
+        assert!(parse(&["--log-format", "xml", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_output_format() {
+        assert_eq!(
+            parse(&["transactions.csv"]).unwrap().output,
+            OutputFormat::Csv
+        );
+        let args = parse(&["--output", "ndjson", "transactions.csv"]).unwrap();
+        assert_eq!(args.output, OutputFormat::Ndjson);
+        assert!(parse(&["--output", "json", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_rounding() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().rounding, None);
+        let args = parse(&["--rounding", "half-up", "transactions.csv"]).unwrap();
+        assert_eq!(args.rounding, Some(Rounding::HalfUp));
+        let args = parse(&["--rounding", "truncate", "transactions.csv"]).unwrap();
+        assert_eq!(args.rounding, Some(Rounding::Truncate));
+        assert!(parse(&["--rounding", "ceiling", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_minor_units() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().minor_units, None);
+        let args = parse(&["--minor-units", "2", "transactions.csv"]).unwrap();
+        assert_eq!(args.minor_units, Some(2));
+        assert_eq!(
+            parse(&["--minor-units", "29", "transactions.csv"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--minor-units".to_string(),
+                value: "29".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_order() {
+        assert_eq!(
+            parse(&["transactions.csv"]).unwrap().order,
+            AccountOrder::ClientId
+        );
+        let args = parse(&["--order", "first-seen", "transactions.csv"]).unwrap();
+        assert_eq!(args.order, AccountOrder::FirstSeen);
+        let args = parse(&["--order", "client-id", "transactions.csv"]).unwrap();
+        assert_eq!(args.order, AccountOrder::ClientId);
+        assert!(parse(&["--order", "last-seen", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_tx_retention() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().tx_retention, None);
+        let args = parse(&["--tx-retention", "10000", "transactions.csv"]).unwrap();
+        assert_eq!(args.tx_retention, Some(10000));
+    }
+
+    #[test]
+    fn should_parse_error_output() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().error_output, None);
+        let args = parse(&["--error-output", "errors.csv", "transactions.csv"]).unwrap();
+        assert_eq!(args.error_output, Some(PathBuf::from("errors.csv")));
+    }
+
+    #[test]
+    fn should_parse_progress() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().progress, None);
+        let args = parse(&["--progress", "1000", "transactions.csv"]).unwrap();
+        assert_eq!(args.progress, NonZeroU64::new(1000));
+        assert!(matches!(
+            parse(&["--progress", "0", "transactions.csv"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn should_scale_amounts_to_config_precision() {
+        let args = parse(&["--amount-scale", "round", "transactions.csv"]).unwrap();
+        assert_eq!(
+            args.amount_scale_policy(&EngineConfig::default()),
+            AmountScalePolicy::Round(4)
+        );
+        let config = EngineConfig::from_toml("precision = 2").unwrap();
+        assert_eq!(
+            args.amount_scale_policy(&config),
+            AmountScalePolicy::Round(2)
+        );
+        let args = parse(&["--amount-scale", "reject", "transactions.csv"]).unwrap();
+        assert_eq!(
+            args.amount_scale_policy(&config),
+            AmountScalePolicy::Reject(2)
+        );
+    }
+
+    #[test]
+    fn should_parse_hold_expiry() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().hold_expiry, None);
+        let args = parse(&["--hold-expiry", "50", "transactions.csv"]).unwrap();
+        assert_eq!(args.hold_expiry, Some(50));
+    }
+
+    #[test]
+    fn should_override_config_file_with_flags() {
+        let path = env::temp_dir().join(format!("payments_engine_args_{}.toml", process::id()));
+        fs::write(
+            &path,
+            "precision = 2\nrounding = \"truncate\"\ntx-retention = 500\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let config = parse(&["--config", path, "transactions.csv"])
+            .unwrap()
+            .engine_config()
+            .unwrap();
+        assert_eq!(config.rounding, Some(Rounding::Truncate));
+        assert_eq!(config.tx_retention, Some(500));
+        let config = parse(&[
+            "--config",
+            path,
+            "--rounding",
+            "half-up",
+            "--tx-retention",
+            "20",
+            "transactions.csv",
+        ])
+        .unwrap()
+        .engine_config()
+        .unwrap();
+        assert_eq!(config.precision, Some(2));
+        assert_eq!(config.rounding, Some(Rounding::HalfUp));
+        assert_eq!(config.tx_retention, Some(20));
+        // without a file only the flags are set
+        let config = parse(&["--rounding", "half-up", "transactions.csv"])
+            .unwrap()
+            .engine_config()
+            .unwrap();
+        assert_eq!(
+            config,
+            EngineConfig {
+                rounding: Some(Rounding::HalfUp),
+                ..EngineConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn should_parse_max_errors() {
+        let args = parse(&["--max-errors", "3", "transactions.csv"]).unwrap();
+        assert_eq!(args.max_errors, Some(3));
+        assert_eq!(parse(&["transactions.csv"]).unwrap().max_errors, None);
+        assert_eq!(
+            parse(&["--max-errors", "many", "transactions.csv"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--max-errors".to_string(),
+                value: "many".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn should_map_log_levels() {
+        assert_eq!(parse_log_level("error"), Ok(LevelFilter::Error));
+        assert_eq!(parse_log_level("warn"), Ok(LevelFilter::Warn));
+        assert_eq!(parse_log_level("info"), Ok(LevelFilter::Info));
+        assert_eq!(parse_log_level("debug"), Ok(LevelFilter::Debug));
+        assert_eq!(parse_log_level("trace"), Ok(LevelFilter::Trace));
+        assert_eq!(
+            parse_log_level("loud"),
+            Err(ArgsError::InvalidValue {
+                flag: "--log-level".to_string(),
+                value: "loud".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_log_options() {
+        let args = parse(&["--log-level", "warn", "--log-file", "-", "transactions.csv"]).unwrap();
+        assert_eq!(args.log_level, LevelFilter::Warn);
+        assert_eq!(args.log_destination, LogDestination::Stderr);
+
+        let args = parse(&["transactions.csv", "--log-file", "/tmp/run.log"]).unwrap();
+        assert_eq!(
+            args.log_destination,
+            LogDestination::File(PathBuf::from("/tmp/run.log"))
+        );
+        assert_eq!(
+            parse(&["transactions.csv", "--log-level"]),
+            Err(ArgsError::MissingValue("--log-level".to_string()))
+        );
+    }
+}
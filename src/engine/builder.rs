@@ -0,0 +1,480 @@
+use std::{collections::VecDeque, io::Write, ops::RangeInclusive, sync::Arc};
+
+use crate::models::{ClientId, TxId, TxIdScope};
+
+use super::{
+    hook::{Hook, Shared},
+    recorder::Recorder,
+    AmountScalePolicy, DisputePolicy, Engine, EngineConfig, HeldUnderflowPolicy, InterestPolicy,
+    LockedAccountPolicy, Rounding, RowGuard, RowHook, UndoLog, WithdrawalFee, DEFAULT_PRECISION,
+};
+
+/// Configures an `Engine` fluently. `Engine::new()` is equivalent to
+/// `EngineBuilder::new().build()`; for ids other than the default `u16`/`u32`
+/// start from e.g. `EngineBuilder::<u64, u64>::default()`.
+#[derive(Clone, Debug)]
+pub struct EngineBuilder<C = u16, T = u32> {
+    dispute_policy: DisputePolicy,
+    locked_policy: LockedAccountPolicy,
+    auto_unlock: bool,
+    held_underflow_policy: HeldUnderflowPolicy,
+    max_accounts: Option<usize>,
+    interest_policy: InterestPolicy,
+    amount_scale_policy: AmountScalePolicy,
+    strict_amounts: bool,
+    strict_withdrawals: bool,
+    idempotent: bool,
+    withdrawal_fee: Option<WithdrawalFee<C>>,
+    client_id_range: Option<RangeInclusive<C>>,
+    tx_id_range: Option<RangeInclusive<T>>,
+    tx_id_scope: TxIdScope,
+    tx_retention: Option<u64>,
+    hold_expiry: Option<u64>,
+    precision: u32,
+    rounding: Rounding,
+    audit: bool,
+    undo: Option<usize>,
+    recorder: Recorder,
+    row_hook: Hook<RowHook<C, T>>,
+    guard: Shared<RowGuard<C, T>>,
+}
+
+impl<C: ClientId, T: TxId> Default for EngineBuilder<C, T> {
+    fn default() -> Self {
+        Self {
+            dispute_policy: DisputePolicy::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            auto_unlock: false,
+            held_underflow_policy: HeldUnderflowPolicy::default(),
+            max_accounts: None,
+            interest_policy: InterestPolicy::default(),
+            amount_scale_policy: AmountScalePolicy::default(),
+            strict_amounts: false,
+            strict_withdrawals: false,
+            idempotent: false,
+            withdrawal_fee: None,
+            client_id_range: None,
+            tx_id_range: None,
+            tx_id_scope: TxIdScope::default(),
+            tx_retention: None,
+            hold_expiry: None,
+            precision: DEFAULT_PRECISION,
+            rounding: Rounding::default(),
+            audit: false,
+            undo: None,
+            recorder: Recorder::default(),
+            row_hook: Hook::default(),
+            guard: Shared::default(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the settings present in `config`, leaving the others as they are.
+    pub fn config(mut self, config: &EngineConfig) -> Self {
+        if let Some(precision) = config.precision {
+            self.precision = precision;
+        }
+        if let Some(rounding) = config.rounding {
+            self.rounding = rounding;
+        }
+        if let Some(policy) = config.dispute_policy {
+            self.dispute_policy = policy;
+        }
+        if let Some(policy) = config.locked_policy {
+            self.locked_policy = policy;
+        }
+        if let Some(enabled) = config.auto_unlock {
+            self.auto_unlock = enabled;
+        }
+        if let Some(policy) = config.held_underflow {
+            self.held_underflow_policy = policy;
+        }
+        if let Some(max) = config.max_accounts {
+            self.max_accounts = Some(max);
+        }
+        if let Some(rows) = config.tx_retention {
+            self.tx_retention = Some(rows);
+        }
+        if let Some(fee) = config.withdrawal_fee {
+            self.withdrawal_fee = Some(fee);
+        }
+        if let Some(range) = &config.client_id_range {
+            self.client_id_range = Some(range.clone());
+        }
+        if let Some(range) = &config.tx_id_range {
+            self.tx_id_range = Some(range.clone());
+        }
+        self
+    }
+}
+
+impl<C: ClientId, T: TxId> EngineBuilder<C, T> {
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    pub fn with_locked_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_policy = policy;
+        self
+    }
+
+    /// Unlocks an account once a deposit accepted under
+    /// `LockedAccountPolicy::AllowDeposits` leaves it with no held funds, no
+    /// disputed transactions and non-negative available funds, e.g. after an
+    /// operator made a charged back client whole. Off by default.
+    pub fn with_auto_unlock(mut self, enabled: bool) -> Self {
+        self.auto_unlock = enabled;
+        self
+    }
+
+    pub fn with_held_underflow_policy(mut self, policy: HeldUnderflowPolicy) -> Self {
+        self.held_underflow_policy = policy;
+        self
+    }
+
+    /// Refuses rows that would open more than `max` accounts with
+    /// `ProcessingError::AccountLimitReached`. Clients that already have an
+    /// account are unaffected, and seeded accounts count towards the limit.
+    pub fn with_max_accounts(mut self, max: usize) -> Self {
+        self.max_accounts = Some(max);
+        self
+    }
+
+    pub fn with_interest_policy(mut self, policy: InterestPolicy) -> Self {
+        self.interest_policy = policy;
+        self
+    }
+
+    pub fn with_amount_scale_policy(mut self, policy: AmountScalePolicy) -> Self {
+        self.amount_scale_policy = policy;
+        self
+    }
+
+    /// Rejects resolves and chargebacks that carry an amount with
+    /// `ProcessingError::UnexpectedAmount`. Off by default, ignoring the
+    /// amount. A dispute's amount is the part of the tx it disputes either way.
+    pub fn with_strict_amounts(mut self, enabled: bool) -> Self {
+        self.strict_amounts = enabled;
+        self
+    }
+
+    /// Rejects withdrawals by clients that have an account but no successful
+    /// deposit yet, e.g. one opened by a transfer, with
+    /// `ProcessingError::NoPriorActivity` instead of processing them. Helps to
+    /// spot files with rows out of order. Off by default.
+    pub fn with_strict_withdrawals(mut self, enabled: bool) -> Self {
+        self.strict_withdrawals = enabled;
+        self
+    }
+
+    /// Ignores a deposit or withdrawal whose tx id is already stored for the
+    /// same client, type and amount, e.g. from a file sent twice, instead of
+    /// failing it as `ProcessingError::DuplicateTx`. A tx id reused with a
+    /// different amount still fails. Off by default.
+    pub fn with_idempotent(mut self, enabled: bool) -> Self {
+        self.idempotent = enabled;
+        self
+    }
+
+    /// Charges `fee.bps` basis points of every withdrawal on top of it, rounded
+    /// to the engine's precision, and credits the fee to the house client,
+    /// opening its account if needed. The client must have the funds for both.
+    /// Disputes and reversals of a withdrawal only return the withdrawn amount,
+    /// never the fee.
+    pub fn with_withdrawal_fee(mut self, fee: WithdrawalFee<C>) -> Self {
+        self.withdrawal_fee = Some(fee);
+        self
+    }
+
+    /// Rejects rows whose client, or transfer destination, is outside `range`
+    /// with `ProcessingError::IdOutOfRange` before they change anything.
+    pub fn with_client_id_range(mut self, range: RangeInclusive<C>) -> Self {
+        self.client_id_range = Some(range);
+        self
+    }
+
+    /// Rejects rows whose tx id is outside `range` with
+    /// `ProcessingError::IdOutOfRange` before they change anything.
+    pub fn with_tx_id_range(mut self, range: RangeInclusive<T>) -> Self {
+        self.tx_id_range = Some(range);
+        self
+    }
+
+    /// Scopes tx ids per client so that different clients may reuse the same
+    /// id. Disputes, resolves and chargebacks then match on (client, tx) pairs.
+    pub fn with_tx_id_scope(mut self, scope: TxIdScope) -> Self {
+        self.tx_id_scope = scope;
+        self
+    }
+
+    /// Only keeps deposits and withdrawals for the `rows` rows following them
+    /// unless they get disputed, bounding memory on large inputs. Disputing,
+    /// resolving or charging back an evicted tx fails with `TxNotFound`, and its
+    /// id is no longer recognised as a duplicate.
+    pub fn with_tx_retention(mut self, rows: u64) -> Self {
+        self.tx_retention = Some(rows);
+        self
+    }
+
+    /// Releases holds back to available funds once `rows` further rows have
+    /// been processed without them being captured. Holds last until captured
+    /// by default.
+    pub fn with_hold_expiry(mut self, rows: u64) -> Self {
+        self.hold_expiry = Some(rows);
+        self
+    }
+
+    /// Number of decimal places balances are reported with.
+    pub fn with_precision(mut self, precision: u32) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Records every balance transition in `Engine::audit_trail`. Off by
+    /// default as the trail grows with every successful row.
+    pub fn with_audit(mut self, enabled: bool) -> Self {
+        self.audit = enabled;
+        self
+    }
+
+    /// Keeps the state before each of the last `depth` successful rows so
+    /// `Engine::undo` can step back through them. Every kept state is a full
+    /// copy of the engine, so this suits interactive use rather than large
+    /// files.
+    pub fn with_undo(mut self, depth: usize) -> Self {
+        self.undo = Some(depth);
+        self
+    }
+
+    /// Writes every row the engine is handed, errored ones included, to
+    /// `writer` as CSV so that the run can be replayed by feeding the recording
+    /// to a fresh engine. Cloning the builder does not clone the recording.
+    pub fn record_to<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.recorder = Recorder::new(Box::new(writer));
+        self
+    }
+
+    /// Calls `hook` after every row with a known type, failed ones included,
+    /// e.g. to export metrics. Rows passed to `Engine::validate_row` are not
+    /// reported. Cloning the builder does not clone the hook.
+    pub fn with_row_hook(mut self, hook: RowHook<C, T>) -> Self {
+        self.row_hook = Hook(Some(hook));
+        self
+    }
+
+    /// Refuses rows `guard` returns false for with
+    /// `ProcessingError::RejectedByGuard` before they change anything, e.g. to
+    /// block sanctioned clients or amounts above a threshold. Unlike a row
+    /// hook, the guard is kept by clones of the engine, so
+    /// `Engine::validate_row` consults it too.
+    pub fn with_guard(mut self, guard: RowGuard<C, T>) -> Self {
+        self.guard = Shared(Some(Arc::new(guard)));
+        self
+    }
+
+    pub fn build(self) -> Engine<C, T> {
+        let mut engine = Engine {
+            dispute_policy: self.dispute_policy,
+            locked_policy: self.locked_policy,
+            auto_unlock: self.auto_unlock,
+            held_underflow_policy: self.held_underflow_policy,
+            max_accounts: self.max_accounts,
+            interest_policy: self.interest_policy,
+            amount_scale_policy: self.amount_scale_policy,
+            strict_amounts: self.strict_amounts,
+            strict_withdrawals: self.strict_withdrawals,
+            idempotent: self.idempotent,
+            withdrawal_fee: self.withdrawal_fee,
+            client_id_range: self.client_id_range,
+            tx_id_range: self.tx_id_range,
+            precision: self.precision,
+            rounding: self.rounding,
+            audit_trail: self.audit.then(Vec::new),
+            undo_log: self.undo.map(|depth| UndoLog {
+                depth,
+                states: VecDeque::new(),
+            }),
+            recorder: self.recorder,
+            row_hook: self.row_hook,
+            guard: self.guard,
+            ..Engine::default()
+        };
+        engine.tx_store.set_scope(self.tx_id_scope);
+        if let Some(rows) = self.tx_retention {
+            engine.tx_store.set_retention(rows);
+        }
+        if let Some(rows) = self.hold_expiry {
+            engine.hold_store.set_expiry(rows);
+        }
+        engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs, process,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        engine::{
+            DisputePolicy, Engine, EngineConfig, HeldUnderflowPolicy, InterestPolicy,
+            LockedAccountPolicy, ProcessingError, Rounding, WithdrawalFee, DEFAULT_PRECISION,
+        },
+        models::{TransactionType, TxRow},
+    };
+
+    use super::EngineBuilder;
+
+    fn row(tx_type: TransactionType, client_id: u16, tx_id: u32, amount: Option<&str>) -> TxRow {
+        TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount: amount.map(|x| x.parse().unwrap()),
+            dest: None,
+            timestamp: None,
+        }
+    }
+
+    fn chargeback_then_deposit(engine: &mut Engine) -> Result<(), ProcessingError> {
+        engine.process_row(&row(TransactionType::Deposit, 1, 1, Some("10")))?;
+        engine.process_row(&row(TransactionType::Dispute, 1, 1, None))?;
+        engine.process_row(&row(TransactionType::Chargeback, 1, 1, None))?;
+        engine.process_row(&row(TransactionType::Deposit, 1, 2, Some("5")))
+    }
+
+    #[test]
+    fn should_build_default_engine() {
+        let mut engine = EngineBuilder::new().build();
+        assert_eq!(engine.precision(), DEFAULT_PRECISION);
+        assert_eq!(
+            chargeback_then_deposit(&mut engine),
+            Err(ProcessingError::AccountLocked(1))
+        );
+    }
+
+    #[test]
+    fn should_configure_builder_from_file() {
+        let path = env::temp_dir().join(format!("payments_engine_config_{}.toml", process::id()));
+        fs::write(
+            &path,
+            "precision = 2\nrounding = \"truncate\"\ndispute-policy = \"reject\"\n\
+             locked-policy = \"allow-deposits\"\nauto-unlock = true\nmax-accounts = 50\n\
+             held-underflow = \"saturate\"\n\
+             tx-retention = 500\n\
+             [withdrawal-fee]\nbps = 10\nhouse-client = 7\n",
+        )
+        .unwrap();
+        let config = EngineConfig::load(&path).unwrap();
+        let builder = EngineBuilder::new()
+            .with_interest_policy(InterestPolicy::RequireAccount)
+            .config(&config);
+        assert_eq!(builder.precision, 2);
+        assert_eq!(builder.rounding, Rounding::Truncate);
+        assert_eq!(builder.dispute_policy, DisputePolicy::Reject);
+        assert_eq!(builder.locked_policy, LockedAccountPolicy::AllowDeposits);
+        assert!(builder.auto_unlock);
+        assert_eq!(builder.max_accounts, Some(50));
+        assert_eq!(builder.held_underflow_policy, HeldUnderflowPolicy::Saturate);
+        assert_eq!(builder.tx_retention, Some(500));
+        assert_eq!(
+            builder.withdrawal_fee,
+            Some(WithdrawalFee {
+                bps: 10,
+                house_client: 7
+            })
+        );
+        assert_eq!(builder.interest_policy, InterestPolicy::RequireAccount);
+        // settings left out keep the defaults
+        let builder =
+            EngineBuilder::new().config(&EngineConfig::from_toml("precision = 6").unwrap());
+        assert_eq!(builder.precision, 6);
+        assert_eq!(builder.rounding, Rounding::HalfEven);
+        assert_eq!(builder.tx_retention, None);
+        assert!(EngineConfig::from_toml("precission = 6").is_err());
+        assert!(EngineConfig::from_toml("[withdrawal-fee]\nbps = 10").is_err());
+        assert!(EngineConfig::from_toml("rounding = \"ceiling\"").is_err());
+    }
+
+    #[test]
+    fn should_apply_id_ranges_from_config() {
+        let config = EngineConfig::from_toml(
+            "client-id-range = { start = 1, end = 100 }\ntx-id-range = { start = 10, end = 20 }",
+        )
+        .unwrap();
+        let mut engine = EngineBuilder::new().config(&config).build();
+        engine
+            .process_row(&row(TransactionType::Deposit, 100, 10, Some("1")))
+            .unwrap();
+        assert_eq!(
+            engine.process_row(&row(TransactionType::Deposit, 101, 11, Some("1"))),
+            Err(ProcessingError::IdOutOfRange(101, 11))
+        );
+        assert_eq!(
+            engine.process_row(&row(TransactionType::Deposit, 1, 21, Some("1"))),
+            Err(ProcessingError::IdOutOfRange(1, 21))
+        );
+    }
+
+    #[test]
+    fn should_build_engine_for_other_id_types() {
+        const CLIENT: u64 = 5_000_000_000;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hook_calls = calls.clone();
+        let mut engine = EngineBuilder::<u64, u64>::default()
+            .with_withdrawal_fee(WithdrawalFee {
+                bps: 100,
+                house_client: 0,
+            })
+            .with_client_id_range(0..=CLIENT)
+            .with_tx_id_range(1..=u64::MAX)
+            .with_guard(Box::new(|row| row.tx_id != 3))
+            .with_row_hook(Box::new(move |_, _| {
+                hook_calls.fetch_add(1, Ordering::Relaxed);
+            }))
+            .build();
+        let row = |tx_type, client_id, tx_id, amount: &str| TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount: Some(amount.parse().unwrap()),
+            dest: None,
+            timestamp: None,
+        };
+        engine
+            .process_row(&row(TransactionType::Deposit, CLIENT, 1, "10"))
+            .unwrap();
+        engine
+            .process_row(&row(TransactionType::Withdrawal, CLIENT, 2, "5"))
+            .unwrap();
+        assert_eq!(
+            engine.process_row(&row(TransactionType::Deposit, CLIENT, 3, "1")),
+            Err(ProcessingError::RejectedByGuard(3))
+        );
+        assert_eq!(
+            engine.process_row(&row(TransactionType::Deposit, CLIENT + 1, 4, "1")),
+            Err(ProcessingError::IdOutOfRange(CLIENT + 1, 4))
+        );
+        assert_eq!(engine.get_account(CLIENT).unwrap().available, dec!(4.95));
+        assert_eq!(engine.get_account(0).unwrap().available, dec!(0.05));
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+}
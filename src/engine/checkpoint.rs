@@ -0,0 +1,218 @@
+use std::{
+    collections::HashSet,
+    error, fmt,
+    io::{self, Read, Write},
+};
+
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Engine;
+use crate::{
+    models::{ClientId, ClientStats, EngineEvent, EngineSummary, TxId},
+    stores::{AccountStore, HoldStore, LockedAccountStore, TransactionStore},
+};
+
+// the state written by Engine::checkpoint, borrowed from the engine
+#[derive(Serialize)]
+#[serde(bound = "C: Serialize, T: Serialize")]
+struct CheckpointRef<'a, C, T> {
+    rows_processed: u64,
+    account_store: &'a AccountStore<C>,
+    tx_store: &'a TransactionStore<C, T>,
+    locked_accounts_store: &'a LockedAccountStore<C>,
+    hold_store: &'a HoldStore<C, T>,
+    summary: &'a EngineSummary,
+    client_limits: Vec<(&'a C, &'a Decimal)>,
+    ever_negative: &'a HashSet<C>,
+    first_seen: &'a [C],
+    latest_opened: Option<C>,
+    events: &'a [EngineEvent<C>],
+    client_stats: Vec<(&'a C, &'a ClientStats)>,
+    written_off: Decimal,
+    seeded: Decimal,
+}
+
+// the state read by Engine::restore; fields match CheckpointRef
+#[derive(Deserialize)]
+#[serde(bound = "C: ClientId + DeserializeOwned, T: TxId + DeserializeOwned")]
+struct Checkpoint<C, T> {
+    rows_processed: u64,
+    account_store: AccountStore<C>,
+    tx_store: TransactionStore<C, T>,
+    locked_accounts_store: LockedAccountStore<C>,
+    hold_store: HoldStore<C, T>,
+    summary: EngineSummary,
+    client_limits: Vec<(C, Decimal)>,
+    ever_negative: HashSet<C>,
+    first_seen: Vec<C>,
+    #[serde(default)]
+    latest_opened: Option<C>,
+    events: Vec<EngineEvent<C>>,
+    client_stats: Vec<(C, ClientStats)>,
+    written_off: Decimal,
+    seeded: Decimal,
+}
+
+impl<C, T> Engine<C, T>
+where
+    C: ClientId + Serialize + DeserializeOwned,
+    T: TxId + Serialize + DeserializeOwned,
+{
+    /// Writes the accounts, stored transactions, holds and totals as JSON to
+    /// `w`, returning the number of rows processed so far. Restoring the
+    /// checkpoint and processing the rows after that many gives the same
+    /// state as processing every row, so a recording (see
+    /// `EngineBuilder::record_to`) can be truncated up to the marker. The
+    /// audit trail is not included.
+    pub fn checkpoint<W: Write>(&self, w: W) -> Result<u64, CheckpointError> {
+        let checkpoint = CheckpointRef {
+            rows_processed: self.rows_processed,
+            account_store: &self.account_store,
+            tx_store: &self.tx_store,
+            locked_accounts_store: &self.locked_accounts_store,
+            hold_store: &self.hold_store,
+            summary: &self.summary,
+            client_limits: self.client_limits.iter().collect(),
+            ever_negative: &self.ever_negative,
+            first_seen: &self.first_seen,
+            latest_opened: self.latest_opened,
+            events: &self.events,
+            client_stats: self.client_stats.iter().collect(),
+            written_off: self.written_off,
+            seeded: self.seeded,
+        };
+        serde_json::to_writer(w, &checkpoint)?;
+        Ok(self.rows_processed)
+    }
+
+    /// Replaces the state of this engine with a checkpoint read from `r`,
+    /// returning its marker. The tx id scope, tx retention and hold expiry
+    /// are taken from the checkpoint; the policies, recording and hook of
+    /// this engine are kept. Nothing changes if the checkpoint is invalid.
+    pub fn restore<R: Read>(&mut self, r: R) -> Result<u64, CheckpointError> {
+        let checkpoint: Checkpoint<C, T> = serde_json::from_reader(r)?;
+        self.rows_processed = checkpoint.rows_processed;
+        self.account_store = checkpoint.account_store;
+        self.tx_store = checkpoint.tx_store;
+        self.locked_accounts_store = checkpoint.locked_accounts_store;
+        self.hold_store = checkpoint.hold_store;
+        self.summary = checkpoint.summary;
+        self.client_limits = checkpoint.client_limits.into_iter().collect();
+        self.ever_negative = checkpoint.ever_negative;
+        self.first_seen = checkpoint.first_seen;
+        self.latest_opened = checkpoint.latest_opened;
+        self.events = checkpoint.events;
+        self.client_stats = checkpoint.client_stats.into_iter().collect();
+        self.written_off = checkpoint.written_off;
+        self.seeded = checkpoint.seeded;
+        Ok(self.rows_processed)
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    // the checkpoint could not be encoded or is malformed
+    Json(serde_json::Error),
+}
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            CheckpointError::Io(err.into())
+        } else {
+            CheckpointError::Json(err)
+        }
+    }
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "could not access checkpoint: {}", err),
+            CheckpointError::Json(err) => write!(f, "invalid checkpoint: {}", err),
+        }
+    }
+}
+
+impl error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(err) => Some(err),
+            CheckpointError::Json(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        engine::{CheckpointError, Engine, EngineBuilder},
+        models::{TransactionType, TxRow},
+    };
+
+    fn row(tx_type: TransactionType, client_id: u16, tx_id: u32, amount: Option<&str>) -> TxRow {
+        TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount: amount.map(|x| x.parse().unwrap()),
+            dest: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn should_resume_from_checkpoint_and_tail() {
+        let head = [
+            row(TransactionType::Deposit, 1, 1, Some("10")),
+            row(TransactionType::Deposit, 2, 2, Some("7.5")),
+            row(TransactionType::Dispute, 1, 1, None),
+            row(TransactionType::Hold, 2, 3, Some("2")),
+            row(TransactionType::Withdrawal, 2, 4, Some("100")),
+        ];
+        let tail = [
+            row(TransactionType::Chargeback, 1, 1, None),
+            row(TransactionType::Capture, 2, 3, None),
+            row(TransactionType::Deposit, 3, 5, Some("1")),
+            row(TransactionType::Dispute, 2, 2, None),
+        ];
+        let mut engine = EngineBuilder::new().with_hold_expiry(10).build();
+        for row in &head {
+            let _ = engine.process_row(row);
+        }
+        let mut checkpoint = Vec::new();
+        assert_eq!(engine.checkpoint(&mut checkpoint).unwrap(), 5);
+        for row in &tail {
+            engine.process_row(row).unwrap();
+        }
+
+        let mut restored = Engine::new();
+        assert_eq!(restored.restore(checkpoint.as_slice()).unwrap(), 5);
+        for row in &tail {
+            restored.process_row(row).unwrap();
+        }
+        assert_eq!(restored.get_account_iter().count(), 3);
+        for (id, _) in engine.get_account_iter() {
+            assert_eq!(restored.get_account(*id), engine.get_account(*id));
+        }
+        assert_eq!(restored.export_transactions(), engine.export_transactions());
+        assert_eq!(restored.summary(), engine.summary());
+        assert_eq!(restored.get_account(1).unwrap().total, dec!(0));
+        assert!(restored.verify_invariants().is_ok());
+    }
+
+    #[test]
+    fn should_leave_engine_unchanged_given_invalid_checkpoint() {
+        let mut engine = Engine::new();
+        engine
+            .process_row(&row(TransactionType::Deposit, 1, 1, Some("10")))
+            .unwrap();
+        let result = engine.restore(&b"{\"rows_processed\": 3}"[..]);
+        assert!(matches!(result, Err(CheckpointError::Json(_))));
+        assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+    }
+}
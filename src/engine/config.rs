@@ -0,0 +1,76 @@
+use std::{error, fmt, fs, io, ops::RangeInclusive, path::Path};
+
+use serde::Deserialize;
+
+use super::{DisputePolicy, HeldUnderflowPolicy, LockedAccountPolicy, Rounding, WithdrawalFee};
+
+/// Engine settings as read from a TOML file, e.g.
+///
+/// ```toml
+/// precision = 2
+/// rounding = "half-up"
+/// dispute-policy = "reject"
+/// locked-policy = "allow-deposits"
+/// auto-unlock = true
+/// max-accounts = 100000
+/// held-underflow = "saturate"
+/// tx-retention = 100000
+///
+/// client-id-range = { start = 1, end = 5000 }
+/// tx-id-range = { start = 1, end = 4294967295 }
+///
+/// [withdrawal-fee]
+/// bps = 25
+/// house-client = 0
+/// ```
+///
+/// Settings left out keep their `EngineBuilder` defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EngineConfig {
+    pub precision: Option<u32>,
+    pub rounding: Option<Rounding>,
+    pub dispute_policy: Option<DisputePolicy>,
+    pub locked_policy: Option<LockedAccountPolicy>,
+    pub auto_unlock: Option<bool>,
+    pub max_accounts: Option<usize>,
+    pub held_underflow: Option<HeldUnderflowPolicy>,
+    pub tx_retention: Option<u64>,
+    pub withdrawal_fee: Option<WithdrawalFee>,
+    pub client_id_range: Option<RangeInclusive<u16>>,
+    pub tx_id_range: Option<RangeInclusive<u32>>,
+}
+
+impl EngineConfig {
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(ConfigError::Toml)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path).map_err(ConfigError::Io)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "could not read config: {}", err),
+            ConfigError::Toml(err) => write!(f, "invalid config: {}", err),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Toml(err) => Some(err),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::{fmt, sync::Arc};
+
+/// Holds an optional callback. Like `Recorder`, clones come without the
+/// callback, so scratch copies of an engine (see `Engine::validate_row`) do
+/// not report rows they only pretend to process.
+pub(crate) struct Hook<F>(pub(crate) Option<F>);
+
+impl<F> Default for Hook<F> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<F> Clone for Hook<F> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<F> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hook")
+            .field("set", &self.0.is_some())
+            .finish()
+    }
+}
+
+/// Holds an optional callback shared between clones, unlike `Hook`, so that
+/// scratch copies of an engine are subject to the same checks.
+pub(crate) struct Shared<F: ?Sized>(pub(crate) Option<Arc<F>>);
+
+impl<F: ?Sized> Default for Shared<F> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<F: ?Sized> Clone for Shared<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Shared<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared")
+            .field("set", &self.0.is_some())
+            .finish()
+    }
+}
@@ -1,132 +1,1009 @@
-use std::{collections::hash_map::Iter, fmt};
+use std::{
+    collections::{btree_map::Iter, HashMap, HashSet, VecDeque},
+    error, fmt, mem,
+    ops::RangeInclusive,
+};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
-use rust_decimal::Decimal;
+pub use builder::EngineBuilder;
+pub use checkpoint::CheckpointError;
+pub use config::{ConfigError, EngineConfig};
 
+use self::{
+    hook::{Hook, Shared},
+    recorder::Recorder,
+};
 use crate::{
-    models::{AccountData, TransactionType, TxRow},
-    stores::{AccountStore, DataError, LockedAccountStore, TransactionStore},
+    models::{
+        AccountData, AccountDiff, AccountSnapshot, AuditEntry, BalanceError, ClientId, ClientStats,
+        EngineEvent, EngineSummary, RawTxRow, RowError, Transaction, TransactionSnapshot,
+        TransactionType, TxId, TxRow, TxState,
+    },
+    stores::{AccountStore, DataError, HoldStore, LockedAccountStore, TransactionStore},
 };
 
-#[derive(Debug)]
-pub struct Engine {
-    account_store: AccountStore,
-    tx_store: TransactionStore,
-    locked_accounts_store: LockedAccountStore,
+mod builder;
+mod checkpoint;
+mod config;
+mod hook;
+mod recorder;
+#[cfg(feature = "async")]
+mod stream;
+
+/// Decides what happens when disputing a transaction would leave the client's
+/// available funds negative (e.g. the disputed deposit was already withdrawn).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputePolicy {
+    /// Hold the full amount and let available go negative. Resolving restores
+    /// the balance from before the dispute, charging back leaves the client
+    /// owing the withdrawn funds.
+    #[default]
+    AllowNegative,
+    /// Refuse the dispute with `ProcessingError::DisputeWouldOverdraw`.
+    Reject,
 }
 
-impl Engine {
-    pub fn new() -> Self {
+/// Decides what happens when a resolve or chargeback would release more than
+/// the client holds, e.g. after `seed_account` lowered the held funds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeldUnderflowPolicy {
+    /// Refuse the row with `ProcessingError::HeldUnderflow`.
+    #[default]
+    Error,
+    /// Settle only what is held, leaving held funds at zero.
+    Saturate,
+}
+
+/// Decides which rows are still accepted for a locked account.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccountPolicy {
+    /// Reject every row with `ProcessingError::AccountLocked`.
+    #[default]
+    RejectAll,
+    /// Accept deposits (e.g. to cover a chargeback) but reject everything else.
+    AllowDeposits,
+}
+
+/// Decides what happens when interest is credited to a client without an
+/// account.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InterestPolicy {
+    /// Open the account at zero and credit the interest.
+    #[default]
+    CreateAccount,
+    /// Refuse the interest with `ProcessingError::AccountNotFound`.
+    RequireAccount,
+}
+
+/// Decides what happens to amounts with more decimal places than balances are
+/// meant to carry, so that stored and reported balances do not drift apart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AmountScalePolicy {
+    /// Use amounts at whatever scale they are given.
+    #[default]
+    Keep,
+    /// Refuse amounts with more decimal places than given with
+    /// `ProcessingError::AmountTooPrecise`.
+    Reject(u32),
+    /// Round amounts to the given number of decimal places.
+    Round(u32),
+}
+
+/// How balances are rounded to the engine's precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// Round halves away from zero.
+    HalfUp,
+    /// Round halves to the nearest even digit, a.k.a. banker's rounding.
+    #[default]
+    HalfEven,
+    /// Drop excess digits.
+    Truncate,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// A fee of `bps` basis points charged on top of every withdrawal and credited
+/// to `house_client`, see `EngineBuilder::with_withdrawal_fee`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WithdrawalFee<C = u16> {
+    pub bps: u32,
+    pub house_client: C,
+}
+
+pub const DEFAULT_PRECISION: u32 = 4;
+
+/// A row paired with the result of processing it.
+pub type RowOutcome<C = u16, T = u32> = (TxRow<C, T>, Result<(), ProcessingError<C, T>>);
+
+/// Called with every processed row and its result, see `EngineBuilder::with_row_hook`.
+pub type RowHook<C = u16, T = u32> =
+    Box<dyn FnMut(&TxRow<C, T>, &Result<(), ProcessingError<C, T>>) + Send>;
+
+/// Decides whether a row may be processed, see `EngineBuilder::with_guard`.
+pub type RowGuard<C = u16, T = u32> = Box<dyn Fn(&TxRow<C, T>) -> bool + Send + Sync>;
+
+/// Processes transaction rows into account balances. Client and tx ids default
+/// to `u16` and `u32` but any `ClientId` and `TxId` types may be used instead.
+#[derive(Clone, Debug)]
+pub struct Engine<C = u16, T = u32> {
+    account_store: AccountStore<C>,
+    tx_store: TransactionStore<C, T>,
+    locked_accounts_store: LockedAccountStore<C>,
+    hold_store: HoldStore<C, T>,
+    summary: EngineSummary,
+    dispute_policy: DisputePolicy,
+    locked_policy: LockedAccountPolicy,
+    // whether deposits accepted for a locked account may lift the lock
+    auto_unlock: bool,
+    held_underflow_policy: HeldUnderflowPolicy,
+    // most accounts rows may open; None is unlimited
+    max_accounts: Option<usize>,
+    interest_policy: InterestPolicy,
+    amount_scale_policy: AmountScalePolicy,
+    // whether rows referring to another tx may carry an amount
+    strict_amounts: bool,
+    // whether withdrawals must follow a deposit by the same client
+    strict_withdrawals: bool,
+    // whether exact duplicates of stored deposits and withdrawals are ignored
+    idempotent: bool,
+    withdrawal_fee: Option<WithdrawalFee<C>>,
+    // ids the source system may send; None accepts any
+    client_id_range: Option<RangeInclusive<C>>,
+    tx_id_range: Option<RangeInclusive<T>>,
+    // decimal places balances are reported with
+    precision: u32,
+    rounding: Rounding,
+    // maps client_id to the most it may hold under dispute
+    client_limits: HashMap<C, Decimal>,
+    // clients whose available funds went below zero at some point
+    ever_negative: HashSet<C>,
+    // clients in the order their accounts were opened
+    first_seen: Vec<C>,
+    // client whose account the tx inserted last opened, if any, so that
+    // cancelling the tx closes the account again
+    latest_opened: Option<C>,
+    // noteworthy account transitions, oldest first
+    events: Vec<EngineEvent<C>>,
+    // per-client row counts; balances and locks are filled in on request
+    client_stats: HashMap<C, ClientStats>,
+    // balances wiped by reset_account, net of the pending returns it cleared
+    written_off: Decimal,
+    // balances set by seed_account rather than processed rows
+    seeded: Decimal,
+    // rows handed to process_row or process_raw_row, failed ones included
+    rows_processed: u64,
+    // balance transitions of every processed row; None unless auditing
+    audit_trail: Option<Vec<AuditEntry<C, T>>>,
+    // the engine before each of the latest successful rows; None unless undo
+    // is enabled
+    undo_log: Option<UndoLog<C, T>>,
+    recorder: Recorder,
+    row_hook: Hook<RowHook<C, T>>,
+    guard: Shared<RowGuard<C, T>>,
+}
+
+impl<C: ClientId, T: TxId> Default for Engine<C, T> {
+    fn default() -> Self {
         Self {
             account_store: AccountStore::new(),
             tx_store: TransactionStore::new(),
             locked_accounts_store: LockedAccountStore::new(),
+            hold_store: HoldStore::new(),
+            summary: EngineSummary::default(),
+            dispute_policy: DisputePolicy::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            auto_unlock: false,
+            held_underflow_policy: HeldUnderflowPolicy::default(),
+            max_accounts: None,
+            interest_policy: InterestPolicy::default(),
+            amount_scale_policy: AmountScalePolicy::default(),
+            strict_amounts: false,
+            strict_withdrawals: false,
+            idempotent: false,
+            withdrawal_fee: None,
+            client_id_range: None,
+            tx_id_range: None,
+            precision: DEFAULT_PRECISION,
+            rounding: Rounding::default(),
+            client_limits: HashMap::new(),
+            ever_negative: HashSet::new(),
+            first_seen: Vec::new(),
+            latest_opened: None,
+            events: Vec::new(),
+            client_stats: HashMap::new(),
+            written_off: Decimal::ZERO,
+            seeded: Decimal::ZERO,
+            rows_processed: 0,
+            audit_trail: None,
+            undo_log: None,
+            recorder: Recorder::default(),
+            row_hook: Hook::default(),
+            guard: Shared::default(),
         }
     }
+}
 
-    pub fn process_row(&mut self, row: &TxRow) -> Result<(), ProcessingError> {
-        if self.locked_accounts_store.is_account_locked(&row.client_id) {
-            return Err(ProcessingError::AccountLocked(row.client_id));
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: ClientId, T: TxId> Engine<C, T> {
+    pub fn process_row(&mut self, row: &TxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        self.rows_processed += 1;
+        self.recorder.record(
+            &row.tx_type.to_string(),
+            row.client_id,
+            row.tx_id,
+            row.amount.map(|x| x.to_string()).as_deref(),
+            row.dest,
+            row.timestamp,
+        );
+        self.dispatch_row(row)
+    }
+
+    fn dispatch_row(&mut self, row: &TxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        let before = self.undo_log.is_some().then(|| self.undo_state());
+        let result = self.audit_row(row);
+        if let (Ok(()), Some(before), Some(log)) = (&result, before, self.undo_log.as_mut()) {
+            log.push(before);
+        }
+        if let Some(hook) = self.row_hook.0.as_mut() {
+            hook(row, &result);
+        }
+        result
+    }
+
+    fn audit_row(&mut self, row: &TxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        if self.audit_trail.is_none() {
+            return self.apply_row(row);
         }
-        match (row.tx_type, row.amount) {
-            (TransactionType::Deposit, None) => Err(ProcessingError::AmountNotSpecified(row.tx_id)),
-            (TransactionType::Withdrawal, None) => {
-                Err(ProcessingError::AmountNotSpecified(row.tx_id))
+        // a transfer also moves the destination's balance
+        let dest = row
+            .dest
+            .filter(|dest| row.tx_type == TransactionType::Transfer && *dest != row.client_id);
+        let before: Vec<(C, AccountData)> = [Some(row.client_id), dest]
+            .into_iter()
+            .flatten()
+            .map(|id| (id, self.account_store.find_by_id(&id).unwrap_or_default()))
+            .collect();
+        self.apply_row(row)?;
+        for (client_id, before) in before {
+            let after = self
+                .account_store
+                .find_by_id(&client_id)
+                .unwrap_or_default();
+            if let Some(trail) = self.audit_trail.as_mut() {
+                trail.push(AuditEntry {
+                    tx_id: row.tx_id,
+                    client_id,
+                    kind: row.tx_type,
+                    available_before: before.available(),
+                    available_after: after.available(),
+                    held_before: before.held(),
+                    held_after: after.held(),
+                });
             }
+        }
+        Ok(())
+    }
+
+    // a copy of the engine without the undo log itself
+    fn undo_state(&mut self) -> Self {
+        let log = self.undo_log.take();
+        let state = self.clone();
+        self.undo_log = log;
+        state
+    }
+
+    /// Reverts the most recent successful row still in the undo log, see
+    /// `EngineBuilder::with_undo`, e.g. undoing a deposit frees its tx id and
+    /// undoing a chargeback unlocks the account. Rows stay counted as processed
+    /// and in the recording, if any.
+    pub fn undo(&mut self) -> Result<(), UndoError> {
+        let log = self.undo_log.as_mut().ok_or(UndoError::NotEnabled)?;
+        let state = log.states.pop_back().ok_or(UndoError::NothingToUndo)?;
+        *self = Self {
+            rows_processed: self.rows_processed,
+            undo_log: self.undo_log.take(),
+            recorder: mem::take(&mut self.recorder),
+            row_hook: mem::take(&mut self.row_hook),
+            ..state
+        };
+        Ok(())
+    }
+
+    /// Like `process_row`, but also returns the affected client's resulting
+    /// balance. For a transfer that is the source client.
+    pub fn process_row_with_state(
+        &mut self,
+        row: &TxRow<C, T>,
+    ) -> Result<AccountSnapshot<C>, ProcessingError<C, T>> {
+        self.process_row(row)?;
+        self.get_account(row.client_id)
+            .ok_or(ProcessingError::AccountNotFound(row.client_id))
+    }
+
+    fn apply_row(&mut self, row: &TxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        if self.guard.0.as_ref().is_some_and(|guard| !guard(row)) {
+            return Err(ProcessingError::RejectedByGuard(row.tx_id));
+        }
+        if !self.ids_in_range(row) {
+            return Err(ProcessingError::IdOutOfRange(row.client_id, row.tx_id));
+        }
+        self.tx_store.tick();
+        self.expire_holds();
+        let allowed_while_locked = self.locked_policy == LockedAccountPolicy::AllowDeposits
+            && row.tx_type == TransactionType::Deposit;
+        if !allowed_while_locked && self.locked_accounts_store.is_account_locked(&row.client_id) {
+            return Err(ProcessingError::AccountLocked(row.client_id));
+        }
+        let amount = self.normalize_amount(row.tx_id, row.amount)?;
+        match (row.tx_type, amount) {
+            (
+                TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Interest
+                | TransactionType::Transfer
+                | TransactionType::Hold,
+                None,
+            ) => Err(ProcessingError::AmountNotSpecified {
+                tx_id: row.tx_id,
+                tx_type: row.tx_type,
+            }),
             (TransactionType::Deposit, Some(amount)) => {
                 self.process_deposit(row.tx_id, row.client_id, amount)
             }
             (TransactionType::Withdrawal, Some(amount)) => {
                 self.process_withdrawal(row.tx_id, row.client_id, amount)
             }
+            (TransactionType::Resolve | TransactionType::Chargeback, Some(_))
+                if self.strict_amounts =>
+            {
+                Err(ProcessingError::UnexpectedAmount(row.tx_id))
+            }
             (TransactionType::Resolve, _) => self.process_resolve(row.tx_id, row.client_id),
-            (TransactionType::Dispute, _) => self.process_dispute(row.tx_id, row.client_id),
+            (TransactionType::Dispute, amount) => {
+                self.process_dispute(row.tx_id, row.client_id, amount)
+            }
             (TransactionType::Chargeback, _) => self.process_chargeback(row.tx_id, row.client_id),
+            (TransactionType::Reversal, _) => self.process_reversal(row.tx_id, row.client_id),
+            (TransactionType::Cancel, _) => self.process_cancel(row.tx_id, row.client_id),
+            (TransactionType::Interest, Some(amount)) => {
+                self.process_interest(row.tx_id, row.client_id, amount)
+            }
+            (TransactionType::Transfer, Some(amount)) => match row.dest {
+                None => Err(ProcessingError::DestinationNotSpecified(row.tx_id)),
+                Some(dest) => self.process_transfer(row.tx_id, row.client_id, dest, amount),
+            },
+            (TransactionType::Hold, Some(amount)) => {
+                self.process_hold(row.tx_id, row.client_id, amount)
+            }
+            (TransactionType::Capture, _) => self.process_capture(row.tx_id, row.client_id),
         }
     }
 
-    fn process_deposit(
+    /// Processes a row whose type has not been checked yet, failing it with
+    /// `ProcessingError::UnknownTxType` if the type is not recognised.
+    pub fn process_raw_row(&mut self, row: &RawTxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        self.record_raw_row(row);
+        self.try_raw_row(row)
+    }
+
+    /// Counts `row` as processed and records it, if recording, without
+    /// processing it. See `try_raw_row`.
+    pub fn record_raw_row(&mut self, row: &RawTxRow<C, T>) {
+        self.rows_processed += 1;
+        self.recorder.record(
+            &row.tx_type,
+            row.client_id,
+            row.tx_id,
+            row.amount.as_deref(),
+            row.dest,
+            row.timestamp,
+        );
+    }
+
+    /// Like `process_raw_row`, but neither counts nor records the row, for rows
+    /// that may be tried again later, e.g. ones deferred until their target tx
+    /// shows up. Hand the row to `record_raw_row` once its outcome is final, so
+    /// that it is recorded only once and replays in the order it took effect.
+    pub fn try_raw_row(&mut self, row: &RawTxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        match row.to_row() {
+            Ok(row) => self.dispatch_row(&row),
+            Err(RowError::UnknownType) => Err(ProcessingError::UnknownTxType(
+                row.tx_id,
+                row.tx_type.clone(),
+            )),
+            Err(RowError::UnparseableAmount) => Err(ProcessingError::AmountUnparseable(row.tx_id)),
+        }
+    }
+
+    /// Processes `rows` with a default engine, returning it along with the
+    /// errors of the rows that failed, in row order.
+    pub fn from_rows(
+        rows: impl IntoIterator<Item = TxRow<C, T>>,
+    ) -> (Self, Vec<ProcessingError<C, T>>) {
+        let mut engine = Self::default();
+        let errors = rows
+            .into_iter()
+            .filter_map(|row| engine.process_row(&row).err())
+            .collect();
+        (engine, errors)
+    }
+
+    /// Processes every row in order, pairing each with its outcome. A failing
+    /// row does not stop the rows after it.
+    pub fn process_batch(&mut self, rows: &[TxRow<C, T>]) -> Vec<RowOutcome<C, T>> {
+        rows.iter()
+            .map(|row| (*row, self.process_row(row)))
+            .collect()
+    }
+
+    fn normalize_amount(
+        &self,
+        tx_id: T,
+        amount: Option<Decimal>,
+    ) -> Result<Option<Decimal>, ProcessingError<C, T>> {
+        match (self.amount_scale_policy, amount) {
+            // trailing zeros do not count towards the scale
+            (AmountScalePolicy::Reject(scale), Some(amount))
+                if amount.normalize().scale() > scale =>
+            {
+                Err(ProcessingError::AmountTooPrecise(tx_id))
+            }
+            (AmountScalePolicy::Round(scale), Some(amount)) => Ok(Some(
+                amount.round_dp_with_strategy(scale, self.rounding.strategy()),
+            )),
+            _ => Ok(amount),
+        }
+    }
+
+    /// Caps how much `client_id` may hold under dispute. Disputes that would
+    /// push held funds above `max_held` are rejected.
+    pub fn set_client_limit(&mut self, client_id: C, max_held: Decimal) {
+        self.client_limits.insert(client_id, max_held);
+    }
+
+    fn exceeds_held_limit(&self, client_id: C, held: Decimal) -> bool {
+        self.client_limits
+            .get(&client_id)
+            .is_some_and(|max_held| held > *max_held)
+    }
+
+    // stores the tx, returning false if it is an exact duplicate to be ignored
+    fn insert_tx(
         &mut self,
-        tx_id: u32,
-        client_id: u16,
+        client_id: C,
+        tx_id: T,
+        tx_type: TransactionType,
         amount: Decimal,
-    ) -> Result<(), ProcessingError> {
+    ) -> Result<bool, ProcessingError<C, T>> {
+        if !self.check_tx_id(client_id, tx_id, tx_type, amount)? {
+            return Ok(false);
+        }
         self.tx_store
-            .insert_tx(tx_id, amount)
+            .insert_tx(client_id, tx_id, tx_type, amount)
             .map_err(|e| match e {
                 DataError::AlreadyExists => ProcessingError::DuplicateTx(tx_id),
-            })
-            .and_then(|_| {
-                let mut account =
-                    self.account_store
-                        .find_by_id(&client_id)
-                        .unwrap_or(AccountData {
-                            available: Decimal::ZERO,
-                            held: Decimal::ZERO,
-                        });
-                account.available += amount;
-                self.account_store
-                    .add_or_update_account(&client_id, &account);
-                Ok(())
+                DataError::AlreadyDisputed => ProcessingError::DuplicateDisputedTx(tx_id),
+            })?;
+        self.latest_opened = None;
+        Ok(true)
+    }
+
+    // whether the tx id is free, without storing anything; false if the row is
+    // an exact duplicate to be ignored
+    fn check_tx_id(
+        &self,
+        client_id: C,
+        tx_id: T,
+        tx_type: TransactionType,
+        amount: Decimal,
+    ) -> Result<bool, ProcessingError<C, T>> {
+        match self.tx_store.find_by_id(client_id, tx_id) {
+            None => Ok(true),
+            Some(_)
+                if self.idempotent
+                    && self.is_stored_duplicate(client_id, tx_id, tx_type, amount) =>
+            {
+                log::info!("ignored duplicate of tx {}", tx_id);
+                Ok(false)
+            }
+            Some(tx) if tx.state == TxState::Disputed => {
+                Err(ProcessingError::DuplicateDisputedTx(tx_id))
+            }
+            Some(_) => Err(ProcessingError::DuplicateTx(tx_id)),
+        }
+    }
+
+    fn is_stored_duplicate(
+        &self,
+        client_id: C,
+        tx_id: T,
+        tx_type: TransactionType,
+        amount: Decimal,
+    ) -> bool {
+        matches!(
+            tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && self
+            .tx_store
+            .find_by_id(client_id, tx_id)
+            .is_some_and(|tx| {
+                tx.client_id == client_id && tx.tx_type == tx_type && tx.amount == amount
             })
     }
 
-    fn process_withdrawal(
+    // fails if opening an account for `client_id` would exceed max_accounts
+    fn check_account_limit(&self, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        match self.max_accounts {
+            Some(max)
+                if self.first_seen.len() >= max
+                    && self.account_store.find_by_id(&client_id).is_none() =>
+            {
+                Err(ProcessingError::AccountLimitReached(client_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn save_account(&mut self, client_id: C, data: &AccountData) {
+        if self.account_store.find_by_id(&client_id).is_none() {
+            self.first_seen.push(client_id);
+            self.latest_opened = Some(client_id);
+            self.summary.new_accounts += 1;
+            log::info!("opened account {}", client_id);
+        }
+        if data.available() < Decimal::ZERO {
+            self.ever_negative.insert(client_id);
+        }
+        self.account_store.add_or_update_account(&client_id, data);
+    }
+
+    /// Runs every check `process_row` would against a scratch copy of the engine,
+    /// leaving this engine untouched. The copy takes every account and stored
+    /// tx along, the undo log and audit trail included, so each call costs time
+    /// in proportion to the engine's state and checking a whole input row by
+    /// row this way is quadratic. Rows validated this way do not see each other
+    /// either: to validate a sequence, clone the engine once and process the
+    /// rows through the clone, the way `--dry-run` feeds the input through an
+    /// engine whose balances it never reports.
+    pub fn validate_row(&self, row: &TxRow<C, T>) -> Result<(), ProcessingError<C, T>> {
+        self.clone().process_row(row)
+    }
+
+    fn process_deposit(
         &mut self,
-        tx_id: u32,
-        client_id: u16,
+        tx_id: T,
+        client_id: C,
         amount: Decimal,
-    ) -> Result<(), ProcessingError> {
+    ) -> Result<(), ProcessingError<C, T>> {
+        self.check_account_limit(client_id)?;
+        let mut account = self
+            .account_store
+            .find_by_id(&client_id)
+            .unwrap_or_default();
+        let was_negative = account.available() < Decimal::ZERO;
+        account
+            .deposit(amount)
+            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+        let total_deposits = self
+            .summary
+            .total_deposits
+            .checked_add(amount)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        if !self.insert_tx(client_id, tx_id, TransactionType::Deposit, amount)? {
+            return Ok(());
+        }
+        if was_negative && account.available() >= Decimal::ZERO {
+            log::warn!("account {} recovered from negative funds", client_id);
+            self.events
+                .push(EngineEvent::AccountRecoveredFromNegative(client_id));
+        }
+        self.save_account(client_id, &account);
+        self.summary.total_deposits = total_deposits;
+        self.client_stats.entry(client_id).or_default().deposits += 1;
+        if self.auto_unlock
+            && self.locked_accounts_store.is_account_locked(&client_id)
+            && account.held().is_zero()
+            && account.available() >= Decimal::ZERO
+            && !self.has_disputes(client_id)
+        {
+            log::info!("unlocking account {} after recovery deposit", client_id);
+            self.locked_accounts_store.unlock_account(&client_id);
+            self.events.push(EngineEvent::AccountUnlocked(client_id));
+        }
+        Ok(())
+    }
+
+    fn has_disputes(&self, client_id: C) -> bool {
         self.tx_store
-            .insert_tx(tx_id, amount)
-            .map_err(|e| match e {
-                DataError::AlreadyExists => ProcessingError::DuplicateTx(tx_id),
-            })
-            .and_then(|_| match self.account_store.find_by_id(&client_id) {
-                None => Err(ProcessingError::AccountNotFound(client_id)),
-                Some(mut account) => {
-                    if account.available < amount {
-                        Err(ProcessingError::InsufficientFunds(client_id))
-                    } else {
-                        account.available -= amount;
+            .find_all()
+            .any(|tx| tx.client_id == client_id && tx.state == TxState::Disputed)
+    }
+
+    fn process_withdrawal(
+        &mut self,
+        tx_id: T,
+        client_id: C,
+        amount: Decimal,
+    ) -> Result<(), ProcessingError<C, T>> {
+        if !self.check_tx_id(client_id, tx_id, TransactionType::Withdrawal, amount)? {
+            return Ok(());
+        }
+        let mut account = self
+            .account_store
+            .find_by_id(&client_id)
+            .ok_or(ProcessingError::AccountNotFound(client_id))?;
+        if self.strict_withdrawals
+            && self
+                .client_stats
+                .get(&client_id)
+                .is_none_or(|stats| stats.deposits == 0)
+        {
+            return Err(ProcessingError::NoPriorActivity(client_id));
+        }
+        let fee = self.withdrawal_fee_for(tx_id, amount)?;
+        let debit = amount
+            .checked_add(fee)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        account.withdraw(debit).map_err(|e| {
+            balance_error(
+                e,
+                tx_id,
+                ProcessingError::InsufficientFunds {
+                    client_id,
+                    requested: debit,
+                    available: account.available(),
+                },
+            )
+        })?;
+        let total_withdrawals = self
+            .summary
+            .total_withdrawals
+            .checked_add(amount)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        let total_fees = self
+            .summary
+            .total_fees
+            .checked_add(fee)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        let house = match self.withdrawal_fee {
+            Some(WithdrawalFee { house_client, .. }) if !fee.is_zero() => {
+                // a house client withdrawing pays the fee to itself
+                let mut house = match house_client == client_id {
+                    true => account,
+                    false => {
+                        self.check_account_limit(house_client)?;
                         self.account_store
-                            .add_or_update_account(&client_id, &account);
-                        Ok(())
+                            .find_by_id(&house_client)
+                            .unwrap_or_default()
                     }
+                };
+                house
+                    .deposit(fee)
+                    .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+                Some((house_client, house))
+            }
+            _ => None,
+        };
+        // only withdrawals that went through are stored, so the tx log
+        // matches the balances
+        self.insert_tx(client_id, tx_id, TransactionType::Withdrawal, amount)?;
+        self.save_account(client_id, &account);
+        if let Some((house_client, house)) = house {
+            self.save_account(house_client, &house);
+        }
+        self.summary.total_withdrawals = total_withdrawals;
+        self.summary.total_fees = total_fees;
+        self.client_stats.entry(client_id).or_default().withdrawals += 1;
+        Ok(())
+    }
+
+    // the fee charged on top of withdrawing `amount`, if any
+    fn withdrawal_fee_for(
+        &self,
+        tx_id: T,
+        amount: Decimal,
+    ) -> Result<Decimal, ProcessingError<C, T>> {
+        match self.withdrawal_fee {
+            None => Ok(Decimal::ZERO),
+            Some(fee) => amount
+                .checked_mul(Decimal::from(fee.bps))
+                .and_then(|fee| fee.checked_div(Decimal::from(10_000)))
+                .map(|fee| self.round(fee))
+                .ok_or(ProcessingError::AmountOverflow(tx_id)),
+        }
+    }
+
+    /// Credits interest to available funds. Interest is tallied apart from
+    /// deposits and cannot be disputed.
+    fn process_interest(
+        &mut self,
+        tx_id: T,
+        client_id: C,
+        amount: Decimal,
+    ) -> Result<(), ProcessingError<C, T>> {
+        let mut account = match self.account_store.find_by_id(&client_id) {
+            Some(account) => account,
+            None if self.interest_policy == InterestPolicy::CreateAccount => {
+                self.check_account_limit(client_id)?;
+                AccountData::default()
+            }
+            None => return Err(ProcessingError::AccountNotFound(client_id)),
+        };
+        account
+            .deposit(amount)
+            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+        let total_interest = self
+            .summary
+            .total_interest
+            .checked_add(amount)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        self.insert_tx(client_id, tx_id, TransactionType::Interest, amount)?;
+        self.save_account(client_id, &account);
+        self.summary.total_interest = total_interest;
+        self.summary.interest_credits += 1;
+        Ok(())
+    }
+
+    /// Moves `amount` from `client_id`'s available funds to `dest`'s, opening
+    /// the destination account if needed. Only the source is checked for a
+    /// lock or sufficient funds. Transfers cannot be disputed or reversed.
+    fn process_transfer(
+        &mut self,
+        tx_id: T,
+        client_id: C,
+        dest: C,
+        amount: Decimal,
+    ) -> Result<(), ProcessingError<C, T>> {
+        let mut source = self
+            .account_store
+            .find_by_id(&client_id)
+            .ok_or(ProcessingError::AccountNotFound(client_id))?;
+        source.withdraw(amount).map_err(|e| {
+            balance_error(
+                e,
+                tx_id,
+                ProcessingError::InsufficientFunds {
+                    client_id,
+                    requested: amount,
+                    available: source.available(),
+                },
+            )
+        })?;
+        // a transfer to oneself credits the debited balance
+        let mut target = match dest == client_id {
+            true => source,
+            false => {
+                self.check_account_limit(dest)?;
+                self.account_store.find_by_id(&dest).unwrap_or_default()
+            }
+        };
+        target
+            .deposit(amount)
+            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+        self.insert_tx(client_id, tx_id, TransactionType::Transfer, amount)?;
+        self.save_account(client_id, &source);
+        self.save_account(dest, &target);
+        self.summary.transfers += 1;
+        Ok(())
+    }
+
+    /// Moves `amount` from available to held funds until a capture with the
+    /// same tx id takes it out of the account, or the hold expires and the
+    /// funds become available again. Holds cannot be disputed or reversed.
+    fn process_hold(
+        &mut self,
+        tx_id: T,
+        client_id: C,
+        amount: Decimal,
+    ) -> Result<(), ProcessingError<C, T>> {
+        let mut account = self
+            .account_store
+            .find_by_id(&client_id)
+            .ok_or(ProcessingError::AccountNotFound(client_id))?;
+        if account.available() < amount {
+            return Err(ProcessingError::InsufficientFunds {
+                client_id,
+                requested: amount,
+                available: account.available(),
+            });
+        }
+        account
+            .apply_hold(amount)
+            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+        self.insert_tx(client_id, tx_id, TransactionType::Hold, amount)?;
+        self.save_account(client_id, &account);
+        self.hold_store.place(client_id, tx_id, amount);
+        self.summary.holds += 1;
+        Ok(())
+    }
+
+    /// Takes the funds of a pending hold out of the account, total shrinks.
+    fn process_capture(&mut self, tx_id: T, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        let amount = match self.hold_store.find_by_id(client_id, tx_id) {
+            Some(amount) => amount,
+            None => {
+                return match self.tx_store.find_by_id(client_id, tx_id) {
+                    None => Err(ProcessingError::TxNotFound(tx_id)),
+                    Some(_) => Err(ProcessingError::HoldNotPending(tx_id)),
                 }
-            })
+            }
+        };
+        let mut account = self
+            .account_store
+            .find_by_id(&client_id)
+            .ok_or(ProcessingError::AccountNotFound(client_id))?;
+        account.remove_held(amount).map_err(|e| {
+            balance_error(e, tx_id, ProcessingError::HeldUnderflow(client_id, tx_id))
+        })?;
+        self.summary.total_captured = self
+            .summary
+            .total_captured
+            .checked_add(amount)
+            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+        self.save_account(client_id, &account);
+        self.hold_store.capture(client_id, tx_id);
+        self.tx_store.capture_transaction(client_id, tx_id, amount);
+        self.summary.captures += 1;
+        Ok(())
     }
 
-    fn process_dispute(&mut self, tx_id: u32, client_id: u16) -> Result<(), ProcessingError> {
-        match self.tx_store.find_by_id(&tx_id) {
+    // releases the holds that have gone uncaptured for too long
+    fn expire_holds(&mut self) {
+        for (client_id, tx_id, amount) in self.hold_store.tick() {
+            let mut account = self
+                .account_store
+                .find_by_id(&client_id)
+                .unwrap_or_default();
+            if let Err(err) = account.release_hold(amount) {
+                log::error!(
+                    "{}",
+                    balance_error(
+                        err,
+                        tx_id,
+                        ProcessingError::<C, T>::HeldUnderflow(client_id, tx_id)
+                    )
+                );
+                continue;
+            }
+            self.save_account(client_id, &account);
+            self.summary.expired_holds += 1;
+            log::info!("released expired hold tx_id {}", tx_id);
+        }
+    }
+
+    /// Holds `amount` of the tx, or all of it if no amount is given, until a
+    /// resolve or chargeback settles the held part.
+    fn process_dispute(
+        &mut self,
+        tx_id: T,
+        client_id: C,
+        amount: Option<Decimal>,
+    ) -> Result<(), ProcessingError<C, T>> {
+        match self.tx_store.find_by_id(client_id, tx_id) {
             None => Err(ProcessingError::TxNotFound(tx_id)),
-            Some(tx) => match tx.disputed {
-                true => Err(ProcessingError::TxAlreadyDisputed(tx_id)),
-                false => match self.account_store.find_by_id(&client_id) {
-                    None => Err(ProcessingError::AccountNotFound(client_id)),
-                    Some(mut data) => {
-                        data.held += tx.amount;
-                        data.available -= tx.amount;
-                        self.account_store.add_or_update_account(&client_id, &data);
-                        self.tx_store.dispute_transaction(tx_id);
-                        Ok(())
+            Some(tx) if tx.client_id != client_id => Err(ProcessingError::ClientMismatch {
+                tx_id,
+                client_id,
+                owner: tx.client_id,
+            }),
+            Some(tx) => match tx.state {
+                TxState::Disputed => Err(ProcessingError::TxAlreadyDisputed(tx_id)),
+                TxState::ChargedBack => Err(ProcessingError::TxAlreadyChargedBack(tx_id)),
+                TxState::Reversed => Err(ProcessingError::TxAlreadyReversed(tx_id)),
+                TxState::Captured => Err(ProcessingError::TxNotDisputable(tx_id)),
+                TxState::Normal | TxState::Resolved
+                    if matches!(
+                        tx.tx_type,
+                        TransactionType::Interest
+                            | TransactionType::Transfer
+                            | TransactionType::Hold
+                    ) =>
+                {
+                    Err(ProcessingError::TxNotDisputable(tx_id))
+                }
+                TxState::Normal | TxState::Resolved => {
+                    let disputed = amount.unwrap_or(tx.amount);
+                    if disputed <= Decimal::ZERO || disputed > tx.amount {
+                        return Err(ProcessingError::InvalidDisputeAmount(tx_id));
                     }
-                },
+                    match self.account_store.find_by_id(&client_id) {
+                        None => Err(ProcessingError::OwnerAccountNotFound(client_id, tx_id)),
+                        Some(data)
+                            if self.dispute_policy == DisputePolicy::Reject
+                                && tx.tx_type == TransactionType::Deposit
+                                && data.available() < disputed =>
+                        {
+                            Err(ProcessingError::DisputeWouldOverdraw(client_id, tx_id))
+                        }
+                        Some(data)
+                            if self.exceeds_held_limit(
+                                client_id,
+                                data.held().saturating_add(disputed),
+                            ) =>
+                        {
+                            Err(ProcessingError::HeldLimitExceeded(client_id))
+                        }
+                        Some(mut data) => {
+                            // deposit: available -> held, total unchanged
+                            // withdrawal: the withdrawn amount is held as a pending
+                            // return, so held and total grow, available unchanged
+                            match tx.tx_type {
+                                TransactionType::Withdrawal => data.add_held(disputed),
+                                _ => data.apply_hold(disputed),
+                            }
+                            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+                            self.save_account(client_id, &data);
+                            self.tx_store
+                                .dispute_transaction(client_id, tx_id, disputed);
+                            self.summary.disputes += 1;
+                            self.client_stats.entry(client_id).or_default().disputes += 1;
+                            Ok(())
+                        }
+                    }
+                }
             },
         }
     }
 
-    fn process_resolve(&mut self, tx_id: u32, client_id: u16) -> Result<(), ProcessingError> {
-        match self.tx_store.find_by_id(&tx_id) {
+    fn process_resolve(&mut self, tx_id: T, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        match self.tx_store.find_by_id(client_id, tx_id) {
             None => Err(ProcessingError::TxNotFound(tx_id)),
-            Some(tx) => match tx.disputed {
-                false => Err(ProcessingError::TxNotDisputed(tx_id)),
-                true => match self.account_store.find_by_id(&client_id) {
-                    None => Err(ProcessingError::AccountNotFound(client_id)),
+            Some(tx) if tx.client_id != client_id => Err(ProcessingError::ClientMismatch {
+                tx_id,
+                client_id,
+                owner: tx.client_id,
+            }),
+            Some(tx) => match tx.state {
+                // a chargeback ends the dispute, so there is nothing left to
+                // release
+                TxState::Normal
+                | TxState::Resolved
+                | TxState::Reversed
+                | TxState::ChargedBack
+                | TxState::Captured => Err(ProcessingError::TxNotDisputed(tx_id)),
+                TxState::Disputed => match self.account_store.find_by_id(&client_id) {
+                    None => Err(ProcessingError::OwnerAccountNotFound(client_id, tx_id)),
                     Some(mut data) => {
-                        data.held -= tx.amount;
-                        data.available += tx.amount;
-                        self.account_store.add_or_update_account(&client_id, &data);
-                        self.tx_store.resolve_transaction(&tx_id);
+                        let amount = self.held_to_settle(&data, tx_id, tx.disputed_amount);
+                        // deposit: held -> available, total unchanged
+                        // withdrawal: the pending return is dropped, so held and
+                        // total shrink back, available unchanged
+                        match tx.tx_type {
+                            TransactionType::Withdrawal => data.remove_held(amount),
+                            _ => data.release_hold(amount),
+                        }
+                        .map_err(|e| {
+                            balance_error(
+                                e,
+                                tx_id,
+                                ProcessingError::HeldUnderflow(client_id, tx_id),
+                            )
+                        })?;
+                        self.save_account(client_id, &data);
+                        self.tx_store.resolve_transaction(client_id, tx_id);
+                        self.summary.resolves += 1;
                         Ok(())
                     }
                 },
@@ -134,17 +1011,65 @@ impl Engine {
         }
     }
 
-    fn process_chargeback(&mut self, tx_id: u32, client_id: u16) -> Result<(), ProcessingError> {
-        match self.tx_store.find_by_id(&tx_id) {
+    // the disputed amount a resolve or chargeback takes out of held funds
+    fn held_to_settle(&self, data: &AccountData, tx_id: T, disputed: Decimal) -> Decimal {
+        match self.held_underflow_policy {
+            HeldUnderflowPolicy::Saturate if data.held() < disputed => {
+                log::warn!(
+                    "settling {} of tx {} instead of {}, all that is held",
+                    data.held(),
+                    tx_id,
+                    disputed
+                );
+                data.held().max(Decimal::ZERO)
+            }
+            _ => disputed,
+        }
+    }
+
+    fn process_chargeback(&mut self, tx_id: T, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        match self.tx_store.find_by_id(client_id, tx_id) {
             None => Err(ProcessingError::TxNotFound(tx_id)),
-            Some(tx) => match tx.disputed {
-                false => Err(ProcessingError::TxNotDisputed(tx_id)),
-                true => match self.account_store.find_by_id(&client_id) {
-                    None => Err(ProcessingError::AccountNotFound(client_id)),
+            Some(tx) if tx.client_id != client_id => Err(ProcessingError::ClientMismatch {
+                tx_id,
+                client_id,
+                owner: tx.client_id,
+            }),
+            Some(tx) => match tx.state {
+                TxState::Normal | TxState::Resolved | TxState::Reversed | TxState::Captured => {
+                    Err(ProcessingError::TxNotDisputed(tx_id))
+                }
+                TxState::ChargedBack => Err(ProcessingError::TxAlreadyChargedBack(tx_id)),
+                TxState::Disputed => match self.account_store.find_by_id(&client_id) {
+                    None => Err(ProcessingError::OwnerAccountNotFound(client_id, tx_id)),
                     Some(mut data) => {
-                        data.held -= tx.amount;
-                        self.account_store.add_or_update_account(&client_id, &data);
+                        let amount = self.held_to_settle(&data, tx_id, tx.disputed_amount);
+                        // deposit: held funds are removed, total shrinks
+                        // withdrawal: the pending return moves from held to
+                        // available, restoring the withdrawn funds
+                        match tx.tx_type {
+                            TransactionType::Withdrawal => data.release_hold(amount),
+                            _ => data.remove_held(amount),
+                        }
+                        .map_err(|e| {
+                            balance_error(
+                                e,
+                                tx_id,
+                                ProcessingError::HeldUnderflow(client_id, tx_id),
+                            )
+                        })?;
+                        let total = match tx.tx_type {
+                            TransactionType::Withdrawal => &mut self.summary.total_returned,
+                            _ => &mut self.summary.total_charged_back,
+                        };
+                        *total = total
+                            .checked_add(amount)
+                            .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+                        self.save_account(client_id, &data);
+                        self.tx_store
+                            .chargeback_transaction(client_id, tx_id, amount);
                         self.locked_accounts_store.lock_account(client_id);
+                        self.summary.chargebacks += 1;
                         Ok(())
                     }
                 },
@@ -152,196 +1077,1030 @@ impl Engine {
         }
     }
 
-    pub fn get_account_iter(&self) -> Iter<u16, AccountData> {
-        self.account_store.find_all()
+    /// Undoes an undisputed deposit or withdrawal, e.g. to correct a mistake.
+    /// Reversed transactions count as never having happened, so they are
+    /// netted out of the summary's deposit and withdrawal totals.
+    fn process_reversal(&mut self, tx_id: T, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        match self.tx_store.find_by_id(client_id, tx_id) {
+            None => Err(ProcessingError::ReversalTargetNotFound(tx_id)),
+            Some(tx) if tx.client_id != client_id => Err(ProcessingError::ClientMismatch {
+                tx_id,
+                client_id,
+                owner: tx.client_id,
+            }),
+            Some(tx) => match tx.state {
+                TxState::Disputed => Err(ProcessingError::TxAlreadyDisputed(tx_id)),
+                TxState::ChargedBack => Err(ProcessingError::TxAlreadyChargedBack(tx_id)),
+                TxState::Reversed => Err(ProcessingError::TxAlreadyReversed(tx_id)),
+                TxState::Captured => Err(ProcessingError::TxNotReversible(tx_id)),
+                TxState::Normal | TxState::Resolved
+                    if matches!(
+                        tx.tx_type,
+                        TransactionType::Transfer | TransactionType::Hold
+                    ) =>
+                {
+                    Err(ProcessingError::TxNotReversible(tx_id))
+                }
+                TxState::Normal | TxState::Resolved => {
+                    self.undo_tx(client_id, tx_id, &tx)?;
+                    self.tx_store.reverse_transaction(client_id, tx_id);
+                    self.summary.reversals += 1;
+                    Ok(())
+                }
+            },
+        }
     }
 
-    pub fn is_account_locked(&self, id: u16) -> bool {
-        self.locked_accounts_store.is_account_locked(&id)
+    fn ids_in_range(&self, row: &TxRow<C, T>) -> bool {
+        let client_in_range = |client_id| {
+            self.client_id_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&client_id))
+        };
+        client_in_range(row.client_id)
+            && row.dest.is_none_or(client_in_range)
+            && self
+                .tx_id_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&row.tx_id))
     }
-}
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum ProcessingError {
-    // Unknown,
-    AccountNotFound(u16),
-    AccountLocked(u16),
-    InsufficientFunds(u16),
-    DuplicateTx(u32),
-    TxAlreadyDisputed(u32),
-    TxNotFound(u32),
-    TxNotDisputed(u32),
-    AmountNotSpecified(u32),
-}
-
-impl fmt::Display for ProcessingError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+    /// Removes the tx the previous rows inserted, as if it had never been
+    /// read, along with the account it opened, if any. Only an undisputed
+    /// deposit, withdrawal or interest credit can be cancelled; any fee the
+    /// withdrawal was charged is kept.
+    fn process_cancel(&mut self, tx_id: T, client_id: C) -> Result<(), ProcessingError<C, T>> {
+        let tx = self
+            .tx_store
+            .find_by_id(client_id, tx_id)
+            .ok_or(ProcessingError::TxNotFound(tx_id))?;
+        match tx.state {
+            _ if tx.client_id != client_id => {
+                return Err(ProcessingError::ClientMismatch {
+                    tx_id,
+                    client_id,
+                    owner: tx.client_id,
+                })
+            }
+            TxState::Disputed => return Err(ProcessingError::TxAlreadyDisputed(tx_id)),
+            TxState::Normal
+                if self.tx_store.is_latest(client_id, tx_id)
+                    && !matches!(
+                        tx.tx_type,
+                        TransactionType::Transfer | TransactionType::Hold
+                    ) => {}
+            _ => return Err(ProcessingError::TxNotCancellable(tx_id)),
+        }
+        self.undo_tx(client_id, tx_id, &tx)?;
+        self.tx_store.remove_latest();
+        let stats = self.client_stats.entry(client_id).or_default();
+        match tx.tx_type {
+            TransactionType::Deposit => stats.deposits = stats.deposits.saturating_sub(1),
+            TransactionType::Withdrawal => stats.withdrawals = stats.withdrawals.saturating_sub(1),
+            _ => self.summary.interest_credits = self.summary.interest_credits.saturating_sub(1),
+        }
+        if self.latest_opened.take() == Some(client_id) {
+            // the account only existed because of the cancelled tx
+            self.account_store.remove_account(&client_id);
+            self.first_seen.retain(|id| *id != client_id);
+            self.client_stats.remove(&client_id);
+            self.summary.new_accounts -= 1;
+            log::info!("closed account {}", client_id);
+        }
+        self.summary.cancellations += 1;
+        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::Engine;
-
-    mod deposits {
 
-        use rust_decimal_macros::dec;
+    // Takes an undisputed deposit, withdrawal or interest credit back out of
+    // the client's balance and the totals, changing nothing on failure.
+    fn undo_tx(
+        &mut self,
+        client_id: C,
+        tx_id: T,
+        tx: &Transaction<C>,
+    ) -> Result<(), ProcessingError<C, T>> {
+        match self.account_store.find_by_id(&client_id) {
+            None => Err(ProcessingError::OwnerAccountNotFound(client_id, tx_id)),
+            Some(mut data) => {
+                let total = match tx.tx_type {
+                    TransactionType::Withdrawal => {
+                        data.deposit(tx.amount)
+                            .map_err(|_| ProcessingError::AmountOverflow(tx_id))?;
+                        &mut self.summary.total_withdrawals
+                    }
+                    TransactionType::Interest => {
+                        data.withdraw(tx.amount).map_err(|e| {
+                            balance_error(
+                                e,
+                                tx_id,
+                                ProcessingError::InsufficientFunds {
+                                    client_id,
+                                    requested: tx.amount,
+                                    available: data.available(),
+                                },
+                            )
+                        })?;
+                        &mut self.summary.total_interest
+                    }
+                    _ => {
+                        data.withdraw(tx.amount).map_err(|e| {
+                            balance_error(
+                                e,
+                                tx_id,
+                                ProcessingError::InsufficientFunds {
+                                    client_id,
+                                    requested: tx.amount,
+                                    available: data.available(),
+                                },
+                            )
+                        })?;
+                        &mut self.summary.total_deposits
+                    }
+                };
+                *total = total
+                    .checked_sub(tx.amount)
+                    .ok_or(ProcessingError::AmountOverflow(tx_id))?;
+                self.save_account(client_id, &data);
+                Ok(())
+            }
+        }
+    }
 
-        use crate::{
-            engine::ProcessingError,
-            models::{TransactionType, TxRow},
-        };
+    /// Iterates over the accounts in ascending client id order.
+    pub fn get_account_iter(&self) -> Iter<'_, C, AccountData> {
+        self.account_store.find_all()
+    }
 
-        use super::Engine;
+    /// Iterates over the accounts in the order they were opened, e.g. by a
+    /// client's first deposit or as the destination of a transfer.
+    pub fn get_account_iter_first_seen(&self) -> impl Iterator<Item = (C, AccountData)> + '_ {
+        self.first_seen
+            .iter()
+            .filter_map(|id| self.account_store.find_by_id(id).map(|data| (*id, data)))
+    }
 
-        #[test]
-        fn should_process_deposit() {
-            let row = TxRow {
-                tx_type: TransactionType::Deposit,
-                tx_id: 1,
-                client_id: 2,
-                amount: Some(dec!(123.45)),
-            };
-            let mut engine = Engine::new();
-            engine.process_row(&row).unwrap();
-            if let Some((acc_id, acc)) = engine.get_account_iter().next() {
-                assert_eq!(*acc_id, 2u16);
-                assert_eq!(acc.available, dec!(123.45));
-            } else {
-                panic!("account not found");
-            }
+    /// Wipes `client_id`'s balance, unlocks the account and ends any disputes
+    /// on its transactions, e.g. for test fixtures or admin corrections. The
+    /// client's transactions are kept, so their ids still count as duplicates
+    /// and they can be disputed again.
+    pub fn reset_account(&mut self, client_id: C) {
+        if let Some(data) = self.account_store.find_by_id(&client_id) {
+            let pending_returns: Decimal = self
+                .tx_store
+                .find_all()
+                .filter(|tx| {
+                    tx.client_id == client_id
+                        && tx.tx_type == TransactionType::Withdrawal
+                        && tx.state == TxState::Disputed
+                })
+                .map(|tx| tx.disputed_amount)
+                .sum();
+            self.written_off += data.total() - pending_returns;
+            self.account_store
+                .add_or_update_account(&client_id, &AccountData::default());
         }
+        self.locked_accounts_store.unlock_account(&client_id);
+        self.tx_store.clear_disputes(client_id);
+        self.hold_store.clear_client(client_id);
+    }
 
-        #[test]
-        fn should_not_process_deposit_without_amount() {
-            let row = TxRow {
-                tx_type: TransactionType::Deposit,
-                tx_id: 1,
-                client_id: 2,
-                amount: None,
-            };
-            let mut engine = Engine::new();
-            let err = engine.process_row(&row).unwrap_err();
-            assert_eq!(err, ProcessingError::AmountNotSpecified(1u32));
+    /// Opens `client_id`'s account with the given balance and lock, e.g. the
+    /// closing balances of a previous run, replacing any balance it already
+    /// has. Seeded held funds do not belong to any dispute, so they stay held
+    /// until the account is reset. Meant for clients without transactions yet.
+    /// Fails without changing anything if the total, or the sum of all seeded
+    /// balances, does not fit a `Decimal`.
+    pub fn seed_account(
+        &mut self,
+        client_id: C,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+    ) -> Result<(), BalanceError> {
+        let data = AccountData::new(available, held)?;
+        let old = self.account_store.find_by_id(&client_id);
+        self.seeded = data
+            .total()
+            .checked_sub(old.map_or(Decimal::ZERO, |old| old.total()))
+            .and_then(|change| self.seeded.checked_add(change))
+            .ok_or(BalanceError::Overflow)?;
+        if old.is_none() {
+            self.first_seen.push(client_id);
         }
-
-        #[test]
-        fn should_not_process_duplicate_deposit() {
-            let row = TxRow {
-                tx_type: TransactionType::Deposit,
-                tx_id: 1,
-                client_id: 2,
-                amount: Some(dec!(123.45)),
-            };
-            let row2 = row.clone();
-            let mut engine = Engine::new();
-            engine.process_row(&row).unwrap();
-            let err = engine.process_row(&row2).unwrap_err();
-            assert_eq!(err, ProcessingError::DuplicateTx(1u32));
+        if available < Decimal::ZERO {
+            self.ever_negative.insert(client_id);
+        }
+        self.account_store.add_or_update_account(&client_id, &data);
+        match locked {
+            true => self.locked_accounts_store.lock_account(client_id),
+            false => self.locked_accounts_store.unlock_account(&client_id),
         }
+        Ok(())
     }
 
-    mod withdrawals {
+    pub fn get_account(&self, id: C) -> Option<AccountSnapshot<C>> {
+        self.account_store
+            .find_by_id(&id)
+            .map(|data| AccountSnapshot {
+                client_id: id,
+                available: data.available(),
+                held: data.held(),
+                total: data.total(),
+                locked: self.is_account_locked(id),
+            })
+    }
 
-        use rust_decimal_macros::dec;
+    /// The most funds `client_id` has had held at once, e.g. under dispute,
+    /// even if they have been released since.
+    pub fn peak_held(&self, client_id: C) -> Option<Decimal> {
+        self.account_store
+            .find_by_id(&client_id)
+            .map(|data| data.peak_held())
+    }
 
-        use crate::{
-            engine::ProcessingError,
-            models::{TransactionType, TxRow},
+    pub fn is_account_locked(&self, id: C) -> bool {
+        self.locked_accounts_store.is_account_locked(&id)
+    }
+
+    /// Clients whose accounts are currently locked, in ascending order.
+    pub fn locked_accounts(&self) -> Vec<C> {
+        let mut clients: Vec<C> = self.locked_accounts_store.locked_iter().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// A SHA-256 hex digest of every account's balances and lock state, taken
+    /// in client order, so engines in the same state have the same fingerprint
+    /// however their rows were ordered, e.g. to compare two runs in CI.
+    pub fn state_fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (client_id, data) in self.account_store.find_all() {
+            hasher.update(format!(
+                "{},{},{},{}\n",
+                client_id,
+                data.available().normalize(),
+                data.held().normalize(),
+                self.is_account_locked(*client_id)
+            ));
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Whether the transaction is currently under dispute, or `None` if it is
+    /// unknown, e.g. never processed or evicted after the retention window.
+    pub fn is_tx_disputed(&self, tx_id: T) -> Option<bool> {
+        self.tx_store.is_disputed(tx_id)
+    }
+
+    /// Ids of the transactions currently under dispute, in no particular order.
+    pub fn disputed_transactions(&self) -> impl Iterator<Item = T> + '_ {
+        self.tx_store.disputed_iter()
+    }
+
+    /// Every stored transaction with its amount and dispute state, ordered by
+    /// tx id and then client, e.g. to inspect disputes. Transactions evicted
+    /// after the retention window are not included.
+    pub fn export_transactions(&self) -> Vec<TransactionSnapshot<C, T>> {
+        let mut transactions: Vec<TransactionSnapshot<C, T>> = self
+            .tx_store
+            .iter()
+            .map(|(tx_id, tx)| TransactionSnapshot {
+                tx_id,
+                client_id: tx.client_id,
+                tx_type: tx.tx_type,
+                amount: tx.amount,
+                state: tx.state,
+            })
+            .collect();
+        transactions.sort_unstable_by_key(|tx| (tx.tx_id, tx.client_id));
+        transactions
+    }
+
+    /// Clients whose available funds went negative at any point, e.g. after a
+    /// dispute on already withdrawn funds, even if they have since recovered.
+    pub fn accounts_ever_negative(&self) -> Vec<C> {
+        let mut clients: Vec<C> = self.ever_negative.iter().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// Noteworthy account transitions in the order they happened.
+    pub fn events(&self) -> &[EngineEvent<C>] {
+        &self.events
+    }
+
+    /// Successful deposits, withdrawals and disputes per client, alongside
+    /// each client's current balance and lock.
+    pub fn client_stats(&self) -> HashMap<C, ClientStats> {
+        self.account_store
+            .find_all()
+            .map(|(id, data)| {
+                let stats = ClientStats {
+                    locked: self.locked_accounts_store.is_account_locked(id),
+                    available: data.available(),
+                    held: data.held(),
+                    ..self.client_stats.get(id).copied().unwrap_or_default()
+                };
+                (*id, stats)
+            })
+            .collect()
+    }
+
+    /// Balance transitions in the order they happened, or nothing unless
+    /// auditing was enabled with `EngineBuilder::with_audit`.
+    pub fn audit_trail(&self) -> &[AuditEntry<C, T>] {
+        self.audit_trail.as_deref().unwrap_or_default()
+    }
+
+    /// Number of transactions currently kept for disputes and duplicate checks.
+    pub fn stored_tx_count(&self) -> usize {
+        self.tx_store.len()
+    }
+
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    /// Rounds `value` to the engine's precision for reporting.
+    pub fn round(&self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(self.precision, self.rounding.strategy())
+    }
+
+    pub fn summary(&self) -> EngineSummary {
+        EngineSummary {
+            locked_accounts: self.locked_accounts_store.count(),
+            ..self.summary
+        }
+    }
+
+    /// The accounts whose balances, locks or existence differ from those of
+    /// `other`, in ascending client order, e.g. to check that processing
+    /// shards in parallel and merging them agrees with a sequential run.
+    pub fn diff(&self, other: &Engine<C, T>) -> Vec<AccountDiff<C>> {
+        let mut clients: Vec<C> = self
+            .account_store
+            .find_all()
+            .chain(other.account_store.find_all())
+            .map(|(id, _)| *id)
+            .collect();
+        clients.sort_unstable();
+        clients.dedup();
+        clients
+            .into_iter()
+            .filter_map(|client_id| {
+                let ours = self.account_store.find_by_id(&client_id);
+                let theirs = other.account_store.find_by_id(&client_id);
+                let (ours_data, theirs_data) =
+                    (ours.unwrap_or_default(), theirs.unwrap_or_default());
+                let diff = AccountDiff {
+                    client_id,
+                    exists: differing(ours.is_some(), theirs.is_some()),
+                    available: differing(ours_data.available(), theirs_data.available()),
+                    held: differing(ours_data.held(), theirs_data.held()),
+                    locked: differing(
+                        self.is_account_locked(client_id),
+                        other.is_account_locked(client_id),
+                    ),
+                };
+                (diff.exists.is_some()
+                    || diff.available.is_some()
+                    || diff.held.is_some()
+                    || diff.locked.is_some())
+                .then_some(diff)
+            })
+            .collect()
+    }
+
+    /// Takes over the clients and transactions of `other`, e.g. an engine that
+    /// processed another region's shard. Fails without changing anything if
+    /// both engines know a client or, as scoped by this engine, a tx id, or
+    /// if the seeded or written off balances together do not fit a `Decimal`.
+    /// The policies, recording and hook of this engine are kept.
+    pub fn merge(&mut self, other: Engine<C, T>) -> Result<(), MergeError<C, T>> {
+        if let Some((id, _)) = other
+            .account_store
+            .find_all()
+            .find(|(id, _)| self.account_store.find_by_id(id).is_some())
+        {
+            return Err(MergeError::ClientOverlap(*id));
+        }
+        let (Some(written_off), Some(seeded)) = (
+            self.written_off.checked_add(other.written_off),
+            self.seeded.checked_add(other.seeded),
+        ) else {
+            return Err(MergeError::Overflow);
+        };
+        self.tx_store
+            .merge(other.tx_store)
+            .map_err(MergeError::TxOverlap)?;
+        self.account_store.merge(other.account_store);
+        self.locked_accounts_store
+            .merge(other.locked_accounts_store);
+        self.hold_store.merge(other.hold_store);
+        self.summary.merge(&other.summary);
+        self.client_limits.extend(other.client_limits);
+        self.ever_negative.extend(other.ever_negative);
+        self.first_seen.extend(other.first_seen);
+        self.events.extend(other.events);
+        self.client_stats.extend(other.client_stats);
+        self.written_off = written_off;
+        self.seeded = seeded;
+        self.rows_processed += other.rows_processed;
+        if let (Some(trail), Some(other_trail)) = (self.audit_trail.as_mut(), other.audit_trail) {
+            trail.extend(other_trail);
+        }
+        Ok(())
+    }
+
+    /// Recomputes the expected sum of all balances from the transaction log,
+    /// each tx counted as its current state leaves it, plus the seeded and
+    /// minus the written off balances, and compares it against the accounts.
+    /// The summary totals play no part, so a row that updates them without
+    /// touching a balance, or the other way round, is caught. Either sum not
+    /// fitting a `Decimal` is reported rather than compared.
+    pub fn verify_invariants(&self) -> Result<(), InvariantViolation<C>> {
+        let mut actual = Decimal::ZERO;
+        for (id, data) in self.account_store.find_all() {
+            if data.held() < Decimal::ZERO {
+                return Err(InvariantViolation::NegativeHeld(*id));
+            }
+            actual = actual
+                .checked_add(data.total())
+                .ok_or(InvariantViolation::Overflow)?;
+        }
+        let expected = self
+            .tx_store
+            .balance_effect()
+            .and_then(|effect| effect.checked_sub(self.written_off))
+            .and_then(|effect| effect.checked_add(self.seeded))
+            .ok_or(InvariantViolation::Overflow)?;
+        match expected == actual {
+            true => Ok(()),
+            false => Err(InvariantViolation::TotalMismatch { expected, actual }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvariantViolation<C = u16> {
+    NegativeHeld(C),
+    TotalMismatch {
+        expected: Decimal,
+        actual: Decimal,
+    },
+    /// A sum of balances does not fit a `Decimal`.
+    Overflow,
+}
+
+#[derive(Clone, Debug)]
+struct UndoLog<C, T> {
+    depth: usize,
+    // oldest first
+    states: VecDeque<Engine<C, T>>,
+}
+
+impl<C, T> UndoLog<C, T> {
+    fn push(&mut self, state: Engine<C, T>) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.states.len() == self.depth {
+            self.states.pop_front();
+        }
+        self.states.push_back(state);
+    }
+}
+
+/// Why `Engine::undo` could not step back.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UndoError {
+    NotEnabled,
+    NothingToUndo,
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoError::NotEnabled => write!(f, "undo is not enabled"),
+            UndoError::NothingToUndo => write!(f, "there are no more rows to undo"),
+        }
+    }
+}
+
+impl error::Error for UndoError {}
+
+/// Why two engines could not be merged, see `Engine::merge`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeError<C = u16, T = u32> {
+    ClientOverlap(C),
+    TxOverlap(T),
+    /// The merged balances do not fit a `Decimal`.
+    Overflow,
+}
+
+impl<C: fmt::Display, T: fmt::Display> fmt::Display for MergeError<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::ClientOverlap(client_id) => {
+                write!(f, "client {} is known to both engines", client_id)
+            }
+            MergeError::TxOverlap(tx_id) => write!(f, "tx {} is known to both engines", tx_id),
+            MergeError::Overflow => write!(f, "the merged balances overflow"),
+        }
+    }
+}
+
+impl<C, T> error::Error for MergeError<C, T>
+where
+    C: fmt::Debug + fmt::Display,
+    T: fmt::Debug + fmt::Display,
+{
+}
+
+impl<C: fmt::Display> fmt::Display for InvariantViolation<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::NegativeHeld(client_id) => {
+                write!(f, "client {} has negative held funds", client_id)
+            }
+            InvariantViolation::TotalMismatch { expected, actual } => write!(
+                f,
+                "accounts total {} but the processed transactions add up to {}",
+                actual, expected
+            ),
+            InvariantViolation::Overflow => {
+                write!(f, "the balances add up beyond the decimal range")
+            }
+        }
+    }
+}
+
+impl<C: fmt::Debug + fmt::Display> error::Error for InvariantViolation<C> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProcessingError<C = u16, T = u32> {
+    // Unknown,
+    AccountNotFound(C),
+    AccountLocked(C),
+    /// `requested` is more than the `available` funds of the client.
+    InsufficientFunds {
+        client_id: C,
+        requested: Decimal,
+        available: Decimal,
+    },
+    DuplicateTx(T),
+    TxAlreadyDisputed(T),
+    TxNotFound(T),
+    TxNotDisputed(T),
+    /// A row of `tx_type` lacks the amount that type requires.
+    AmountNotSpecified {
+        tx_id: T,
+        tx_type: TransactionType,
+    },
+    DisputeWouldOverdraw(C, T),
+    TxAlreadyChargedBack(T),
+    HeldLimitExceeded(C),
+    ReversalTargetNotFound(T),
+    TxAlreadyReversed(T),
+    HeldUnderflow(C, T),
+    TxNotDisputable(T),
+    UnknownTxType(T, String),
+    DestinationNotSpecified(T),
+    TxNotReversible(T),
+    TxNotCancellable(T),
+    AmountTooPrecise(T),
+    DuplicateDisputedTx(T),
+    HoldNotPending(T),
+    AmountUnparseable(T),
+    AmountOverflow(T),
+    UnexpectedAmount(T),
+    NoPriorActivity(C),
+    IdOutOfRange(C, T),
+    /// The row's client is not the `owner` of the tx it refers to.
+    ClientMismatch {
+        tx_id: T,
+        client_id: C,
+        owner: C,
+    },
+    /// The tx exists but its owner has no account, e.g. after restoring a
+    /// checkpoint that lacks the account.
+    OwnerAccountNotFound(C, T),
+    RejectedByGuard(T),
+    /// Opening an account for the client would exceed `max_accounts`.
+    AccountLimitReached(C),
+    /// The dispute's amount is not above zero or exceeds the tx's amount.
+    InvalidDisputeAmount(T),
+}
+
+impl<C, T> ProcessingError<C, T> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessingError::AccountNotFound(_) => "AccountNotFound",
+            ProcessingError::AccountLocked(_) => "AccountLocked",
+            ProcessingError::InsufficientFunds { .. } => "InsufficientFunds",
+            ProcessingError::DuplicateTx(_) => "DuplicateTx",
+            ProcessingError::TxAlreadyDisputed(_) => "TxAlreadyDisputed",
+            ProcessingError::TxNotFound(_) => "TxNotFound",
+            ProcessingError::TxNotDisputed(_) => "TxNotDisputed",
+            ProcessingError::AmountNotSpecified { .. } => "AmountNotSpecified",
+            ProcessingError::DisputeWouldOverdraw(_, _) => "DisputeWouldOverdraw",
+            ProcessingError::TxAlreadyChargedBack(_) => "TxAlreadyChargedBack",
+            ProcessingError::HeldLimitExceeded(_) => "HeldLimitExceeded",
+            ProcessingError::ReversalTargetNotFound(_) => "ReversalTargetNotFound",
+            ProcessingError::TxAlreadyReversed(_) => "TxAlreadyReversed",
+            ProcessingError::HeldUnderflow(_, _) => "HeldUnderflow",
+            ProcessingError::TxNotDisputable(_) => "TxNotDisputable",
+            ProcessingError::UnknownTxType(_, _) => "UnknownTxType",
+            ProcessingError::DestinationNotSpecified(_) => "DestinationNotSpecified",
+            ProcessingError::TxNotReversible(_) => "TxNotReversible",
+            ProcessingError::TxNotCancellable(_) => "TxNotCancellable",
+            ProcessingError::AmountTooPrecise(_) => "AmountTooPrecise",
+            ProcessingError::DuplicateDisputedTx(_) => "DuplicateDisputedTx",
+            ProcessingError::HoldNotPending(_) => "HoldNotPending",
+            ProcessingError::AmountUnparseable(_) => "AmountUnparseable",
+            ProcessingError::AmountOverflow(_) => "AmountOverflow",
+            ProcessingError::UnexpectedAmount(_) => "UnexpectedAmount",
+            ProcessingError::NoPriorActivity(_) => "NoPriorActivity",
+            ProcessingError::IdOutOfRange(..) => "IdOutOfRange",
+            ProcessingError::ClientMismatch { .. } => "ClientMismatch",
+            ProcessingError::OwnerAccountNotFound(_, _) => "OwnerAccountNotFound",
+            ProcessingError::RejectedByGuard(_) => "RejectedByGuard",
+            ProcessingError::AccountLimitReached(_) => "AccountLimitReached",
+            ProcessingError::InvalidDisputeAmount(_) => "InvalidDisputeAmount",
+        }
+    }
+}
+
+impl<C: fmt::Display, T: fmt::Display> fmt::Display for ProcessingError<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessingError::AccountNotFound(client_id) => {
+                write!(f, "client {} does not have an account", client_id)
+            }
+            ProcessingError::AccountLocked(client_id) => {
+                write!(f, "client {} has a locked account", client_id)
+            }
+            ProcessingError::InsufficientFunds {
+                client_id,
+                requested,
+                available,
+            } => write!(
+                f,
+                "client {} has insufficient available funds: {} requested, {} available",
+                client_id, requested, available
+            ),
+            ProcessingError::DuplicateTx(tx_id) => {
+                write!(f, "tx {} has already been processed", tx_id)
+            }
+            ProcessingError::TxAlreadyDisputed(tx_id) => {
+                write!(f, "tx {} is already under dispute", tx_id)
+            }
+            ProcessingError::TxNotFound(tx_id) => write!(f, "tx {} does not exist", tx_id),
+            ProcessingError::TxNotDisputed(tx_id) => {
+                write!(f, "tx {} is not under dispute", tx_id)
+            }
+            ProcessingError::AmountNotSpecified { tx_id, tx_type } => {
+                write!(f, "{} tx {} is missing an amount", tx_type, tx_id)
+            }
+            ProcessingError::DisputeWouldOverdraw(client_id, tx_id) => write!(
+                f,
+                "disputing tx {} would overdraw client {}'s available funds",
+                tx_id, client_id
+            ),
+            ProcessingError::TxAlreadyChargedBack(tx_id) => {
+                write!(f, "tx {} has already been charged back", tx_id)
+            }
+            ProcessingError::HeldLimitExceeded(client_id) => {
+                write!(f, "client {} would exceed its held funds limit", client_id)
+            }
+            ProcessingError::ReversalTargetNotFound(tx_id) => {
+                write!(f, "tx {} cannot be reversed as it does not exist", tx_id)
+            }
+            ProcessingError::TxAlreadyReversed(tx_id) => {
+                write!(f, "tx {} has already been reversed", tx_id)
+            }
+            ProcessingError::HeldUnderflow(client_id, tx_id) => write!(
+                f,
+                "releasing tx {} would leave client {} with negative held funds",
+                tx_id, client_id
+            ),
+            ProcessingError::TxNotDisputable(tx_id) => {
+                write!(f, "tx {} cannot be disputed", tx_id)
+            }
+            ProcessingError::UnknownTxType(tx_id, tx_type) => {
+                write!(f, "tx {} has unknown type {}", tx_id, tx_type)
+            }
+            ProcessingError::DestinationNotSpecified(tx_id) => {
+                write!(f, "tx {} is missing a destination client", tx_id)
+            }
+            ProcessingError::TxNotReversible(tx_id) => {
+                write!(f, "tx {} cannot be reversed", tx_id)
+            }
+            ProcessingError::TxNotCancellable(tx_id) => {
+                write!(f, "tx {} cannot be cancelled", tx_id)
+            }
+            ProcessingError::AmountTooPrecise(tx_id) => {
+                write!(f, "tx {} has an amount with too many decimal places", tx_id)
+            }
+            ProcessingError::DuplicateDisputedTx(tx_id) => {
+                write!(
+                    f,
+                    "tx {} has already been processed and is under dispute",
+                    tx_id
+                )
+            }
+            ProcessingError::HoldNotPending(tx_id) => {
+                write!(f, "tx {} is not a pending hold", tx_id)
+            }
+            ProcessingError::AmountUnparseable(tx_id) => {
+                write!(
+                    f,
+                    "tx {} has an amount that is not a supported number",
+                    tx_id
+                )
+            }
+            ProcessingError::AmountOverflow(tx_id) => {
+                write!(f, "tx {} would overflow a balance or total", tx_id)
+            }
+            ProcessingError::UnexpectedAmount(tx_id) => {
+                write!(f, "tx {} has an amount but refers to another tx", tx_id)
+            }
+            ProcessingError::NoPriorActivity(client_id) => {
+                write!(f, "client {} withdrew before any deposit", client_id)
+            }
+            ProcessingError::IdOutOfRange(client_id, tx_id) => write!(
+                f,
+                "tx {} of client {} has an id outside the accepted range",
+                tx_id, client_id
+            ),
+            ProcessingError::ClientMismatch {
+                tx_id,
+                client_id,
+                owner,
+            } => write!(
+                f,
+                "tx {} belongs to client {}, not client {}",
+                tx_id, owner, client_id
+            ),
+            ProcessingError::OwnerAccountNotFound(client_id, tx_id) => write!(
+                f,
+                "tx {} belongs to client {} who does not have an account",
+                tx_id, client_id
+            ),
+            ProcessingError::RejectedByGuard(tx_id) => {
+                write!(f, "tx {} was rejected by the guard", tx_id)
+            }
+            ProcessingError::AccountLimitReached(client_id) => write!(
+                f,
+                "client {} cannot open an account, the account limit is reached",
+                client_id
+            ),
+            ProcessingError::InvalidDisputeAmount(tx_id) => write!(
+                f,
+                "dispute of tx {} must be for more than zero and at most its amount",
+                tx_id
+            ),
+        }
+    }
+}
+
+impl<C, T> error::Error for ProcessingError<C, T>
+where
+    C: fmt::Debug + fmt::Display,
+    T: fmt::Debug + fmt::Display,
+{
+}
+
+// Both values if they differ, for AccountDiff.
+fn differing<V: PartialEq>(ours: V, theirs: V) -> Option<(V, V)> {
+    (ours != theirs).then_some((ours, theirs))
+}
+
+/// Reports a refused balance change as an overflow if it was one, or as
+/// `otherwise`, e.g. a lack of funds.
+fn balance_error<C, T>(
+    err: BalanceError,
+    tx_id: T,
+    otherwise: ProcessingError<C, T>,
+) -> ProcessingError<C, T> {
+    match err {
+        BalanceError::Overflow => ProcessingError::AmountOverflow(tx_id),
+        _ => otherwise,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::Engine;
+    use crate::models::{TransactionType, TxRow};
+
+    fn row(tx_type: TransactionType, client_id: u16, tx_id: u32, amount: Option<Decimal>) -> TxRow {
+        TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            dest: None,
+            timestamp: None,
+        }
+    }
+
+    mod deposits {
+
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{EngineBuilder, ProcessingError},
+            models::{TransactionType, TxIdScope, TxRow},
         };
 
         use super::Engine;
 
         #[test]
-        fn should_process_withdrawal() {
-            let deposit = TxRow {
+        fn should_process_deposit() {
+            let row = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
-            let withdrawal = TxRow {
-                tx_type: TransactionType::Withdrawal,
-                tx_id: 2,
-                client_id: deposit.client_id,
-                amount: Some(dec!(120.00)),
+            let mut engine = Engine::new();
+            engine.process_row(&row).unwrap();
+            if let Some((acc_id, acc)) = engine.get_account_iter().next() {
+                assert_eq!(*acc_id, 2u16);
+                assert_eq!(acc.available(), dec!(123.45));
+            } else {
+                panic!("account not found");
+            }
+        }
+
+        #[test]
+        fn should_sum_deposits_without_float_drift() {
+            let mut engine = Engine::new();
+            for (tx_id, amount) in [(1, dec!(0.1)), (2, dec!(0.2))] {
+                engine
+                    .process_row(&TxRow {
+                        tx_type: TransactionType::Deposit,
+                        tx_id,
+                        client_id: 1,
+                        amount: Some(amount),
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            // 0.1 + 0.2 is 0.30000000000000004 in f64
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(0.3));
+            assert_eq!(engine.summary().total_deposits, dec!(0.3));
+        }
+
+        #[test]
+        fn should_not_process_deposit_without_amount() {
+            let row = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
-            engine.process_row(&deposit).unwrap();
-            engine.process_row(&withdrawal).unwrap();
-            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
-            assert_eq!(*acc_id, 2u16);
+            let err = engine.process_row(&row).unwrap_err();
             assert_eq!(
-                acc.available,
-                deposit.amount.unwrap() - withdrawal.amount.unwrap()
+                err,
+                ProcessingError::AmountNotSpecified {
+                    tx_id: 1u32,
+                    tx_type: TransactionType::Deposit,
+                }
             );
+            assert_eq!(err.to_string(), "deposit tx 1 is missing an amount");
         }
 
         #[test]
-        fn should_not_process_overdraft_withdrawal() {
-            let deposit = TxRow {
+        fn should_not_process_duplicate_deposit() {
+            let row = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
-            let withdrawal = TxRow {
-                tx_type: TransactionType::Withdrawal,
-                tx_id: 2,
-                client_id: deposit.client_id,
-                amount: Some(dec!(125.00)),
+            let row2 = row;
+            let mut engine = Engine::new();
+            engine.process_row(&row).unwrap();
+            let err = engine.process_row(&row2).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(1u32));
+        }
+
+        #[test]
+        fn should_flag_duplicate_of_disputed_tx() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 5,
+                client_id: 1,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                amount: None,
+                ..deposit
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit).unwrap();
+            engine.process_row(&dispute).unwrap();
+            let err = engine.process_row(&deposit).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateDisputedTx(5));
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                ..deposit
+            };
             let err = engine.process_row(&withdrawal).unwrap_err();
-            assert_eq!(err, ProcessingError::InsufficientFunds(2u16));
-            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
-            assert_eq!(*acc_id, 2u16);
-            assert_eq!(acc.available, deposit.amount.unwrap());
+            assert_eq!(err, ProcessingError::DuplicateDisputedTx(5));
+            let (_, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(data.held(), dec!(10.00));
+            // once resolved it is an ordinary duplicate again
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Resolve,
+                    ..dispute
+                })
+                .unwrap();
+            let err = engine.process_row(&deposit).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(5));
         }
 
         #[test]
-        fn should_not_process_withdrawal_when_account_not_found() {
-            let withdrawal = TxRow {
-                tx_type: TransactionType::Withdrawal,
+        fn should_not_process_same_tx_id_for_other_client_by_default() {
+            let row = TxRow {
+                tx_type: TransactionType::Deposit,
                 tx_id: 1,
+                client_id: 1,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let row2 = TxRow {
                 client_id: 2,
-                amount: Some(dec!(125.00)),
+                ..row
             };
             let mut engine = Engine::new();
-            let err = engine.process_row(&withdrawal).unwrap_err();
-            assert_eq!(err, ProcessingError::AccountNotFound(2u16));
-            assert!(
-                engine.get_account_iter().next().is_none(),
-                "account should not exist"
-            );
+            engine.process_row(&row).unwrap();
+            let err = engine.process_row(&row2).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(1u32));
         }
 
         #[test]
-        fn should_not_process_duplicate_withdrawal() {
-            let deposit = TxRow {
+        fn should_process_same_tx_id_for_different_clients_when_scoped_per_client() {
+            let row = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 1,
+                client_id: 1,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let row2 = TxRow {
                 client_id: 2,
-                amount: Some(dec!(123.45)),
+                amount: Some(dec!(20.00)),
+                ..row
             };
-            let withdrawal1 = TxRow {
-                tx_type: TransactionType::Withdrawal,
-                tx_id: 2,
-                client_id: deposit.client_id,
-                amount: Some(dec!(123.45)),
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: 1,
+                client_id: 2,
+                amount: None,
+                dest: None,
+                timestamp: None,
             };
-            let withdrawal2 = withdrawal1.clone();
-            let mut engine = Engine::new();
-            engine.process_row(&deposit).unwrap();
-            engine.process_row(&withdrawal1).unwrap();
-            let err = engine.process_row(&withdrawal2).unwrap_err();
-            assert_eq!(err, ProcessingError::DuplicateTx(2u32));
+            let mut engine = EngineBuilder::new()
+                .with_tx_id_scope(TxIdScope::PerClient)
+                .build();
+            engine.process_row(&row).unwrap();
+            engine.process_row(&row2).unwrap();
+            let err = engine.process_row(&row).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(1u32));
+            engine.process_row(&dispute).unwrap();
+            let accounts: Vec<_> = engine.get_account_iter().collect();
+            assert_eq!(accounts[0].1.available(), dec!(10.00));
+            assert_eq!(accounts[0].1.held(), dec!(0));
+            assert_eq!(accounts[1].1.available(), dec!(0.00));
+            assert_eq!(accounts[1].1.held(), dec!(20.00));
         }
     }
 
-    mod disputes {
+    mod withdrawals {
+
         use rust_decimal_macros::dec;
 
         use crate::{
@@ -352,24 +2111,211 @@ mod tests {
         use super::Engine;
 
         #[test]
-        fn should_process_dispute() {
+        fn should_process_withdrawal() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(120.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal).unwrap();
+            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(*acc_id, 2u16);
+            assert_eq!(
+                acc.available(),
+                deposit.amount.unwrap() - withdrawal.amount.unwrap()
+            );
+        }
+
+        #[test]
+        fn should_not_process_overdraft_withdrawal() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(125.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            let err = engine.process_row(&withdrawal).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::InsufficientFunds {
+                    client_id: 2u16,
+                    requested: dec!(125.00),
+                    available: dec!(123.45),
+                }
+            );
+            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(*acc_id, 2u16);
+            assert_eq!(acc.available(), deposit.amount.unwrap());
+        }
+
+        #[test]
+        fn should_not_process_withdrawal_without_amount() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 5,
+                amount: None,
+                ..deposit
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            let err = engine.process_row(&withdrawal).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::AmountNotSpecified {
+                    tx_id: 5,
+                    tx_type: TransactionType::Withdrawal,
+                }
+            );
+            assert_eq!(err.to_string(), "withdrawal tx 5 is missing an amount");
+            assert_eq!(engine.stored_tx_count(), 1);
+        }
+
+        #[test]
+        fn should_not_process_withdrawal_when_account_not_found() {
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(125.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            let err = engine.process_row(&withdrawal).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountNotFound(2u16));
+            assert!(
+                engine.get_account_iter().next().is_none(),
+                "account should not exist"
+            );
+        }
+
+        #[test]
+        fn should_not_process_duplicate_withdrawal() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal1 = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal2 = withdrawal1;
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal1).unwrap();
+            let err = engine.process_row(&withdrawal2).unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(2u32));
+        }
+
+        #[test]
+        fn should_report_shortfall_of_overdraft() {
+            let row = |tx_type, tx_id, amount| TxRow {
+                tx_type,
+                tx_id,
+                client_id: 3,
+                amount: Some(amount),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, dec!(10.00)))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 2, dec!(4.00)))
+                .unwrap();
+            match engine.process_row(&row(TransactionType::Withdrawal, 3, dec!(9.50))) {
+                Err(ProcessingError::InsufficientFunds {
+                    client_id,
+                    requested,
+                    available,
+                }) => {
+                    assert_eq!(client_id, 3);
+                    assert_eq!(requested, dec!(9.50));
+                    assert_eq!(available, dec!(6.00));
+                    assert_eq!(requested - available, dec!(3.50));
+                }
+                other => panic!("expected InsufficientFunds, got {:?}", other),
+            }
+        }
+    }
+
+    mod disputes {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{DisputePolicy, EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        use super::Engine;
+
+        #[test]
+        fn should_process_dispute() {
             let deposit1 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: deposit1.client_id,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: deposit2.tx_id,
                 client_id: deposit2.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
@@ -377,13 +2323,48 @@ mod tests {
             engine.process_row(&dispute).unwrap();
             if let Some((acc_id, acc)) = engine.get_account_iter().next() {
                 assert_eq!(*acc_id, 2u16);
-                assert_eq!(acc.available, deposit1.amount.unwrap());
-                assert_eq!(acc.held, deposit2.amount.unwrap());
+                assert_eq!(acc.available(), deposit1.amount.unwrap());
+                assert_eq!(acc.held(), deposit2.amount.unwrap());
             } else {
                 panic!("account not found");
             }
         }
 
+        #[test]
+        fn should_iterate_live_disputes() {
+            let mut engine = Engine::new();
+            for tx_id in 1..=3 {
+                engine
+                    .process_row(&TxRow {
+                        tx_type: TransactionType::Deposit,
+                        tx_id,
+                        client_id: 2,
+                        amount: Some(dec!(10.00)),
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            for (tx_type, tx_id) in [
+                (TransactionType::Dispute, 1),
+                (TransactionType::Dispute, 3),
+                (TransactionType::Resolve, 1),
+            ] {
+                engine
+                    .process_row(&TxRow {
+                        tx_type,
+                        tx_id,
+                        client_id: 2,
+                        amount: None,
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            let disputed: Vec<u32> = engine.disputed_transactions().collect();
+            assert_eq!(disputed, vec![3]);
+        }
+
         #[test]
         fn should_not_process_duplicate_dispute() {
             let deposit1 = TxRow {
@@ -391,20 +2372,26 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: deposit1.client_id,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute1 = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: deposit2.tx_id,
                 client_id: deposit2.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
-            let dispute2 = dispute1.clone();
+            let dispute2 = dispute1;
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
             engine.process_row(&deposit2).unwrap();
@@ -420,18 +2407,24 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: deposit1.client_id,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: 3,
                 client_id: deposit2.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
@@ -441,30 +2434,305 @@ mod tests {
         }
 
         #[test]
-        fn should_not_process_dispute_when_account_not_found() {
+        fn should_not_process_dispute_from_other_client() {
             let deposit1 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: deposit1.client_id,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: 2,
                 client_id: 1,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
             engine.process_row(&deposit2).unwrap();
             let err = engine.process_row(&dispute).unwrap_err();
-            assert_eq!(err, ProcessingError::AccountNotFound(dispute.client_id));
+            assert_eq!(
+                err,
+                ProcessingError::ClientMismatch {
+                    tx_id: 2,
+                    client_id: 1,
+                    owner: 2,
+                }
+            );
+            assert_eq!(engine.get_account(2).unwrap().held, dec!(0));
+        }
+
+        #[test]
+        fn should_not_process_dispute_when_owner_account_not_found() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 3,
+                amount: Some(dec!(5)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: 1,
+                client_id: 3,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            // a checkpoint that kept the tx but lost its owner's account
+            let mut checkpoint = Vec::new();
+            engine.checkpoint(&mut checkpoint).unwrap();
+            let mut checkpoint: serde_json::Value = serde_json::from_slice(&checkpoint).unwrap();
+            checkpoint["account_store"]["accounts"] = serde_json::json!([]);
+            let mut engine = Engine::new();
+            engine.restore(checkpoint.to_string().as_bytes()).unwrap();
+            let err = engine.process_row(&dispute).unwrap_err();
+            assert_eq!(err, ProcessingError::OwnerAccountNotFound(3, 1));
+            let dispute = TxRow {
+                client_id: 4,
+                ..dispute
+            };
+            let err = engine.process_row(&dispute).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::ClientMismatch {
+                    tx_id: 1,
+                    client_id: 4,
+                    owner: 3,
+                }
+            );
+        }
+
+        fn overdrawing_rows() -> [TxRow; 3] {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(90.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit.tx_id,
+                client_id: deposit.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            [deposit, withdrawal, dispute]
+        }
+
+        #[test]
+        fn should_allow_overdrawing_dispute_by_default() {
+            let [deposit, withdrawal, dispute] = overdrawing_rows();
+            let mut engine = EngineBuilder::new()
+                .with_dispute_policy(DisputePolicy::AllowNegative)
+                .build();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal).unwrap();
+            engine.process_row(&dispute).unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(-90.00));
+            assert_eq!(acc.held(), dec!(100.00));
+        }
+
+        #[test]
+        fn should_reject_overdrawing_dispute_under_reject_policy() {
+            let [deposit, withdrawal, dispute] = overdrawing_rows();
+            let mut engine = EngineBuilder::new()
+                .with_dispute_policy(DisputePolicy::Reject)
+                .build();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal).unwrap();
+            let err = engine.process_row(&dispute).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::DisputeWouldOverdraw(dispute.client_id, dispute.tx_id)
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(10.00));
+            assert_eq!(acc.held(), dec!(0.00));
+        }
+
+        // deposit 100, withdraw all of it, then dispute the deposit
+        fn fully_withdrawn_dispute(policy: DisputePolicy) -> Engine {
+            let [deposit, withdrawal, dispute] = overdrawing_rows();
+            let withdrawal = TxRow {
+                amount: deposit.amount,
+                ..withdrawal
+            };
+            let mut engine = EngineBuilder::new().with_dispute_policy(policy).build();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal).unwrap();
+            let result = engine.process_row(&dispute);
+            match policy {
+                DisputePolicy::AllowNegative => assert_eq!(result, Ok(())),
+                DisputePolicy::Reject => {
+                    assert_eq!(result, Err(ProcessingError::DisputeWouldOverdraw(2, 1)))
+                }
+            }
+            engine
+        }
+
+        #[test]
+        fn should_restore_withdrawn_balance_when_overdrawing_dispute_resolves() {
+            let mut engine = fully_withdrawn_dispute(DisputePolicy::AllowNegative);
+            let account = engine.get_account(2).unwrap();
+            assert_eq!(account.available, dec!(-100.00));
+            assert_eq!(account.held, dec!(100.00));
+            assert_eq!(account.total, dec!(0.00));
+            let [_, _, dispute] = overdrawing_rows();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Resolve,
+                    ..dispute
+                })
+                .unwrap();
+            // back to where the client was before the dispute: the withdrawal
+            // stands and the deposit is cleared again
+            let account = engine.get_account(2).unwrap();
+            assert_eq!(account.available, dec!(0.00));
+            assert_eq!(account.held, dec!(0.00));
+            assert!(!account.locked);
+            assert_eq!(engine.accounts_ever_negative(), vec![2]);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_leave_client_owing_when_overdrawing_dispute_is_charged_back() {
+            let mut engine = fully_withdrawn_dispute(DisputePolicy::AllowNegative);
+            let [_, _, dispute] = overdrawing_rows();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Chargeback,
+                    ..dispute
+                })
+                .unwrap();
+            // the deposit is clawed back although its funds were withdrawn
+            let account = engine.get_account(2).unwrap();
+            assert_eq!(account.available, dec!(-100.00));
+            assert_eq!(account.held, dec!(0.00));
+            assert_eq!(account.total, dec!(-100.00));
+            assert!(account.locked);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_leave_fully_withdrawn_deposit_undisputed_under_reject_policy() {
+            let mut engine = fully_withdrawn_dispute(DisputePolicy::Reject);
+            let [_, _, dispute] = overdrawing_rows();
+            assert_eq!(
+                engine.process_row(&TxRow {
+                    tx_type: TransactionType::Resolve,
+                    ..dispute
+                }),
+                Err(ProcessingError::TxNotDisputed(1))
+            );
+            let account = engine.get_account(2).unwrap();
+            assert_eq!(account.available, dec!(0.00));
+            assert_eq!(account.held, dec!(0.00));
+            assert!(engine.accounts_ever_negative().is_empty());
+        }
+
+        fn held_limit_rows() -> [TxRow; 4] {
+            let deposit1 = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(60.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let deposit2 = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 2,
+                client_id: deposit1.client_id,
+                amount: Some(dec!(50.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute1 = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit1.tx_id,
+                client_id: deposit1.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let dispute2 = TxRow {
+                tx_id: deposit2.tx_id,
+                ..dispute1
+            };
+            [deposit1, deposit2, dispute1, dispute2]
+        }
+
+        #[test]
+        fn should_process_disputes_without_held_limit() {
+            let rows = held_limit_rows();
+            let mut engine = Engine::new();
+            engine.set_client_limit(3, dec!(1.00));
+            for row in rows.iter() {
+                engine.process_row(row).unwrap();
+            }
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.held(), dec!(110.00));
+        }
+
+        #[test]
+        fn should_not_process_dispute_exceeding_held_limit() {
+            let [deposit1, deposit2, dispute1, dispute2] = held_limit_rows();
+            let mut engine = Engine::new();
+            engine.set_client_limit(deposit1.client_id, dec!(100.00));
+            engine.process_row(&deposit1).unwrap();
+            engine.process_row(&deposit2).unwrap();
+            engine.process_row(&dispute1).unwrap();
+            let err = engine.process_row(&dispute2).unwrap_err();
+            assert_eq!(err, ProcessingError::HeldLimitExceeded(dispute2.client_id));
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(50.00));
+            assert_eq!(acc.held(), dec!(60.00));
+        }
+
+        #[test]
+        fn should_report_whether_tx_is_disputed() {
+            let [deposit1, deposit2, dispute1, _] = held_limit_rows();
+            let mut engine = Engine::new();
+            engine.process_row(&deposit1).unwrap();
+            engine.process_row(&deposit2).unwrap();
+            engine.process_row(&dispute1).unwrap();
+            assert_eq!(engine.is_tx_disputed(dispute1.tx_id), Some(true));
+            assert_eq!(engine.is_tx_disputed(deposit2.tx_id), Some(false));
+            assert_eq!(engine.is_tx_disputed(99), None);
+            let resolve = TxRow {
+                tx_type: TransactionType::Resolve,
+                ..dispute1
+            };
+            engine.process_row(&resolve).unwrap();
+            assert_eq!(engine.is_tx_disputed(dispute1.tx_id), Some(false));
         }
     }
 
@@ -474,7 +2742,7 @@ mod tests {
 
         use crate::{
             engine::ProcessingError,
-            models::{TransactionType, TxRow},
+            models::{AccountData, TransactionType, TxRow},
         };
 
         use super::Engine;
@@ -486,24 +2754,32 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: deposit1.client_id,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: deposit2.tx_id,
                 client_id: deposit2.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let resolve = TxRow {
                 tx_type: TransactionType::Resolve,
                 tx_id: dispute.tx_id,
                 client_id: dispute.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
@@ -513,10 +2789,42 @@ mod tests {
             let (acc_id2, acc2) = engine.get_account_iter().next().unwrap();
             assert_eq!(*acc_id2, dispute.client_id);
             assert_eq!(
-                acc2.available,
+                acc2.available(),
                 deposit1.amount.unwrap() + deposit2.amount.unwrap()
             );
-            assert_eq!(acc2.held, Decimal::ZERO);
+            assert_eq!(acc2.held(), Decimal::ZERO);
+        }
+
+        #[test]
+        fn should_keep_peak_held_after_resolve() {
+            let row = |tx_type, tx_id, amount| TxRow {
+                tx_type,
+                tx_id,
+                client_id: 2,
+                amount,
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, Some(dec!(30.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, Some(dec!(20.00))))
+                .unwrap();
+            assert_eq!(engine.peak_held(2), Some(Decimal::ZERO));
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, None))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Resolve, 1, None))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 2, None))
+                .unwrap();
+            assert_eq!(engine.get_account(2).unwrap().held, dec!(20.00));
+            assert_eq!(engine.peak_held(2), Some(dec!(30.00)));
+            assert_eq!(engine.peak_held(3), None);
         }
 
         #[test]
@@ -526,12 +2834,16 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let resolve = TxRow {
                 tx_type: TransactionType::Resolve,
                 tx_id: deposit.tx_id,
                 client_id: deposit.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit).unwrap();
@@ -546,18 +2858,64 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(123.45)),
+                dest: None,
+                timestamp: None,
             };
             let resolve = TxRow {
                 tx_type: TransactionType::Resolve,
                 tx_id: 2,
                 client_id: deposit.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit).unwrap();
             let err = engine.process_row(&resolve).unwrap_err();
             assert_eq!(err, ProcessingError::TxNotFound(resolve.tx_id));
         }
+
+        #[test]
+        fn should_not_process_resolve_exceeding_held() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit.tx_id,
+                client_id: deposit.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let resolve = TxRow {
+                tx_type: TransactionType::Resolve,
+                ..dispute
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&dispute).unwrap();
+            // simulate held funds released behind the dispute's back
+            let corrupted = AccountData::new(dec!(60.00), dec!(40.00)).unwrap();
+            engine
+                .account_store
+                .add_or_update_account(&deposit.client_id, &corrupted);
+            let err = engine.process_row(&resolve).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::HeldUnderflow(resolve.client_id, resolve.tx_id)
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(60.00));
+            assert_eq!(acc.held(), dec!(40.00));
+            assert_eq!(engine.disputed_transactions().collect::<Vec<_>>(), vec![1]);
+            assert_eq!(engine.summary().resolves, 0);
+        }
     }
 
     mod chargebacks {
@@ -566,9 +2924,66 @@ mod tests {
 
         use crate::{
             engine::{Engine, ProcessingError},
-            models::{TransactionType, TxRow},
+            models::{AccountData, TransactionType, TxRow, TxState},
         };
 
+        #[test]
+        fn should_charge_back_tx_disputed_again_after_resolve() {
+            let row = |tx_type, tx_id, amount| TxRow {
+                tx_type,
+                tx_id,
+                client_id: 4,
+                amount,
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, Some(dec!(80.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, Some(dec!(20.00))))
+                .unwrap();
+            let balance = |engine: &Engine| {
+                let account = engine.get_account(4).unwrap();
+                (account.available, account.held, account.locked)
+            };
+            let state = |engine: &Engine| engine.export_transactions()[0].state;
+            let steps = [
+                (
+                    TransactionType::Dispute,
+                    (dec!(20.00), dec!(80.00), false),
+                    TxState::Disputed,
+                ),
+                (
+                    TransactionType::Resolve,
+                    (dec!(100.00), dec!(0.00), false),
+                    TxState::Resolved,
+                ),
+                (
+                    TransactionType::Dispute,
+                    (dec!(20.00), dec!(80.00), false),
+                    TxState::Disputed,
+                ),
+                (
+                    TransactionType::Chargeback,
+                    (dec!(20.00), dec!(0.00), true),
+                    TxState::ChargedBack,
+                ),
+            ];
+            for (tx_type, expected_balance, expected_state) in steps {
+                engine.process_row(&row(tx_type, 1, None)).unwrap();
+                assert_eq!(balance(&engine), expected_balance, "after {}", tx_type);
+                assert_eq!(state(&engine), expected_state, "after {}", tx_type);
+            }
+            let summary = engine.summary();
+            assert_eq!(summary.disputes, 2);
+            assert_eq!(summary.resolves, 1);
+            assert_eq!(summary.chargebacks, 1);
+            assert_eq!(summary.total_charged_back, dec!(80.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
         #[test]
         fn should_process_chargeback() {
             let deposit1 = TxRow {
@@ -576,24 +2991,32 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let deposit2 = TxRow {
                 tx_type: TransactionType::Deposit,
                 tx_id: 2,
                 client_id: 2,
                 amount: Some(dec!(50.00)),
+                dest: None,
+                timestamp: None,
             };
             let dispute = TxRow {
                 tx_type: TransactionType::Dispute,
                 tx_id: deposit2.tx_id,
                 client_id: deposit2.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let chargeback = TxRow {
                 tx_type: TransactionType::Chargeback,
                 tx_id: dispute.tx_id,
                 client_id: dispute.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit1).unwrap();
@@ -602,8 +3025,8 @@ mod tests {
             engine.process_row(&chargeback).unwrap();
             let (acc_id, acc) = engine.get_account_iter().next().unwrap();
             assert_eq!(*acc_id, dispute.client_id);
-            assert_eq!(acc.available, deposit1.amount.unwrap());
-            assert_eq!(acc.held, Decimal::ZERO);
+            assert_eq!(acc.available(), deposit1.amount.unwrap());
+            assert_eq!(acc.held(), Decimal::ZERO);
             assert!(engine.is_account_locked(*acc_id))
         }
 
@@ -614,6 +3037,8 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             let err = engine.process_row(&chargeback).unwrap_err();
@@ -627,17 +3052,3364 @@ mod tests {
                 tx_id: 1,
                 client_id: 2,
                 amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
             };
             let chargeback = TxRow {
                 tx_type: TransactionType::Chargeback,
                 tx_id: deposit.tx_id,
                 client_id: deposit.client_id,
                 amount: None,
+                dest: None,
+                timestamp: None,
             };
             let mut engine = Engine::new();
             engine.process_row(&deposit).unwrap();
             let err = engine.process_row(&chargeback).unwrap_err();
             assert_eq!(err, ProcessingError::TxNotDisputed(chargeback.tx_id));
         }
+
+        fn charged_back_engine() -> (Engine, TxRow) {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit.tx_id,
+                client_id: deposit.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let chargeback = TxRow {
+                tx_type: TransactionType::Chargeback,
+                tx_id: dispute.tx_id,
+                client_id: dispute.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&dispute).unwrap();
+            engine.process_row(&chargeback).unwrap();
+            (engine, chargeback)
+        }
+
+        // process_row rejects rows for the now-locked account, so these call the
+        // per-type handlers directly to exercise the transaction state itself
+
+        #[test]
+        fn should_not_process_double_chargeback() {
+            let (mut engine, chargeback) = charged_back_engine();
+            let err = engine
+                .process_chargeback(chargeback.tx_id, chargeback.client_id)
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxAlreadyChargedBack(chargeback.tx_id));
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), Decimal::ZERO);
+            assert_eq!(acc.held(), Decimal::ZERO);
+        }
+
+        #[test]
+        fn should_not_process_resolve_after_chargeback() {
+            let (mut engine, chargeback) = charged_back_engine();
+            let err = engine
+                .process_resolve(chargeback.tx_id, chargeback.client_id)
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotDisputed(chargeback.tx_id));
+            assert_eq!(engine.is_tx_disputed(chargeback.tx_id), Some(false));
+            let err = engine
+                .process_dispute(chargeback.tx_id, chargeback.client_id, None)
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxAlreadyChargedBack(chargeback.tx_id));
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), Decimal::ZERO);
+            assert_eq!(acc.held(), Decimal::ZERO);
+        }
+
+        #[test]
+        fn should_not_process_chargeback_exceeding_held() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit.tx_id,
+                client_id: deposit.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let chargeback = TxRow {
+                tx_type: TransactionType::Chargeback,
+                ..dispute
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&dispute).unwrap();
+            // simulate held funds released behind the dispute's back
+            let corrupted = AccountData::new(dec!(60.00), dec!(40.00)).unwrap();
+            engine
+                .account_store
+                .add_or_update_account(&deposit.client_id, &corrupted);
+            let err = engine.process_row(&chargeback).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::HeldUnderflow(chargeback.client_id, chargeback.tx_id)
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(60.00));
+            assert_eq!(acc.held(), dec!(40.00));
+            assert!(!engine.is_account_locked(chargeback.client_id));
+            assert_eq!(engine.disputed_transactions().collect::<Vec<_>>(), vec![1]);
+            assert_eq!(engine.summary().chargebacks, 0);
+        }
+    }
+
+    mod summary {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::Engine,
+            models::{EngineSummary, TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_track_summary_counters() {
+            let rows = [
+                (TransactionType::Deposit, 1, 1, Some(dec!(100.00))),
+                (TransactionType::Deposit, 2, 2, Some(dec!(50.00))),
+                (TransactionType::Deposit, 1, 3, Some(dec!(25.50))),
+                (TransactionType::Withdrawal, 1, 4, Some(dec!(10.00))),
+                (TransactionType::Withdrawal, 2, 5, Some(dec!(500.00))),
+                (TransactionType::Dispute, 1, 3, None),
+                (TransactionType::Resolve, 1, 3, None),
+                (TransactionType::Dispute, 2, 2, None),
+                (TransactionType::Chargeback, 2, 2, None),
+            ];
+            let mut engine = Engine::new();
+            for (tx_type, client_id, tx_id, amount) in rows {
+                let _ = engine.process_row(&TxRow {
+                    tx_type,
+                    client_id,
+                    tx_id,
+                    amount,
+                    dest: None,
+                    timestamp: None,
+                });
+            }
+            assert_eq!(
+                engine.summary(),
+                EngineSummary {
+                    total_deposits: dec!(175.50),
+                    total_withdrawals: dec!(10.00),
+                    total_charged_back: dec!(50.00),
+                    total_returned: dec!(0),
+                    total_interest: dec!(0),
+                    total_captured: dec!(0),
+                    total_fees: dec!(0),
+                    disputes: 2,
+                    resolves: 1,
+                    chargebacks: 1,
+                    reversals: 0,
+                    cancellations: 0,
+                    interest_credits: 0,
+                    transfers: 0,
+                    holds: 0,
+                    captures: 0,
+                    expired_holds: 0,
+                    new_accounts: 2,
+                    locked_accounts: 1,
+                }
+            );
+        }
+
+        #[test]
+        fn should_count_new_account_once() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                client_id: 3,
+                tx_id: 1,
+                amount: Some(dec!(1.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine
+                .process_row(&TxRow {
+                    tx_id: 2,
+                    ..deposit
+                })
+                .unwrap();
+            assert_eq!(engine.summary().new_accounts, 1);
+            let transfer = TxRow {
+                tx_type: TransactionType::Transfer,
+                tx_id: 3,
+                dest: Some(4),
+                ..deposit
+            };
+            engine.process_row(&transfer).unwrap();
+            engine
+                .process_row(&TxRow {
+                    tx_id: 4,
+                    ..transfer
+                })
+                .unwrap();
+            assert_eq!(engine.summary().new_accounts, 2);
+        }
+    }
+
+    mod validation {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_validate_row_without_mutating_accounts() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(40.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.validate_row(&withdrawal).unwrap();
+            engine.validate_row(&withdrawal).unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(100.00));
+            assert_eq!(engine.summary().total_withdrawals, dec!(0));
+        }
+
+        #[test]
+        fn should_report_same_errors_as_processing() {
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(40.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let engine = Engine::new();
+            let err = engine.validate_row(&withdrawal).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountNotFound(withdrawal.client_id));
+            assert!(engine.get_account_iter().next().is_none());
+        }
+
+        #[test]
+        fn should_validate_sequence_against_shadow_engine() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: deposit.tx_id,
+                client_id: deposit.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let engine = Engine::new();
+            let mut shadow = engine.clone();
+            shadow.process_row(&deposit).unwrap();
+            shadow.process_row(&dispute).unwrap();
+            assert_eq!(
+                shadow.validate_row(&dispute).unwrap_err(),
+                ProcessingError::TxAlreadyDisputed(dispute.tx_id)
+            );
+            assert!(engine.get_account_iter().next().is_none());
+        }
+    }
+
+    mod errors {
+        use std::error::Error;
+
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{RawTxRow, TransactionType, TxRow},
+        };
+
+        fn raw_row(tx_type: &str, tx_id: u32) -> RawTxRow {
+            RawTxRow {
+                tx_type: tx_type.to_string(),
+                client_id: 1,
+                tx_id,
+                amount: Some("5.00".to_string()),
+                dest: None,
+                timestamp: None,
+            }
+        }
+
+        #[test]
+        fn should_skip_raw_row_with_unknown_type() {
+            let mut engine = Engine::new();
+            engine.process_raw_row(&raw_row("deposit", 1)).unwrap();
+            let err = engine.process_raw_row(&raw_row("payout", 2)).unwrap_err();
+            assert_eq!(err, ProcessingError::UnknownTxType(2, "payout".to_string()));
+            assert_eq!(err.name(), "UnknownTxType");
+            engine.process_raw_row(&raw_row("deposit", 3)).unwrap();
+            let (_, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(data.available(), dec!(10.00));
+        }
+
+        #[test]
+        fn should_skip_raw_row_with_unparseable_amount() {
+            let mut engine = Engine::new();
+            engine.process_raw_row(&raw_row("deposit", 1)).unwrap();
+            let huge = RawTxRow {
+                amount: Some("9".repeat(40)),
+                ..raw_row("deposit", 2)
+            };
+            let err = engine.process_raw_row(&huge).unwrap_err();
+            assert_eq!(err, ProcessingError::AmountUnparseable(2));
+            assert_eq!(err.name(), "AmountUnparseable");
+            // the tx id is not taken by the skipped row
+            engine.process_raw_row(&raw_row("deposit", 2)).unwrap();
+            let (_, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(data.available(), dec!(10.00));
+        }
+
+        #[test]
+        fn should_render_errors_as_sentences() {
+            assert_eq!(
+                ProcessingError::<u16, u32>::InsufficientFunds {
+                    client_id: 2,
+                    requested: dec!(5.25),
+                    available: dec!(3.00),
+                }
+                .to_string(),
+                "client 2 has insufficient available funds: 5.25 requested, 3.00 available"
+            );
+            assert_eq!(
+                ProcessingError::<u16, u32>::TxNotDisputed(7).to_string(),
+                "tx 7 is not under dispute"
+            );
+            assert_eq!(
+                ProcessingError::<u16, u32>::DisputeWouldOverdraw(2, 7).to_string(),
+                "disputing tx 7 would overdraw client 2's available funds"
+            );
+        }
+
+        #[test]
+        fn should_box_errors_as_std_errors() {
+            fn deposit_then_dispute(engine: &mut Engine) -> Result<(), Box<dyn Error>> {
+                engine.process_row(&TxRow {
+                    tx_type: TransactionType::Deposit,
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Some(dec!(1)),
+                    dest: None,
+                    timestamp: None,
+                })?;
+                engine.process_row(&TxRow {
+                    tx_type: TransactionType::Dispute,
+                    client_id: 1,
+                    tx_id: 2,
+                    amount: None,
+                    dest: None,
+                    timestamp: None,
+                })?;
+                Ok(())
+            }
+            let err = deposit_then_dispute(&mut Engine::new()).unwrap_err();
+            assert_eq!(err.to_string(), "tx 2 does not exist");
+            assert_eq!(
+                err.downcast_ref::<ProcessingError>(),
+                Some(&ProcessingError::TxNotFound(2))
+            );
+        }
+    }
+
+    mod batches {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_align_batch_results_with_rows() {
+            let rows = [
+                TxRow {
+                    tx_type: TransactionType::Withdrawal,
+                    tx_id: 1,
+                    client_id: 2,
+                    amount: Some(dec!(10.00)),
+                    dest: None,
+                    timestamp: None,
+                },
+                TxRow {
+                    tx_type: TransactionType::Deposit,
+                    tx_id: 2,
+                    client_id: 2,
+                    amount: Some(dec!(10.00)),
+                    dest: None,
+                    timestamp: None,
+                },
+                TxRow {
+                    tx_type: TransactionType::Dispute,
+                    tx_id: 9,
+                    client_id: 2,
+                    amount: None,
+                    dest: None,
+                    timestamp: None,
+                },
+                TxRow {
+                    tx_type: TransactionType::Withdrawal,
+                    tx_id: 3,
+                    client_id: 2,
+                    amount: Some(dec!(4.00)),
+                    dest: None,
+                    timestamp: None,
+                },
+            ];
+            let mut engine = Engine::new();
+            let results = engine.process_batch(&rows);
+            assert_eq!(results.len(), rows.len());
+            for ((row, _), expected) in results.iter().zip(rows.iter()) {
+                assert_eq!(row.tx_id, expected.tx_id);
+            }
+            let outcomes: Vec<_> = results.into_iter().map(|(_, result)| result).collect();
+            assert_eq!(
+                outcomes,
+                vec![
+                    Err(ProcessingError::AccountNotFound(2)),
+                    Ok(()),
+                    Err(ProcessingError::TxNotFound(9)),
+                    Ok(()),
+                ]
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(6.00));
+        }
+
+        #[test]
+        fn should_build_engine_from_rows() {
+            let row = |tx_type, client_id, tx_id, amount| TxRow {
+                tx_type,
+                client_id,
+                tx_id,
+                amount,
+                dest: None,
+                timestamp: None,
+            };
+            let rows = vec![
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))),
+                row(TransactionType::Withdrawal, 2, 2, Some(dec!(1.00))),
+                row(TransactionType::Deposit, 2, 3, Some(dec!(5.00))),
+                row(TransactionType::Dispute, 1, 1, None),
+                row(TransactionType::Withdrawal, 1, 4, Some(dec!(1.00))),
+            ];
+            let mut manual = Engine::new();
+            let manual_errors: Vec<_> = rows
+                .iter()
+                .filter_map(|row| manual.process_row(row).err())
+                .collect();
+
+            let (engine, errors) = Engine::from_rows(rows);
+            assert_eq!(
+                errors,
+                [
+                    ProcessingError::AccountNotFound(2),
+                    ProcessingError::InsufficientFunds {
+                        client_id: 1,
+                        requested: dec!(1.00),
+                        available: dec!(0.00),
+                    },
+                ]
+            );
+            assert_eq!(errors, manual_errors);
+            for client_id in [1, 2] {
+                assert_eq!(engine.get_account(client_id), manual.get_account(client_id));
+            }
+            assert_eq!(engine.state_fingerprint(), manual.state_fingerprint());
+        }
+    }
+
+    mod invariants {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, InvariantViolation},
+            models::{AccountData, TransactionType, TxRow},
+        };
+
+        // xorshift keeps the sequence reproducible without pulling in a rng crate
+        struct Rng(u64);
+
+        impl Rng {
+            fn next(&mut self, bound: u64) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0 % bound
+            }
+        }
+
+        #[test]
+        fn should_hold_invariants_over_random_sequence() {
+            let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+            let mut engine = Engine::new();
+            // (tx_id, client_id) of every deposit/withdrawal so that disputes
+            // reference existing transactions of the right client
+            let mut txs = vec![];
+            for tx_id in 1..=10_000u32 {
+                let row = match rng.next(10) {
+                    n if n < 6 || txs.is_empty() => {
+                        let client_id = rng.next(20) as u16;
+                        txs.push((tx_id, client_id));
+                        TxRow {
+                            tx_type: match rng.next(2) {
+                                0 => TransactionType::Deposit,
+                                _ => TransactionType::Withdrawal,
+                            },
+                            client_id,
+                            tx_id,
+                            amount: Some(Decimal::new(rng.next(100_000) as i64, 2)),
+                            dest: None,
+                            timestamp: None,
+                        }
+                    }
+                    n => {
+                        let (tx_id, client_id) = txs[rng.next(txs.len() as u64) as usize];
+                        TxRow {
+                            tx_type: match n {
+                                6 | 7 => TransactionType::Dispute,
+                                8 => TransactionType::Resolve,
+                                _ => TransactionType::Chargeback,
+                            },
+                            client_id,
+                            tx_id,
+                            amount: None,
+                            dest: None,
+                            timestamp: None,
+                        }
+                    }
+                };
+                let _ = engine.process_row(&row);
+                assert_eq!(engine.verify_invariants(), Ok(()), "after {:?}", row);
+            }
+            assert!(engine.summary().chargebacks > 0);
+        }
+
+        #[test]
+        fn should_detect_total_mismatch() {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.account_store.add_or_update_account(
+                &deposit.client_id,
+                &AccountData::new(dec!(9.00), Decimal::ZERO).unwrap(),
+            );
+            assert_eq!(
+                engine.verify_invariants(),
+                Err(InvariantViolation::TotalMismatch {
+                    expected: dec!(10.00),
+                    actual: dec!(9.00),
+                })
+            );
+        }
+
+        #[test]
+        fn should_report_overflowing_total() {
+            let mut engine = Engine::new();
+            for client_id in [1, 2] {
+                engine.account_store.add_or_update_account(
+                    &client_id,
+                    &AccountData::new(Decimal::MAX, Decimal::ZERO).unwrap(),
+                );
+            }
+            assert_eq!(
+                engine.verify_invariants(),
+                Err(InvariantViolation::Overflow)
+            );
+        }
+    }
+
+    mod withdrawal_disputes {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_not_dispute_withdrawal_refused_for_lack_of_funds() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            assert!(matches!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(1000)))),
+                Err(ProcessingError::InsufficientFunds { .. })
+            ));
+            for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                assert_eq!(
+                    engine.process_row(&row(tx_type, 1, 2, None)),
+                    Err(ProcessingError::TxNotFound(2))
+                );
+            }
+            let (_, account) = engine.get_account_iter().next().unwrap();
+            assert_eq!(account.available(), dec!(10));
+            assert!(!engine.is_account_locked(1));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_not_dispute_withdrawal_refused_for_missing_account() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(1))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 2, 2, Some(dec!(50)))),
+                Err(ProcessingError::AccountNotFound(2))
+            );
+            for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                assert_eq!(
+                    engine.process_row(&row(tx_type, 2, 2, None)),
+                    Err(ProcessingError::TxNotFound(2))
+                );
+            }
+            assert_eq!(engine.get_account_iter().count(), 1);
+            // the id of the refused withdrawal is still free
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(50))))
+                .unwrap();
+            let (_, account) = engine.get_account_iter().next().unwrap();
+            assert_eq!(account.available(), dec!(51));
+        }
+
+        fn disputed_withdrawal_engine() -> (Engine, TxRow) {
+            let deposit = TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 1,
+                client_id: 2,
+                amount: Some(dec!(100.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let withdrawal = TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 2,
+                client_id: deposit.client_id,
+                amount: Some(dec!(40.00)),
+                dest: None,
+                timestamp: None,
+            };
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                tx_id: withdrawal.tx_id,
+                client_id: withdrawal.client_id,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            };
+            let mut engine = Engine::new();
+            engine.process_row(&deposit).unwrap();
+            engine.process_row(&withdrawal).unwrap();
+            engine.process_row(&dispute).unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(60.00));
+            assert_eq!(acc.held(), dec!(40.00));
+            (engine, dispute)
+        }
+
+        #[test]
+        fn should_restore_funds_on_withdrawal_chargeback() {
+            let (mut engine, dispute) = disputed_withdrawal_engine();
+            let chargeback = TxRow {
+                tx_type: TransactionType::Chargeback,
+                ..dispute
+            };
+            engine.process_row(&chargeback).unwrap();
+            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(100.00));
+            assert_eq!(acc.held(), Decimal::ZERO);
+            assert!(engine.is_account_locked(*acc_id));
+            assert_eq!(engine.summary().total_returned, dec!(40.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_drop_pending_return_on_withdrawal_resolve() {
+            let (mut engine, dispute) = disputed_withdrawal_engine();
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            let resolve = TxRow {
+                tx_type: TransactionType::Resolve,
+                ..dispute
+            };
+            engine.process_row(&resolve).unwrap();
+            let (acc_id, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(60.00));
+            assert_eq!(acc.held(), Decimal::ZERO);
+            assert!(!engine.is_account_locked(*acc_id));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod reversals {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        fn engine_with_deposit_and_withdrawal() -> Engine {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Deposit,
+                    tx_id: 1,
+                    client_id: 2,
+                    amount: Some(dec!(100.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Withdrawal,
+                    tx_id: 2,
+                    client_id: 2,
+                    amount: Some(dec!(30.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            engine
+        }
+
+        fn reversal(tx_id: u32) -> TxRow {
+            TxRow {
+                tx_type: TransactionType::Reversal,
+                tx_id,
+                client_id: 2,
+                amount: None,
+                dest: None,
+                timestamp: None,
+            }
+        }
+
+        #[test]
+        fn should_not_reverse_refused_withdrawal() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            assert!(matches!(
+                engine.process_row(&TxRow {
+                    tx_type: TransactionType::Withdrawal,
+                    tx_id: 3,
+                    client_id: 2,
+                    amount: Some(dec!(1000.00)),
+                    dest: None,
+                    timestamp: None,
+                }),
+                Err(ProcessingError::InsufficientFunds { .. })
+            ));
+            assert_eq!(
+                engine.process_row(&reversal(3)),
+                Err(ProcessingError::ReversalTargetNotFound(3))
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(70.00));
+            assert_eq!(engine.summary().total_withdrawals, dec!(30.00));
+        }
+
+        #[test]
+        fn should_reverse_deposit() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Deposit,
+                    tx_id: 3,
+                    client_id: 2,
+                    amount: Some(dec!(20.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            engine.process_row(&reversal(3)).unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(70.00));
+            assert_eq!(engine.summary().total_deposits, dec!(100.00));
+            assert_eq!(engine.summary().reversals, 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_reverse_withdrawal() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            engine.process_row(&reversal(2)).unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(100.00));
+            assert_eq!(engine.summary().total_withdrawals, dec!(0));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_not_reverse_twice_or_dispute_after_reversal() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            engine.process_row(&reversal(2)).unwrap();
+            let err = engine.process_row(&reversal(2)).unwrap_err();
+            assert_eq!(err, ProcessingError::TxAlreadyReversed(2));
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                ..reversal(2)
+            };
+            let err = engine.process_row(&dispute).unwrap_err();
+            assert_eq!(err, ProcessingError::TxAlreadyReversed(2));
+        }
+
+        #[test]
+        fn should_not_reverse_non_existing_tx() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            let err = engine.process_row(&reversal(9)).unwrap_err();
+            assert_eq!(err, ProcessingError::ReversalTargetNotFound(9));
+        }
+
+        #[test]
+        fn should_not_reverse_deposit_already_spent() {
+            let mut engine = engine_with_deposit_and_withdrawal();
+            let err = engine.process_row(&reversal(1)).unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::InsufficientFunds {
+                    client_id: 2,
+                    requested: dec!(100.00),
+                    available: dec!(70.00),
+                }
+            );
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(70.00));
+        }
+    }
+
+    mod locked_accounts {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, EngineBuilder, LockedAccountPolicy, ProcessingError},
+            models::{EngineEvent, TransactionType, TxRow},
+        };
+
+        fn lock(engine: &mut Engine) {
+            for (tx_type, amount) in [
+                (TransactionType::Deposit, Some(dec!(100.00))),
+                (TransactionType::Dispute, None),
+                (TransactionType::Chargeback, None),
+            ] {
+                engine
+                    .process_row(&TxRow {
+                        tx_type,
+                        tx_id: 1,
+                        client_id: 2,
+                        amount,
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            assert!(engine.is_account_locked(2));
+        }
+
+        fn deposit() -> TxRow {
+            TxRow {
+                tx_type: TransactionType::Deposit,
+                tx_id: 2,
+                client_id: 2,
+                amount: Some(dec!(50.00)),
+                dest: None,
+                timestamp: None,
+            }
+        }
+
+        fn withdrawal() -> TxRow {
+            TxRow {
+                tx_type: TransactionType::Withdrawal,
+                tx_id: 3,
+                client_id: 2,
+                amount: Some(dec!(10.00)),
+                dest: None,
+                timestamp: None,
+            }
+        }
+
+        #[test]
+        fn should_reject_all_rows_for_locked_account_by_default() {
+            let mut engine = Engine::new();
+            lock(&mut engine);
+            let err = engine.process_row(&deposit()).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountLocked(2));
+            let err = engine.process_row(&withdrawal()).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountLocked(2));
+        }
+
+        #[test]
+        fn should_allow_deposits_to_locked_account_when_configured() {
+            let mut engine = EngineBuilder::new()
+                .with_locked_policy(LockedAccountPolicy::AllowDeposits)
+                .build();
+            lock(&mut engine);
+            engine.process_row(&deposit()).unwrap();
+            let err = engine.process_row(&withdrawal()).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountLocked(2));
+            let dispute = TxRow {
+                tx_type: TransactionType::Dispute,
+                amount: None,
+                ..deposit()
+            };
+            let err = engine.process_row(&dispute).unwrap_err();
+            assert_eq!(err, ProcessingError::AccountLocked(2));
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(50.00));
+            assert!(engine.is_account_locked(2));
+        }
+
+        #[test]
+        fn should_unlock_account_once_recovery_deposit_clears_debt() {
+            let mut engine = EngineBuilder::new()
+                .with_locked_policy(LockedAccountPolicy::AllowDeposits)
+                .with_auto_unlock(true)
+                .build();
+            let row = |tx_type, tx_id, amount| TxRow {
+                tx_type,
+                tx_id,
+                client_id: 2,
+                amount,
+                dest: None,
+                timestamp: None,
+            };
+            // the disputed deposit was already partly withdrawn, so the
+            // chargeback leaves the client owing 80
+            for row in [
+                row(TransactionType::Deposit, 1, Some(dec!(100))),
+                row(TransactionType::Withdrawal, 2, Some(dec!(80))),
+                row(TransactionType::Dispute, 1, None),
+                row(TransactionType::Chargeback, 1, None),
+                row(TransactionType::Deposit, 3, Some(dec!(50))),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            assert!(engine.is_account_locked(2));
+            assert_eq!(engine.get_account(2).unwrap().available, dec!(-30));
+
+            engine
+                .process_row(&row(TransactionType::Deposit, 4, Some(dec!(30))))
+                .unwrap();
+            assert!(!engine.is_account_locked(2));
+            assert_eq!(
+                engine.events().last(),
+                Some(&EngineEvent::AccountUnlocked(2))
+            );
+            engine
+                .process_row(&row(TransactionType::Deposit, 5, Some(dec!(10))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 6, Some(dec!(5))))
+                .unwrap();
+            assert_eq!(engine.get_account(2).unwrap().available, dec!(5));
+        }
+
+        #[test]
+        fn should_keep_lock_without_auto_unlock() {
+            let mut engine = EngineBuilder::new()
+                .with_locked_policy(LockedAccountPolicy::AllowDeposits)
+                .build();
+            lock(&mut engine);
+            engine.process_row(&deposit()).unwrap();
+            assert!(engine.is_account_locked(2));
+        }
+
+        #[test]
+        fn should_list_locked_accounts() {
+            let mut engine = Engine::new();
+            assert!(engine.locked_accounts().is_empty());
+            for (client_id, tx_id) in [(7, 1), (3, 2), (5, 3)] {
+                engine
+                    .process_row(&TxRow {
+                        tx_type: TransactionType::Deposit,
+                        tx_id,
+                        client_id,
+                        amount: Some(dec!(10.00)),
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            for (client_id, tx_id) in [(7, 1), (3, 2)] {
+                for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                    engine
+                        .process_row(&TxRow {
+                            tx_type,
+                            tx_id,
+                            client_id,
+                            amount: None,
+                            dest: None,
+                            timestamp: None,
+                        })
+                        .unwrap();
+                }
+            }
+            assert_eq!(engine.locked_accounts(), vec![3, 7]);
+            engine.reset_account(7);
+            assert_eq!(engine.locked_accounts(), vec![3]);
+        }
+    }
+
+    mod interest {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, InterestPolicy, ProcessingError},
+            models::TransactionType,
+        };
+
+        #[test]
+        fn should_credit_interest_to_available() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 4, 1, Some(dec!(100.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Interest, 4, 2, Some(dec!(1.25))))
+                .unwrap();
+            let (_, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(data.available(), dec!(101.25));
+            assert_eq!(data.held(), dec!(0));
+            let summary = engine.summary();
+            assert_eq!(summary.total_deposits, dec!(100.00));
+            assert_eq!(summary.total_interest, dec!(1.25));
+            assert_eq!(summary.interest_credits, 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_open_account_for_interest_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Interest, 4, 1, Some(dec!(0.50))))
+                .unwrap();
+            let (id, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(*id, 4);
+            assert_eq!(data.available(), dec!(0.50));
+        }
+
+        #[test]
+        fn should_not_credit_interest_to_missing_account_when_required() {
+            let mut engine = EngineBuilder::new()
+                .with_interest_policy(InterestPolicy::RequireAccount)
+                .build();
+            let err = engine
+                .process_row(&row(TransactionType::Interest, 4, 1, Some(dec!(0.50))))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::AccountNotFound(4));
+            assert_eq!(engine.get_account_iter().count(), 0);
+            assert_eq!(engine.summary().interest_credits, 0);
+        }
+
+        #[test]
+        fn should_not_dispute_interest() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Interest, 4, 1, Some(dec!(0.50))))
+                .unwrap();
+            let err = engine
+                .process_row(&row(TransactionType::Dispute, 4, 1, None))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotDisputable(1));
+            assert_eq!(engine.summary().disputes, 0);
+        }
+
+        #[test]
+        fn should_not_process_interest_without_amount() {
+            let mut engine = Engine::new();
+            let err = engine
+                .process_row(&row(TransactionType::Interest, 4, 1, None))
+                .unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::AmountNotSpecified {
+                    tx_id: 1,
+                    tx_type: TransactionType::Interest,
+                }
+            );
+        }
+    }
+
+    mod generic_ids {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        const CLIENT: u64 = 5_000_000_000;
+        const TX: u64 = 9_000_000_000;
+
+        #[test]
+        fn should_process_rows_with_u64_ids() {
+            let mut engine: Engine<u64, u64> = Engine::default();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Deposit,
+                    client_id: CLIENT,
+                    tx_id: TX,
+                    amount: Some(dec!(10.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Withdrawal,
+                    client_id: CLIENT,
+                    tx_id: TX + 1,
+                    amount: Some(dec!(4.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            let (id, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(*id, CLIENT);
+            assert_eq!(data.available(), dec!(6.00));
+            let err = engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Dispute,
+                    client_id: CLIENT,
+                    tx_id: TX + 2,
+                    amount: None,
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotFound(TX + 2));
+            assert_eq!(err.to_string(), "tx 9000000002 does not exist");
+        }
+
+        #[test]
+        fn should_build_engine_with_u128_tx_ids() {
+            let mut engine = EngineBuilder::<u16, u128>::default().build();
+            let tx_id = u128::MAX;
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Deposit,
+                    client_id: 1,
+                    tx_id,
+                    amount: Some(dec!(1.00)),
+                    dest: None,
+                    timestamp: None,
+                })
+                .unwrap();
+            assert_eq!(engine.summary().total_deposits, dec!(1.00));
+        }
+    }
+
+    mod transfers {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        fn transfer(client_id: u16, dest: u16, tx_id: u32, amount: Decimal) -> TxRow {
+            TxRow {
+                dest: Some(dest),
+                ..row(TransactionType::Transfer, client_id, tx_id, Some(amount))
+            }
+        }
+
+        fn funded_engine() -> Engine {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+        }
+
+        fn balance(engine: &Engine, client_id: u16) -> Option<Decimal> {
+            engine
+                .get_account_iter()
+                .find(|(id, _)| **id == client_id)
+                .map(|(_, data)| data.available())
+        }
+
+        #[test]
+        fn should_process_transfer() {
+            let mut engine = funded_engine();
+            engine.process_row(&transfer(1, 2, 2, dec!(4.00))).unwrap();
+            assert_eq!(balance(&engine, 1), Some(dec!(6.00)));
+            assert_eq!(balance(&engine, 2), Some(dec!(4.00)));
+            assert_eq!(engine.summary().transfers, 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_not_process_overdrafting_transfer() {
+            let mut engine = funded_engine();
+            let err = engine
+                .process_row(&transfer(1, 2, 2, dec!(10.01)))
+                .unwrap_err();
+            assert_eq!(
+                err,
+                ProcessingError::InsufficientFunds {
+                    client_id: 1,
+                    requested: dec!(10.01),
+                    available: dec!(10.00),
+                }
+            );
+            assert_eq!(balance(&engine, 1), Some(dec!(10.00)));
+            assert_eq!(balance(&engine, 2), None);
+            // the failed transfer's id stays free
+            engine.process_row(&transfer(1, 2, 2, dec!(10.00))).unwrap();
+        }
+
+        #[test]
+        fn should_not_process_transfer_from_locked_account() {
+            let mut engine = funded_engine();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Chargeback, 1, 2, None))
+                .unwrap();
+            let err = engine
+                .process_row(&transfer(1, 2, 3, dec!(1.00)))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::AccountLocked(1));
+            assert_eq!(balance(&engine, 2), None);
+        }
+
+        #[test]
+        fn should_not_dispute_or_reverse_transfer() {
+            let mut engine = funded_engine();
+            engine.process_row(&transfer(1, 2, 2, dec!(4.00))).unwrap();
+            let err = engine
+                .process_row(&row(TransactionType::Dispute, 1, 2, None))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotDisputable(2));
+            let err = engine
+                .process_row(&row(TransactionType::Reversal, 1, 2, None))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotReversible(2));
+        }
+
+        #[test]
+        fn should_not_process_transfer_without_destination() {
+            let mut engine = funded_engine();
+            let err = engine
+                .process_row(&row(TransactionType::Transfer, 1, 2, Some(dec!(1.00))))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::DestinationNotSpecified(2));
+        }
+    }
+
+    mod rounding {
+        use rust_decimal_macros::dec;
+
+        use crate::engine::{Engine, EngineBuilder, Rounding};
+
+        #[test]
+        fn should_round_half_even_by_default() {
+            let engine = Engine::new();
+            assert_eq!(engine.round(dec!(1.23445)), dec!(1.2344));
+            assert_eq!(engine.round(dec!(1.23455)), dec!(1.2346));
+        }
+
+        #[test]
+        fn should_round_with_selected_strategy() {
+            let half_up: Engine = EngineBuilder::new().with_rounding(Rounding::HalfUp).build();
+            assert_eq!(half_up.round(dec!(1.23445)), dec!(1.2345));
+            assert_eq!(half_up.round(dec!(-1.23445)), dec!(-1.2345));
+            let truncate: Engine = EngineBuilder::new()
+                .with_rounding(Rounding::Truncate)
+                .build();
+            assert_eq!(truncate.round(dec!(1.23449)), dec!(1.2344));
+            assert_eq!(truncate.round(dec!(-1.23449)), dec!(-1.2344));
+        }
+
+        #[test]
+        fn should_round_to_configured_precision() {
+            let engine: Engine = EngineBuilder::new().with_precision(2).build();
+            assert_eq!(engine.precision(), 2);
+            assert_eq!(engine.round(dec!(1.235)), dec!(1.24));
+            assert_eq!(engine.round(dec!(1.245)), dec!(1.24));
+        }
+    }
+
+    mod amount_scale {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::{AmountScalePolicy, Engine, EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        fn deposit(tx_id: u32, amount: Decimal) -> TxRow {
+            TxRow {
+                tx_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id,
+                amount: Some(amount),
+                dest: None,
+                timestamp: None,
+            }
+        }
+
+        #[test]
+        fn should_keep_amount_scale_by_default() {
+            let mut engine = Engine::new();
+            engine.process_row(&deposit(1, dec!(1.234567))).unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(1.234567));
+        }
+
+        #[test]
+        fn should_reject_too_precise_amount() {
+            let mut engine = EngineBuilder::new()
+                .with_amount_scale_policy(AmountScalePolicy::Reject(4))
+                .build();
+            let err = engine.process_row(&deposit(1, dec!(1.234567))).unwrap_err();
+            assert_eq!(err, ProcessingError::AmountTooPrecise(1));
+            assert_eq!(engine.get_account(1), None);
+            // trailing zeros are fine
+            engine.process_row(&deposit(2, dec!(1.230000))).unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(1.23));
+        }
+
+        #[test]
+        fn should_round_too_precise_amount() {
+            let mut engine = EngineBuilder::new()
+                .with_amount_scale_policy(AmountScalePolicy::Round(4))
+                .build();
+            engine.process_row(&deposit(1, dec!(1.234567))).unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(1.2346));
+            assert_eq!(account.available.scale(), 4);
+            assert_eq!(engine.summary().total_deposits, dec!(1.2346));
+        }
+    }
+
+    mod resets {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{AccountSnapshot, TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_reset_account_to_clean_slate() {
+            let mut engine = Engine::new();
+            let rows = [
+                row(TransactionType::Deposit, 7, 1, Some(dec!(10.00))),
+                row(TransactionType::Deposit, 7, 2, Some(dec!(5.00))),
+                row(TransactionType::Withdrawal, 7, 3, Some(dec!(2.00))),
+                row(TransactionType::Dispute, 7, 1, None),
+                row(TransactionType::Chargeback, 7, 1, None),
+            ];
+            for row in &rows {
+                engine.process_row(row).unwrap();
+            }
+            let other = TxRow {
+                client_id: 8,
+                ..row(TransactionType::Deposit, 7, 4, Some(dec!(1.00)))
+            };
+            engine.process_row(&other).unwrap();
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Dispute,
+                    ..other
+                })
+                .unwrap();
+            engine.reset_account(7);
+            assert_eq!(
+                engine.get_account(7),
+                Some(AccountSnapshot {
+                    client_id: 7,
+                    available: dec!(0),
+                    held: dec!(0),
+                    total: dec!(0),
+                    locked: false,
+                })
+            );
+            // other clients are untouched
+            assert_eq!(engine.get_account(8).unwrap().held, dec!(1.00));
+            assert_eq!(engine.disputed_transactions().collect::<Vec<_>>(), vec![4]);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            // the account is usable again but keeps its history
+            engine
+                .process_row(&row(TransactionType::Deposit, 7, 5, Some(dec!(3.00))))
+                .unwrap();
+            let err = engine
+                .process_row(&row(TransactionType::Deposit, 7, 2, Some(dec!(3.00))))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::DuplicateTx(2));
+            assert_eq!(engine.get_account(7).unwrap().available, dec!(3.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_end_disputes_when_resetting() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 7, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 7, 2, Some(dec!(4.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 7, 2, None))
+                .unwrap();
+            engine.reset_account(7);
+            assert_eq!(engine.disputed_transactions().count(), 0);
+            let err = engine
+                .process_row(&row(TransactionType::Chargeback, 7, 2, None))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotDisputed(2));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod snapshots {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{AccountSnapshot, TransactionSnapshot, TransactionType, TxState},
+        };
+
+        #[test]
+        fn should_return_balance_after_row() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 6, 1, Some(dec!(10.00))))
+                .unwrap();
+            let snapshot = engine
+                .process_row_with_state(&row(TransactionType::Dispute, 6, 1, None))
+                .unwrap();
+            assert_eq!(
+                snapshot,
+                AccountSnapshot {
+                    client_id: 6,
+                    available: dec!(0.00),
+                    held: dec!(10.00),
+                    total: dec!(10.00),
+                    locked: false,
+                }
+            );
+            assert_eq!(engine.get_account(6), Some(snapshot));
+            let snapshot = engine
+                .process_row_with_state(&row(TransactionType::Chargeback, 6, 1, None))
+                .unwrap();
+            assert!(snapshot.locked);
+            assert_eq!(engine.get_account(6), Some(snapshot));
+        }
+
+        #[test]
+        fn should_return_error_for_rejected_row() {
+            let mut engine = Engine::new();
+            let err = engine
+                .process_row_with_state(&row(TransactionType::Withdrawal, 6, 1, Some(dec!(1.00))))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::AccountNotFound(6));
+            assert_eq!(engine.get_account(6), None);
+        }
+
+        #[test]
+        fn should_export_stored_transactions() {
+            let mut engine = Engine::new();
+            assert_eq!(engine.export_transactions(), []);
+            for row in [
+                row(TransactionType::Deposit, 6, 3, Some(dec!(10.00))),
+                row(TransactionType::Deposit, 6, 1, Some(dec!(5.00))),
+                row(TransactionType::Withdrawal, 6, 2, Some(dec!(2.50))),
+                row(TransactionType::Reversal, 6, 1, None),
+                row(TransactionType::Dispute, 6, 3, None),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            // a duplicate leaves the stored transaction as it was
+            assert!(engine
+                .process_row(&row(TransactionType::Withdrawal, 6, 2, Some(dec!(1.00))))
+                .is_err());
+            let tx = |tx_id, tx_type, amount, state| TransactionSnapshot {
+                tx_id,
+                client_id: 6,
+                tx_type,
+                amount,
+                state,
+            };
+            assert_eq!(
+                engine.export_transactions(),
+                [
+                    tx(1, TransactionType::Deposit, dec!(5.00), TxState::Reversed),
+                    tx(2, TransactionType::Withdrawal, dec!(2.50), TxState::Normal),
+                    tx(3, TransactionType::Deposit, dec!(10.00), TxState::Disputed),
+                ]
+            );
+        }
+    }
+
+    mod audit {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder},
+            models::{AuditEntry, TransactionType},
+        };
+
+        #[test]
+        fn should_record_transitions_when_auditing() {
+            let mut engine = EngineBuilder::new().with_audit(true).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(50.00))))
+                .unwrap_err();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert_eq!(
+                engine.audit_trail(),
+                [
+                    AuditEntry {
+                        tx_id: 1,
+                        client_id: 1,
+                        kind: TransactionType::Deposit,
+                        available_before: dec!(0),
+                        available_after: dec!(10.00),
+                        held_before: dec!(0),
+                        held_after: dec!(0),
+                    },
+                    AuditEntry {
+                        tx_id: 1,
+                        client_id: 1,
+                        kind: TransactionType::Dispute,
+                        available_before: dec!(10.00),
+                        available_after: dec!(0.00),
+                        held_before: dec!(0),
+                        held_after: dec!(10.00),
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn should_not_record_transitions_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            assert!(engine.audit_trail().is_empty());
+        }
+
+        #[test]
+        fn should_record_both_sides_of_transfer() {
+            let mut engine = EngineBuilder::new().with_audit(true).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            let mut transfer = row(TransactionType::Transfer, 1, 2, Some(dec!(4)));
+            engine.process_row(&transfer).unwrap_err();
+            transfer.dest = Some(2);
+            engine.process_row(&transfer).unwrap();
+            let trail = engine.audit_trail();
+            assert_eq!(trail.len(), 3);
+            assert_eq!(trail[1].client_id, 1);
+            assert_eq!(trail[1].available_after, dec!(6));
+            assert_eq!(trail[2].client_id, 2);
+            assert_eq!(trail[2].available_after, dec!(4));
+        }
+    }
+
+    mod client_stats {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::Engine,
+            models::{ClientStats, TransactionType},
+        };
+
+        #[test]
+        fn should_track_stats_per_client() {
+            let rows = [
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))),
+                row(TransactionType::Deposit, 1, 2, Some(dec!(5.00))),
+                row(TransactionType::Withdrawal, 1, 3, Some(dec!(3.00))),
+                row(TransactionType::Deposit, 2, 4, Some(dec!(20.00))),
+                row(TransactionType::Withdrawal, 2, 5, Some(dec!(50.00))),
+                row(TransactionType::Dispute, 2, 4, None),
+                row(TransactionType::Chargeback, 2, 4, None),
+            ];
+            let mut engine = Engine::new();
+            engine.process_batch(&rows);
+            let stats = engine.client_stats();
+            assert_eq!(stats.len(), 2);
+            assert_eq!(
+                stats[&1],
+                ClientStats {
+                    deposits: 2,
+                    withdrawals: 1,
+                    disputes: 0,
+                    locked: false,
+                    available: dec!(12.00),
+                    held: dec!(0.00),
+                }
+            );
+            // the failed withdrawal is not counted
+            assert_eq!(
+                stats[&2],
+                ClientStats {
+                    deposits: 1,
+                    withdrawals: 0,
+                    disputes: 1,
+                    locked: true,
+                    available: dec!(0.00),
+                    held: dec!(0.00),
+                }
+            );
+        }
+    }
+
+    mod negative_accounts {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::Engine,
+            models::{EngineEvent, TransactionType},
+        };
+
+        #[test]
+        fn should_report_account_that_went_negative_and_recovered() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 3, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 3, 2, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 3, 1, None))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Resolve, 3, 1, None))
+                .unwrap();
+            let (_, data) = engine.get_account_iter().next().unwrap();
+            assert_eq!(data.available(), dec!(5.00));
+            assert_eq!(engine.accounts_ever_negative(), vec![3]);
+        }
+
+        #[test]
+        fn should_not_report_accounts_that_stayed_positive() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 3, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 3, 1, None))
+                .unwrap();
+            assert!(engine.accounts_ever_negative().is_empty());
+        }
+
+        #[test]
+        fn should_signal_recovery_from_negative_once() {
+            let mut engine = Engine::new();
+            for (tx_type, tx_id, amount) in [
+                (TransactionType::Deposit, 1, Some(dec!(10.00))),
+                (TransactionType::Withdrawal, 2, Some(dec!(8.00))),
+                (TransactionType::Dispute, 1, None),
+                (TransactionType::Deposit, 3, Some(dec!(5.00))),
+            ] {
+                engine.process_row(&row(tx_type, 3, tx_id, amount)).unwrap();
+            }
+            // still at -3.00
+            assert!(engine.events().is_empty());
+            engine
+                .process_row(&row(TransactionType::Deposit, 3, 4, Some(dec!(3.00))))
+                .unwrap();
+            assert_eq!(
+                engine.events(),
+                [EngineEvent::AccountRecoveredFromNegative(3)]
+            );
+            engine
+                .process_row(&row(TransactionType::Deposit, 3, 5, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.events().len(), 1);
+        }
+    }
+
+    mod holds {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::TransactionType,
+        };
+
+        fn engine_with_hold(expiry: u64) -> Engine {
+            let mut engine = EngineBuilder::new().with_hold_expiry(expiry).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(100.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Hold, 1, 2, Some(dec!(30.00))))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(70.00));
+            assert_eq!(account.held, dec!(30.00));
+            engine
+        }
+
+        // processes rows unrelated to the hold
+        fn pass_rows(engine: &mut Engine, tx_ids: std::ops::Range<u32>) {
+            for tx_id in tx_ids {
+                engine
+                    .process_row(&row(TransactionType::Deposit, 1, tx_id, Some(dec!(1.00))))
+                    .unwrap();
+            }
+        }
+
+        #[test]
+        fn should_capture_hold_before_expiry() {
+            let mut engine = engine_with_hold(3);
+            pass_rows(&mut engine, 10..11);
+            // the third row after the hold would find it expired
+            engine
+                .process_row(&row(TransactionType::Capture, 1, 2, None))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(71.00));
+            assert_eq!(account.held, dec!(0.00));
+            assert_eq!(account.total, dec!(71.00));
+            // captured holds never expire
+            pass_rows(&mut engine, 11..20);
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(80.00));
+            let summary = engine.summary();
+            assert_eq!(summary.total_captured, dec!(30.00));
+            assert_eq!(
+                (summary.holds, summary.captures, summary.expired_holds),
+                (1, 1, 0)
+            );
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_release_hold_after_expiry() {
+            let mut engine = engine_with_hold(3);
+            pass_rows(&mut engine, 10..12);
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(30.00));
+            pass_rows(&mut engine, 12..13);
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(103.00));
+            assert_eq!(account.held, dec!(0.00));
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Capture, 1, 2, None)),
+                Err(ProcessingError::HoldNotPending(2))
+            );
+            assert_eq!(engine.summary().expired_holds, 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_keep_hold_without_expiry() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(100.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Hold, 1, 2, Some(dec!(30.00))))
+                .unwrap();
+            pass_rows(&mut engine, 10..20);
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(30.00));
+        }
+
+        #[test]
+        fn should_not_place_hold_exceeding_available() {
+            let mut engine = engine_with_hold(3);
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Hold, 1, 3, Some(dec!(70.01)))),
+                Err(ProcessingError::InsufficientFunds {
+                    client_id: 1,
+                    requested: dec!(70.01),
+                    available: dec!(70.00),
+                })
+            );
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(30.00));
+        }
+
+        #[test]
+        fn should_not_capture_other_transactions() {
+            let mut engine = engine_with_hold(3);
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Capture, 1, 1, None)),
+                Err(ProcessingError::HoldNotPending(1))
+            );
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Capture, 1, 9, None)),
+                Err(ProcessingError::TxNotFound(9))
+            );
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Dispute, 1, 2, None)),
+                Err(ProcessingError::TxNotDisputable(2))
+            );
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Reversal, 1, 2, None)),
+                Err(ProcessingError::TxNotReversible(2))
+            );
+        }
+
+        #[test]
+        fn should_capture_evicted_hold_whose_id_was_reused() {
+            let mut engine = EngineBuilder::new().with_tx_retention(2).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(100.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Hold, 1, 2, Some(dec!(30.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 3, Some(dec!(100.00))))
+                .unwrap();
+            // client 1's hold has been evicted, so its id is free again
+            engine
+                .process_row(&row(TransactionType::Hold, 2, 2, Some(dec!(50.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Capture, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().total, dec!(70.00));
+            assert_eq!(engine.get_account(2).unwrap().held, dec!(50.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_evict_captured_hold() {
+            let mut engine = EngineBuilder::new().with_tx_retention(2).build();
+            for row in [
+                row(TransactionType::Deposit, 1, 1, Some(dec!(100.00))),
+                row(TransactionType::Hold, 1, 2, Some(dec!(30.00))),
+                row(TransactionType::Capture, 1, 2, None),
+                row(TransactionType::Deposit, 1, 3, Some(dec!(1.00))),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            // the captured hold has been evicted, so its id is free again
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().total, dec!(72.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod retention {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::TransactionType,
+        };
+
+        #[test]
+        fn should_not_process_dispute_for_evicted_tx() {
+            let mut engine = EngineBuilder::new().with_tx_retention(2).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 2, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 3, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 2, 3, None))
+                .unwrap();
+            let err = engine
+                .process_row(&row(TransactionType::Dispute, 2, 1, None))
+                .unwrap_err();
+            assert_eq!(err, ProcessingError::TxNotFound(1));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_keep_disputed_tx_past_retention() {
+            let mut engine = EngineBuilder::new().with_tx_retention(2).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 2, 1, None))
+                .unwrap();
+            for tx_id in 2..10 {
+                engine
+                    .process_row(&row(TransactionType::Deposit, 2, tx_id, Some(dec!(10.00))))
+                    .unwrap();
+            }
+            engine
+                .process_row(&row(TransactionType::Resolve, 2, 1, None))
+                .unwrap();
+            let (_, acc) = engine.get_account_iter().next().unwrap();
+            assert_eq!(acc.available(), dec!(90.00));
+            assert_eq!(acc.held(), dec!(0));
+        }
+
+        #[test]
+        fn should_keep_every_tx_without_retention() {
+            let mut engine = Engine::new();
+            for tx_id in 1..10 {
+                engine
+                    .process_row(&row(TransactionType::Deposit, 2, tx_id, Some(dec!(10.00))))
+                    .unwrap();
+            }
+            engine
+                .process_row(&row(TransactionType::Dispute, 2, 1, None))
+                .unwrap();
+        }
+    }
+
+    mod ordering {
+        use rust_decimal_macros::dec;
+
+        use crate::{
+            engine::Engine,
+            models::{TransactionType, TxRow},
+        };
+
+        fn run(clients: &[u16]) -> Vec<u16> {
+            let mut engine = Engine::new();
+            for (tx_id, client_id) in (1..).zip(clients) {
+                engine
+                    .process_row(&TxRow {
+                        tx_type: TransactionType::Deposit,
+                        client_id: *client_id,
+                        tx_id,
+                        amount: Some(dec!(1.00)),
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            engine.get_account_iter().map(|(id, _)| *id).collect()
+        }
+
+        #[test]
+        fn should_iterate_accounts_in_ascending_client_order() {
+            let clients = [42, 7, 65535, 1, 300, 7, 0, 19];
+            let expected = vec![0, 1, 7, 19, 42, 300, 65535];
+            assert_eq!(run(&clients), expected);
+            let mut reversed = clients;
+            reversed.reverse();
+            assert_eq!(run(&reversed), expected);
+        }
+
+        #[test]
+        fn should_iterate_accounts_in_first_seen_order() {
+            let mut engine = Engine::new();
+            for (tx_id, client_id) in (1..).zip([5, 2, 5, 9]) {
+                engine
+                    .process_row(&TxRow {
+                        tx_type: TransactionType::Deposit,
+                        client_id,
+                        tx_id,
+                        amount: Some(dec!(1.00)),
+                        dest: None,
+                        timestamp: None,
+                    })
+                    .unwrap();
+            }
+            // a transfer opens the destination account too
+            engine
+                .process_row(&TxRow {
+                    tx_type: TransactionType::Transfer,
+                    client_id: 9,
+                    tx_id: 5,
+                    amount: Some(dec!(0.50)),
+                    dest: Some(1),
+                    timestamp: None,
+                })
+                .unwrap();
+            let clients: Vec<u16> = engine
+                .get_account_iter_first_seen()
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(clients, [5, 2, 9, 1]);
+            let balances: Vec<_> = engine
+                .get_account_iter_first_seen()
+                .map(|(_, data)| data.available())
+                .collect();
+            assert_eq!(balances, [dec!(2.00), dec!(1.00), dec!(0.50), dec!(0.50)]);
+        }
+    }
+
+    mod row_hooks {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{engine::EngineBuilder, models::TransactionType};
+
+        #[test]
+        fn should_report_every_processed_row_to_hook() {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let failures = Arc::new(AtomicUsize::new(0));
+            let (hook_calls, hook_failures) = (calls.clone(), failures.clone());
+            let mut engine = EngineBuilder::new()
+                .with_row_hook(Box::new(move |_, result| {
+                    hook_calls.fetch_add(1, Ordering::Relaxed);
+                    if result.is_err() {
+                        hook_failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                }))
+                .build();
+            engine.process_batch(&[
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+                row(TransactionType::Withdrawal, 1, 2, Some(dec!(50))),
+                row(TransactionType::Dispute, 1, 1, None),
+                row(TransactionType::Resolve, 1, 1, None),
+            ]);
+            // rows only validated are not reported
+            engine
+                .validate_row(&row(TransactionType::Deposit, 1, 3, Some(dec!(1))))
+                .unwrap();
+            assert_eq!(calls.load(Ordering::Relaxed), 4);
+            assert_eq!(failures.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    mod merging {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, MergeError, ProcessingError},
+            models::TransactionType,
+        };
+
+        fn shard(client_id: u16, first_tx: u32) -> Engine {
+            let mut engine = Engine::new();
+            engine.process_batch(&[
+                row(
+                    TransactionType::Deposit,
+                    client_id,
+                    first_tx,
+                    Some(dec!(10.00)),
+                ),
+                row(
+                    TransactionType::Deposit,
+                    client_id,
+                    first_tx + 1,
+                    Some(dec!(5.00)),
+                ),
+                row(TransactionType::Dispute, client_id, first_tx + 1, None),
+            ]);
+            engine
+        }
+
+        #[test]
+        fn should_merge_engines_with_disjoint_clients() {
+            let mut engine = shard(1, 1);
+            let mut other = shard(2, 3);
+            other
+                .process_row(&row(TransactionType::Chargeback, 2, 4, None))
+                .unwrap();
+            engine.merge(other).unwrap();
+            let clients: Vec<u16> = engine.get_account_iter().map(|(id, _)| *id).collect();
+            assert_eq!(clients, vec![1, 2]);
+            assert!(!engine.is_account_locked(1));
+            assert!(engine.is_account_locked(2));
+            assert_eq!(engine.get_account(2).unwrap().total, dec!(10.00));
+            assert_eq!(engine.summary().total_deposits, dec!(30.00));
+            assert_eq!(engine.summary().chargebacks, 1);
+            assert_eq!(engine.summary().locked_accounts, 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            // the merged txs and locks stay in effect
+            engine
+                .process_row(&row(TransactionType::Resolve, 1, 2, None))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Dispute, 2, 3, None)),
+                Err(ProcessingError::AccountLocked(2))
+            );
+        }
+
+        #[test]
+        fn should_not_merge_engines_sharing_ids() {
+            let mut engine = shard(1, 1);
+            assert_eq!(engine.merge(shard(1, 3)), Err(MergeError::ClientOverlap(1)));
+            assert_eq!(engine.merge(shard(2, 2)), Err(MergeError::TxOverlap(2)));
+            let clients: Vec<u16> = engine.get_account_iter().map(|(id, _)| *id).collect();
+            assert_eq!(clients, vec![1]);
+            assert_eq!(engine.stored_tx_count(), 2);
+            assert_eq!(engine.summary().total_deposits, dec!(15.00));
+        }
+
+        #[test]
+        fn should_not_merge_engines_overflowing_seeded_total() {
+            let mut engine = Engine::new();
+            engine
+                .seed_account(1, Decimal::MAX, Decimal::ZERO, false)
+                .unwrap();
+            let mut other = Engine::new();
+            other
+                .seed_account(2, Decimal::MAX, Decimal::ZERO, false)
+                .unwrap();
+            assert_eq!(engine.merge(other), Err(MergeError::Overflow));
+            assert_eq!(engine.get_account(2), None);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod overflow {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_reject_deposit_overflowing_balance() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(Decimal::MAX)))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(1)))),
+                Err(ProcessingError::AmountOverflow(2))
+            );
+            assert_eq!(engine.get_account(1).unwrap().available, Decimal::MAX);
+            assert_eq!(engine.summary().total_deposits, Decimal::MAX);
+            // the failed deposit was not stored
+            assert_eq!(engine.stored_tx_count(), 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_reject_deposit_overflowing_summary() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(Decimal::MAX)))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Deposit, 3, 2, Some(dec!(1)))),
+                Err(ProcessingError::AmountOverflow(2))
+            );
+            assert!(engine.get_account(3).is_none());
+        }
+
+        #[test]
+        fn should_reject_transfer_overflowing_destination() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(30))))
+                .unwrap();
+            engine
+                .process_row(&row(
+                    TransactionType::Interest,
+                    2,
+                    2,
+                    Some(Decimal::MAX - dec!(20)),
+                ))
+                .unwrap();
+            engine
+                .process_row(&TxRow {
+                    dest: Some(2),
+                    ..row(TransactionType::Transfer, 1, 3, Some(dec!(10)))
+                })
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&TxRow {
+                    dest: Some(2),
+                    ..row(TransactionType::Transfer, 1, 4, Some(dec!(20)))
+                }),
+                Err(ProcessingError::AmountOverflow(4))
+            );
+            // the source keeps its funds
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(20));
+            assert_eq!(
+                engine.get_account(2).unwrap().available,
+                Decimal::MAX - dec!(10)
+            );
+        }
+
+        #[test]
+        fn should_reject_dispute_overflowing_held() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(Decimal::MAX)))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(Decimal::MAX)))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 2, Some(Decimal::MAX)))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Dispute, 1, 1, Some(Decimal::MAX))),
+                Err(ProcessingError::AmountOverflow(1))
+            );
+            assert_eq!(engine.is_tx_disputed(1), Some(false));
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.held, Decimal::MAX);
+        }
+    }
+
+    mod strict_amounts {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::TransactionType,
+        };
+
+        fn engine_with_deposit(strict: bool) -> Engine {
+            let mut engine = EngineBuilder::new().with_strict_amounts(strict).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_ignore_amounts_by_default() {
+            let mut engine = engine_with_deposit(false);
+            for (tx_type, amount) in [
+                (TransactionType::Dispute, None),
+                (TransactionType::Resolve, Some(dec!(99.00))),
+                (TransactionType::Dispute, None),
+                (TransactionType::Chargeback, Some(dec!(99.00))),
+            ] {
+                assert_eq!(engine.process_row(&row(tx_type, 1, 1, amount)), Ok(()));
+            }
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.total, dec!(0.00));
+            assert!(account.locked);
+        }
+
+        #[test]
+        fn should_reject_amounts_when_strict() {
+            let mut engine = engine_with_deposit(true);
+            // a dispute's amount is the part disputed, so it is expected
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            for tx_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+                assert_eq!(
+                    engine.process_row(&row(tx_type, 1, 1, Some(dec!(10.00)))),
+                    Err(ProcessingError::UnexpectedAmount(1))
+                );
+            }
+            assert_eq!(engine.is_tx_disputed(1), Some(true));
+            // the same rows without an amount go through
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Chargeback, 1, 1, None)),
+                Ok(())
+            );
+            assert!(engine.get_account(1).unwrap().locked);
+        }
+    }
+
+    mod seeding {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{AccountSnapshot, BalanceError, TransactionType},
+        };
+
+        #[test]
+        fn should_list_seeded_accounts_without_rows() {
+            let mut engine = Engine::new();
+            engine.seed_account(3, dec!(12.50), dec!(0), false).unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 1, Some(dec!(1))))
+                .unwrap();
+            engine.seed_account(1, dec!(0), dec!(4), true).unwrap();
+
+            let accounts: Vec<_> = engine
+                .get_account_iter()
+                .map(|(id, data)| (*id, data.available(), data.held()))
+                .collect();
+            assert_eq!(
+                accounts,
+                [
+                    (1, dec!(0), dec!(4)),
+                    (2, dec!(1), dec!(0)),
+                    (3, dec!(12.50), dec!(0)),
+                ]
+            );
+            let first_seen: Vec<_> = engine
+                .get_account_iter_first_seen()
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(first_seen, [3, 2, 1]);
+            assert_eq!(engine.locked_accounts(), [1]);
+        }
+
+        #[test]
+        fn should_process_rows_against_seeded_balance() {
+            let mut engine = Engine::new();
+            engine
+                .seed_account(1, dec!(100.00), dec!(5.00), false)
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 1, Some(dec!(30.00))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(70.01)))),
+                Err(ProcessingError::InsufficientFunds {
+                    client_id: 1,
+                    requested: dec!(70.01),
+                    available: dec!(70.00),
+                })
+            );
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 3, Some(dec!(20.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 3, None))
+                .unwrap();
+            assert_eq!(
+                engine.get_account(1),
+                Some(AccountSnapshot {
+                    client_id: 1,
+                    available: dec!(70.00),
+                    held: dec!(25.00),
+                    total: dec!(95.00),
+                    locked: false,
+                })
+            );
+            engine
+                .process_row(&row(TransactionType::Chargeback, 1, 3, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(5.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_seed_locked_account() {
+            let mut engine = Engine::new();
+            engine
+                .seed_account(2, dec!(10.00), Decimal::ZERO, true)
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 2, 1, Some(dec!(1.00)))),
+                Err(ProcessingError::AccountLocked(2))
+            );
+            assert_eq!(engine.locked_accounts(), [2]);
+            // reseeding replaces the balance and lock
+            engine
+                .seed_account(2, dec!(4.00), dec!(1.00), false)
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 2, 2, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.get_account(2).unwrap().total, dec!(4.00));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            // seeding is not a row, so it is not counted as opening an account
+            assert_eq!(engine.summary().new_accounts, 0);
+        }
+
+        #[test]
+        fn should_refuse_seed_overflowing_seeded_total() {
+            let mut engine = Engine::new();
+            engine
+                .seed_account(1, Decimal::MAX, Decimal::ZERO, false)
+                .unwrap();
+            assert_eq!(
+                engine.seed_account(2, dec!(1), Decimal::ZERO, false),
+                Err(BalanceError::Overflow)
+            );
+            assert_eq!(engine.get_account(2), None);
+            assert_eq!(engine.get_account_iter_first_seen().count(), 1);
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod withdrawal_fees {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError, WithdrawalFee},
+            models::TransactionType,
+        };
+
+        fn engine_with_deposit(amount: Decimal) -> Engine {
+            let mut engine = EngineBuilder::new()
+                .with_withdrawal_fee(WithdrawalFee {
+                    bps: 25,
+                    house_client: 9,
+                })
+                .build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(amount)))
+                .unwrap();
+            engine
+        }
+
+        fn available(engine: &Engine, client_id: u16) -> Option<Decimal> {
+            engine
+                .get_account(client_id)
+                .map(|account| account.available)
+        }
+
+        #[test]
+        fn should_charge_fee_to_house_client() {
+            let mut engine = engine_with_deposit(dec!(200.00));
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(100.00))))
+                .unwrap();
+            assert_eq!(available(&engine, 1), Some(dec!(99.75)));
+            assert_eq!(available(&engine, 9), Some(dec!(0.25)));
+            let summary = engine.summary();
+            assert_eq!(summary.total_withdrawals, dec!(100.00));
+            assert_eq!(summary.total_fees, dec!(0.25));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_round_fee_to_precision() {
+            let mut engine = engine_with_deposit(dec!(1.00));
+            // 25 bps of 0.10 is 0.00025, a tie rounded to even
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(0.10))))
+                .unwrap();
+            assert_eq!(available(&engine, 9), Some(dec!(0.0002)));
+            // fees rounding to zero do not open the house account
+            let mut engine = engine_with_deposit(dec!(1.00));
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(0.01))))
+                .unwrap();
+            assert_eq!(available(&engine, 1), Some(dec!(0.99)));
+            assert_eq!(available(&engine, 9), None);
+        }
+
+        #[test]
+        fn should_reject_withdrawal_short_of_fee() {
+            let mut engine = engine_with_deposit(dec!(100.00));
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(100.00)))),
+                Err(ProcessingError::InsufficientFunds {
+                    client_id: 1,
+                    requested: dec!(100.25),
+                    available: dec!(100.00),
+                })
+            );
+            assert_eq!(available(&engine, 1), Some(dec!(100.00)));
+            assert_eq!(available(&engine, 9), None);
+        }
+
+        #[test]
+        fn should_not_return_fee_on_chargeback() {
+            let mut engine = engine_with_deposit(dec!(200.00));
+            for row in [
+                row(TransactionType::Withdrawal, 1, 2, Some(dec!(100.00))),
+                row(TransactionType::Dispute, 1, 2, None),
+                row(TransactionType::Chargeback, 1, 2, None),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            assert_eq!(available(&engine, 1), Some(dec!(199.75)));
+            assert_eq!(available(&engine, 9), Some(dec!(0.25)));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod strict_withdrawals {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        // client 1 deposits, client 2 only receives a transfer
+        fn engine(strict: bool) -> Engine {
+            let mut engine = EngineBuilder::new().with_strict_withdrawals(strict).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&TxRow {
+                    dest: Some(2),
+                    ..row(TransactionType::Transfer, 1, 2, Some(dec!(4.00)))
+                })
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_reject_withdrawal_before_deposit_when_strict() {
+            let mut engine = engine(true);
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 2, 3, Some(dec!(1.00)))),
+                Err(ProcessingError::NoPriorActivity(2))
+            );
+            assert_eq!(engine.get_account(2).unwrap().available, dec!(4.00));
+            // unknown clients and overdrafts keep their own errors
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 3, 4, Some(dec!(1.00)))),
+                Err(ProcessingError::AccountNotFound(3))
+            );
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 5, Some(dec!(7.00)))),
+                Err(ProcessingError::InsufficientFunds {
+                    client_id: 1,
+                    requested: dec!(7.00),
+                    available: dec!(6.00),
+                })
+            );
+            // a deposit makes the client's withdrawals acceptable
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 6, Some(dec!(1.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 2, 7, Some(dec!(5.00))))
+                .unwrap();
+        }
+
+        #[test]
+        fn should_not_dispute_withdrawal_rejected_when_strict() {
+            let mut engine = engine(true);
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 2, 3, Some(dec!(1.00)))),
+                Err(ProcessingError::NoPriorActivity(2))
+            );
+            for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                assert_eq!(
+                    engine.process_row(&row(tx_type, 2, 3, None)),
+                    Err(ProcessingError::TxNotFound(3))
+                );
+            }
+            assert_eq!(engine.get_account(2).unwrap().available, dec!(4.00));
+        }
+
+        #[test]
+        fn should_allow_withdrawal_before_deposit_by_default() {
+            let mut engine = engine(false);
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 2, 3, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.get_account(2).unwrap().available, dec!(3.00));
+        }
+    }
+
+    mod cancellations {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::{EngineSummary, TransactionType},
+        };
+
+        #[test]
+        fn should_not_cancel_refused_withdrawal() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            assert!(matches!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(1000.00)))),
+                Err(ProcessingError::InsufficientFunds { .. })
+            ));
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Cancel, 1, 2, None)),
+                Err(ProcessingError::TxNotFound(2))
+            );
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(10.00));
+            assert_eq!(engine.summary().total_withdrawals, dec!(0));
+            // the deposit is still the latest tx
+            engine
+                .process_row(&row(TransactionType::Cancel, 1, 1, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1), None);
+        }
+
+        #[test]
+        fn should_cancel_fresh_deposit() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Cancel, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(10.00));
+            assert_eq!(engine.is_tx_disputed(2), None);
+            let summary = engine.summary();
+            assert_eq!(summary.total_deposits, dec!(10.00));
+            assert_eq!(summary.cancellations, 1);
+            assert_eq!(engine.client_stats()[&1].deposits, 1);
+            assert!(engine.verify_invariants().is_ok());
+            // the id is free again
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(11.00));
+        }
+
+        #[test]
+        fn should_close_account_opened_by_cancelled_deposit() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            let before = engine.summary();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 2, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Cancel, 2, 2, None))
+                .unwrap();
+            assert_eq!(
+                engine.summary(),
+                EngineSummary {
+                    cancellations: 1,
+                    ..before
+                }
+            );
+            assert_eq!(engine.get_account(2), None);
+            assert_eq!(engine.get_account_iter().count(), 1);
+            assert!(!engine.client_stats().contains_key(&2));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            // the next deposit opens it anew
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 2, Some(dec!(1.00))))
+                .unwrap();
+            assert_eq!(engine.summary().new_accounts, 2);
+        }
+
+        #[test]
+        fn should_only_cancel_latest_unmodified_tx() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(3.00))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Cancel, 1, 1, None)),
+                Err(ProcessingError::TxNotCancellable(1))
+            );
+            engine
+                .process_row(&row(TransactionType::Cancel, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(10.00));
+            assert_eq!(engine.summary().total_withdrawals, dec!(0));
+            // cancelled already, and the deposit before it has not become
+            // cancellable
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Cancel, 1, 2, None)),
+                Err(ProcessingError::TxNotFound(2))
+            );
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Cancel, 1, 1, None)),
+                Err(ProcessingError::TxNotCancellable(1))
+            );
+        }
+
+        #[test]
+        fn should_not_cancel_disputed_tx() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Cancel, 1, 1, None)),
+                Err(ProcessingError::TxAlreadyDisputed(1))
+            );
+            let account = engine.get_account(1).unwrap();
+            assert_eq!((account.available, account.held), (dec!(0), dec!(10.00)));
+            assert_eq!(engine.is_tx_disputed(1), Some(true));
+        }
+    }
+
+    mod id_ranges {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_accept_any_id_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(
+                    TransactionType::Deposit,
+                    u16::MAX,
+                    u32::MAX,
+                    Some(dec!(5.00)),
+                ))
+                .unwrap();
+        }
+
+        #[test]
+        fn should_process_ids_in_range() {
+            let mut engine = EngineBuilder::new()
+                .with_client_id_range(1..=10)
+                .with_tx_id_range(100..=199)
+                .build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 100, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&TxRow {
+                    dest: Some(10),
+                    ..row(TransactionType::Transfer, 1, 199, Some(dec!(5.00)))
+                })
+                .unwrap();
+            assert_eq!(engine.get_account(10).unwrap().available, dec!(5.00));
+        }
+
+        #[test]
+        fn should_reject_ids_out_of_range_before_any_change() {
+            let mut engine = EngineBuilder::new()
+                .with_client_id_range(1..=10)
+                .with_tx_id_range(100..=199)
+                .with_tx_retention(1)
+                .build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 100, Some(dec!(5.00))))
+                .unwrap();
+            for (client_id, tx_id) in [(0, 101), (11, 101), (1, 99)] {
+                assert_eq!(
+                    engine.process_row(&row(
+                        TransactionType::Deposit,
+                        client_id,
+                        tx_id,
+                        Some(dec!(5.00))
+                    )),
+                    Err(ProcessingError::IdOutOfRange(client_id, tx_id))
+                );
+            }
+            assert_eq!(
+                engine.process_row(&TxRow {
+                    dest: Some(11),
+                    ..row(TransactionType::Transfer, 1, 101, Some(dec!(5.00)))
+                }),
+                Err(ProcessingError::IdOutOfRange(1, 101))
+            );
+            // rejected rows do not count towards the retention window
+            assert_eq!(engine.stored_tx_count(), 1);
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(5.00));
+            assert!(engine.get_account(11).is_none());
+        }
+    }
+
+    mod diffs {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::Engine,
+            models::{AccountDiff, TransactionType, TxRow},
+        };
+
+        fn rows() -> Vec<TxRow> {
+            vec![
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+                row(TransactionType::Deposit, 2, 2, Some(dec!(4))),
+                row(TransactionType::Withdrawal, 1, 3, Some(dec!(2.5))),
+                row(TransactionType::Dispute, 2, 2, None),
+                row(TransactionType::Chargeback, 2, 2, None),
+            ]
+        }
+
+        #[test]
+        fn should_not_diff_engines_fed_identical_rows() {
+            let (mut first, mut second) = (Engine::new(), Engine::new());
+            first.process_batch(&rows());
+            second.process_batch(&rows());
+            assert_eq!(first.diff(&second), vec![]);
+        }
+
+        #[test]
+        fn should_not_diff_merged_shards_from_sequential_run() {
+            let mut sequential = Engine::new();
+            sequential.process_batch(&rows());
+            let (mut merged, mut shard) = (Engine::new(), Engine::new());
+            let (ones, twos): (Vec<TxRow>, Vec<TxRow>) =
+                rows().into_iter().partition(|row| row.client_id == 1);
+            merged.process_batch(&ones);
+            shard.process_batch(&twos);
+            merged.merge(shard).unwrap();
+            assert_eq!(merged.diff(&sequential), vec![]);
+        }
+
+        #[test]
+        fn should_diff_differing_fields() {
+            let mut first = Engine::new();
+            first.process_batch(&rows());
+            let mut second = Engine::new();
+            second.process_batch(&rows()[..3]);
+            second
+                .process_row(&row(TransactionType::Deposit, 3, 4, Some(dec!(1))))
+                .unwrap();
+            assert_eq!(
+                first.diff(&second),
+                vec![
+                    AccountDiff {
+                        client_id: 2,
+                        exists: None,
+                        available: Some((Decimal::ZERO, dec!(4))),
+                        held: None,
+                        locked: Some((true, false)),
+                    },
+                    AccountDiff {
+                        client_id: 3,
+                        exists: Some((false, true)),
+                        available: Some((Decimal::ZERO, dec!(1))),
+                        held: None,
+                        locked: None,
+                    },
+                ]
+            );
+        }
+    }
+
+    mod account_limits {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{EngineBuilder, ProcessingError},
+            models::{TransactionType, TxRow},
+        };
+
+        #[test]
+        fn should_reject_deposit_opening_account_beyond_limit() {
+            let mut engine = EngineBuilder::new().with_max_accounts(2).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Deposit, 2, 2, Some(dec!(5.00))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Deposit, 3, 3, Some(dec!(5.00)))),
+                Err(ProcessingError::AccountLimitReached(3))
+            );
+            assert!(engine.get_account(3).is_none());
+            // existing clients are unaffected
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 4, Some(dec!(5.00))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 2, 5, Some(dec!(5.00))))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(10.00));
+            assert_eq!(engine.get_account_iter().count(), 2);
+        }
+
+        #[test]
+        fn should_reject_transfer_opening_account_beyond_limit() {
+            let mut engine = EngineBuilder::new().with_max_accounts(1).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(5.00))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&TxRow {
+                    dest: Some(2),
+                    ..row(TransactionType::Transfer, 1, 2, Some(dec!(5.00)))
+                }),
+                Err(ProcessingError::AccountLimitReached(2))
+            );
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(5.00));
+        }
+    }
+
+    mod idempotency {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, ProcessingError},
+            models::TransactionType,
+        };
+
+        #[test]
+        fn should_ignore_exact_duplicates() {
+            let mut engine = EngineBuilder::new().with_idempotent(true).build();
+            let deposit = row(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+            let withdrawal = row(TransactionType::Withdrawal, 1, 2, Some(dec!(4)));
+            for row in [&deposit, &withdrawal, &deposit, &withdrawal] {
+                assert_eq!(engine.process_row(row), Ok(()));
+            }
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(6));
+            assert_eq!(engine.summary().total_deposits, dec!(10));
+        }
+
+        #[test]
+        fn should_apply_resent_withdrawal_that_failed_before() {
+            let mut engine = EngineBuilder::new().with_idempotent(true).build();
+            let withdrawal = row(TransactionType::Withdrawal, 1, 1, Some(dec!(20)));
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(10))))
+                .unwrap();
+            assert!(matches!(
+                engine.process_row(&withdrawal),
+                Err(ProcessingError::InsufficientFunds { .. })
+            ));
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 3, Some(dec!(100))))
+                .unwrap();
+            // the first attempt was never applied, so this is no duplicate
+            engine.process_row(&withdrawal).unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(90));
+            // but sending it once more is
+            engine.process_row(&withdrawal).unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(90));
+        }
+
+        #[test]
+        fn should_reject_reused_tx_id_with_other_amount() {
+            let mut engine = EngineBuilder::new().with_idempotent(true).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(11)))),
+                Err(ProcessingError::DuplicateTx(1))
+            );
+            // same amount but another type is a conflict too
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Withdrawal, 1, 1, Some(dec!(10)))),
+                Err(ProcessingError::DuplicateTx(1))
+            );
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(10));
+        }
+
+        #[test]
+        fn should_reject_exact_duplicates_by_default() {
+            let mut engine = Engine::new();
+            let deposit = row(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+            engine.process_row(&deposit).unwrap();
+            assert_eq!(
+                engine.process_row(&deposit),
+                Err(ProcessingError::DuplicateTx(1))
+            );
+        }
+    }
+
+    mod fingerprints {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::Engine,
+            models::{TransactionType, TxRow},
+        };
+
+        fn fingerprint(rows: &[TxRow]) -> String {
+            let mut engine = Engine::new();
+            for row in rows {
+                engine.process_row(row).unwrap();
+            }
+            engine.state_fingerprint()
+        }
+
+        #[test]
+        fn should_match_fingerprints_of_equivalent_runs() {
+            let first = [
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+                row(TransactionType::Deposit, 2, 2, Some(dec!(5.50))),
+                row(TransactionType::Dispute, 2, 2, None),
+                row(TransactionType::Chargeback, 2, 2, None),
+                row(TransactionType::Withdrawal, 1, 3, Some(dec!(4))),
+            ];
+            // the same effect per client, interleaved differently
+            let second = [
+                row(TransactionType::Deposit, 2, 2, Some(dec!(5.50))),
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10.000))),
+                row(TransactionType::Dispute, 2, 2, None),
+                row(TransactionType::Withdrawal, 1, 3, Some(dec!(4))),
+                row(TransactionType::Chargeback, 2, 2, None),
+            ];
+            let fingerprint_first = fingerprint(&first);
+            assert_eq!(fingerprint_first.len(), 64);
+            assert_eq!(fingerprint_first, fingerprint(&second));
+        }
+
+        #[test]
+        fn should_tell_apart_fingerprints_of_different_states() {
+            let deposit = row(TransactionType::Deposit, 1, 1, Some(dec!(10)));
+            let available = fingerprint(&[deposit]);
+            assert_ne!(
+                available,
+                fingerprint(&[row(TransactionType::Deposit, 1, 1, Some(dec!(10.01)))])
+            );
+            // the same total, but held
+            assert_ne!(
+                available,
+                fingerprint(&[deposit, row(TransactionType::Dispute, 1, 1, None)])
+            );
+            assert_eq!(
+                Engine::new().state_fingerprint(),
+                Engine::new().state_fingerprint()
+            );
+        }
+    }
+
+    mod undo {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, UndoError},
+            models::TransactionType,
+        };
+
+        #[test]
+        fn should_undo_deposit() {
+            let mut engine = EngineBuilder::new().with_undo(10).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            let before = engine.state_fingerprint();
+            let deposit = row(TransactionType::Deposit, 1, 2, Some(dec!(5)));
+            engine.process_row(&deposit).unwrap();
+            engine.undo().unwrap();
+            assert_eq!(engine.state_fingerprint(), before);
+            assert_eq!(engine.summary().total_deposits, dec!(10));
+            // the tx id is free again
+            engine.process_row(&deposit).unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(15));
+        }
+
+        #[test]
+        fn should_undo_dispute() {
+            let mut engine = EngineBuilder::new().with_undo(10).build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            let before = engine.state_fingerprint();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine.undo().unwrap();
+            assert_eq!(engine.state_fingerprint(), before);
+            assert_eq!(engine.is_tx_disputed(1), Some(false));
+        }
+
+        #[test]
+        fn should_unlock_on_undoing_chargeback() {
+            let mut engine = EngineBuilder::new().with_undo(10).build();
+            for row in [
+                row(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+                row(TransactionType::Dispute, 1, 1, None),
+                row(TransactionType::Chargeback, 1, 1, None),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            assert!(engine.is_account_locked(1));
+            engine.undo().unwrap();
+            assert!(!engine.is_account_locked(1));
+            assert_eq!(engine.is_tx_disputed(1), Some(true));
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+        }
+
+        #[test]
+        fn should_skip_failed_rows_and_keep_depth() {
+            let mut engine = EngineBuilder::new().with_undo(2).build();
+            for tx_id in 1..=3 {
+                engine
+                    .process_row(&row(TransactionType::Deposit, 1, tx_id, Some(dec!(1))))
+                    .unwrap();
+            }
+            engine
+                .process_row(&row(TransactionType::Withdrawal, 1, 4, Some(dec!(100))))
+                .unwrap_err();
+            engine.undo().unwrap();
+            engine.undo().unwrap();
+            assert_eq!(engine.undo(), Err(UndoError::NothingToUndo));
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(1));
+            assert_eq!(Engine::new().undo(), Err(UndoError::NotEnabled));
+        }
+    }
+
+    mod partial_disputes {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, ProcessingError},
+            models::TransactionType,
+        };
+
+        fn engine_with_deposit() -> Engine {
+            let mut engine = Engine::new();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(100))))
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_hold_disputed_part_only() {
+            let mut engine = engine_with_deposit();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, Some(dec!(30))))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(70));
+            assert_eq!(account.held, dec!(30));
+            engine
+                .process_row(&row(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(100));
+            assert_eq!(account.held, dec!(0));
+            // once resolved the tx may be disputed again, in full
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(100));
+        }
+
+        #[test]
+        fn should_charge_back_disputed_part_only() {
+            let mut engine = engine_with_deposit();
+            for row in [
+                row(TransactionType::Dispute, 1, 1, Some(dec!(30))),
+                row(TransactionType::Chargeback, 1, 1, None),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(70));
+            assert_eq!(account.held, dec!(0));
+            assert_eq!(account.total, dec!(70));
+            assert!(account.locked);
+            assert_eq!(engine.summary().total_charged_back, dec!(30));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn should_reject_dispute_beyond_tx_amount() {
+            let mut engine = engine_with_deposit();
+            for amount in [dec!(100.01), dec!(0), dec!(-5)] {
+                assert_eq!(
+                    engine.process_row(&row(TransactionType::Dispute, 1, 1, Some(amount))),
+                    Err(ProcessingError::InvalidDisputeAmount(1))
+                );
+            }
+            assert_eq!(engine.is_tx_disputed(1), Some(false));
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(100));
+            // the full amount is fine
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, Some(dec!(100))))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(100));
+        }
+
+        #[test]
+        fn should_hold_part_of_disputed_withdrawal() {
+            let mut engine = engine_with_deposit();
+            for row in [
+                row(TransactionType::Withdrawal, 1, 2, Some(dec!(40))),
+                row(TransactionType::Dispute, 1, 2, Some(dec!(10))),
+            ] {
+                engine.process_row(&row).unwrap();
+            }
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.available, dec!(60));
+            assert_eq!(account.held, dec!(10));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            engine
+                .process_row(&row(TransactionType::Chargeback, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(70));
+            assert_eq!(engine.summary().total_returned, dec!(10));
+            assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    mod held_underflow {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{Engine, EngineBuilder, HeldUnderflowPolicy, ProcessingError},
+            models::TransactionType,
+        };
+
+        // disputes a deposit of 10, then lowers held funds to 4 behind the
+        // dispute's back
+        fn underheld_engine(policy: HeldUnderflowPolicy) -> Engine {
+            let mut engine = EngineBuilder::new()
+                .with_held_underflow_policy(policy)
+                .build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+                .unwrap();
+            engine
+                .process_row(&row(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine.seed_account(1, dec!(0), dec!(4), false).unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_reject_resolving_more_than_held_by_default() {
+            let mut engine = underheld_engine(HeldUnderflowPolicy::default());
+            assert_eq!(
+                engine.process_row(&row(TransactionType::Resolve, 1, 1, None)),
+                Err(ProcessingError::HeldUnderflow(1, 1))
+            );
+            assert_eq!(engine.get_account(1).unwrap().held, dec!(4));
+            assert_eq!(engine.is_tx_disputed(1), Some(true));
+        }
+
+        #[test]
+        fn should_clamp_held_at_zero_when_saturating() {
+            let mut engine = underheld_engine(HeldUnderflowPolicy::Saturate);
+            engine
+                .process_row(&row(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.held, dec!(0));
+            assert_eq!(account.available, dec!(4));
+            assert_eq!(engine.is_tx_disputed(1), Some(false));
+        }
+
+        #[test]
+        fn should_charge_back_only_what_is_held_when_saturating() {
+            let mut engine = underheld_engine(HeldUnderflowPolicy::Saturate);
+            engine
+                .process_row(&row(TransactionType::Chargeback, 1, 1, None))
+                .unwrap();
+            let account = engine.get_account(1).unwrap();
+            assert_eq!(account.held, dec!(0));
+            assert_eq!(account.total, dec!(0));
+            assert!(account.locked);
+            assert_eq!(engine.summary().total_charged_back, dec!(4));
+        }
+    }
+
+    mod guards {
+        use rust_decimal_macros::dec;
+
+        use super::row;
+        use crate::{
+            engine::{EngineBuilder, ProcessingError},
+            models::TransactionType,
+        };
+
+        #[test]
+        fn should_refuse_rows_rejected_by_guard() {
+            let mut engine = EngineBuilder::new()
+                .with_guard(Box::new(|row| {
+                    row.tx_type != TransactionType::Deposit
+                        || row.amount.is_none_or(|amount| amount <= dec!(1000))
+                }))
+                .build();
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 1, Some(dec!(1000))))
+                .unwrap();
+            let large = row(TransactionType::Deposit, 1, 2, Some(dec!(1000.01)));
+            assert_eq!(
+                engine.validate_row(&large),
+                Err(ProcessingError::RejectedByGuard(2))
+            );
+            assert_eq!(
+                engine.process_row(&large),
+                Err(ProcessingError::RejectedByGuard(2))
+            );
+            assert_eq!(engine.get_account(1).unwrap().available, dec!(1000));
+            // the id of the rejected row is still free
+            engine
+                .process_row(&row(TransactionType::Deposit, 1, 2, Some(dec!(5))))
+                .unwrap();
+        }
     }
 }
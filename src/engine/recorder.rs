@@ -0,0 +1,119 @@
+use std::{fmt, io::Write};
+
+use chrono::{DateTime, FixedOffset};
+
+/// Writes every row handed to the engine back out as CSV in the input format,
+/// errored ones included, so that a run can be replayed. Clones do not record,
+/// so scratch copies of an engine (see `Engine::validate_row`) leave the
+/// recording alone.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    writer: Option<csv::Writer<Box<dyn Write + Send>>>,
+}
+
+impl Recorder {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        let mut recorder = Self {
+            writer: Some(
+                csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(writer),
+            ),
+        };
+        recorder.write(["type", "client", "tx", "amount", "dest", "timestamp"].map(String::from));
+        recorder
+    }
+
+    pub(crate) fn record<C: fmt::Display, T: fmt::Display>(
+        &mut self,
+        tx_type: &str,
+        client_id: C,
+        tx_id: T,
+        amount: Option<&str>,
+        dest: Option<C>,
+        timestamp: Option<DateTime<FixedOffset>>,
+    ) {
+        if self.writer.is_none() {
+            return;
+        }
+        self.write([
+            tx_type.to_string(),
+            client_id.to_string(),
+            tx_id.to_string(),
+            amount.unwrap_or_default().to_string(),
+            dest.map(|x| x.to_string()).unwrap_or_default(),
+            timestamp.map(|x| x.to_rfc3339()).unwrap_or_default(),
+        ]);
+    }
+
+    // flushes every row so that the recording survives an aborted run
+    fn write(&mut self, record: [String; 6]) {
+        if let Some(writer) = self.writer.as_mut() {
+            let result = writer
+                .write_record(&record)
+                .and_then(|_| writer.flush().map_err(csv::Error::from));
+            if let Err(err) = result {
+                log::warn!("failed to record row: {}", err);
+            }
+        }
+    }
+}
+
+impl Clone for Recorder {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("recording", &self.writer.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::DateTime;
+
+    use super::Recorder;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_record_rows_in_input_format() {
+        let buffer = SharedBuffer::default();
+        let mut recorder = Recorder::new(Box::new(buffer.clone()));
+        let timestamp = DateTime::parse_from_rfc3339("2024-01-01T12:00:00+01:00").unwrap();
+        recorder.record("deposit", 1, 2, Some("1.50"), None, Some(timestamp));
+        recorder.record("transfer", 1, 3, Some("1"), Some(4), None);
+        recorder.record("dispute", 1, 2, None, None, None);
+        // clones do not record
+        recorder.clone().record("resolve", 1, 2, None, None, None);
+        let recording = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            recording,
+            "type,client,tx,amount,dest,timestamp\n\
+             deposit,1,2,1.50,,2024-01-01T12:00:00+01:00\n\
+             transfer,1,3,1,4,\n\
+             dispute,1,2,,,\n"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use futures::{Stream, StreamExt};
+
+use super::{Engine, RowOutcome};
+use crate::models::{ClientId, TxId, TxRow};
+
+impl<C: ClientId, T: TxId> Engine<C, T> {
+    /// Processes rows as `rows` yields them, e.g. from a message queue
+    /// consumer, pairing each with its outcome like `process_batch`. Nothing
+    /// is processed until the returned stream is polled, and a failing row
+    /// does not stop the rows after it.
+    pub fn process_stream<'a, S>(&'a mut self, rows: S) -> impl Stream<Item = RowOutcome<C, T>> + 'a
+    where
+        S: Stream<Item = TxRow<C, T>> + 'a,
+    {
+        rows.map(move |row| {
+            let result = self.process_row(&row);
+            (row, result)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, stream, StreamExt};
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        engine::{Engine, ProcessingError},
+        models::{TransactionType, TxRow},
+    };
+
+    fn row(tx_type: TransactionType, tx_id: u32, amount: Option<&str>) -> TxRow {
+        TxRow {
+            tx_type,
+            client_id: 1,
+            tx_id,
+            amount: amount.map(|x| x.parse().unwrap()),
+            dest: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn should_process_rows_from_stream() {
+        let rows = stream::iter([
+            row(TransactionType::Deposit, 1, Some("10")),
+            row(TransactionType::Withdrawal, 2, Some("50")),
+            row(TransactionType::Dispute, 1, None),
+        ]);
+        let mut engine = Engine::new();
+        let results: Vec<_> = block_on(
+            engine
+                .process_stream(rows)
+                .map(|(row, result)| (row.tx_id, result))
+                .collect(),
+        );
+        assert_eq!(
+            results,
+            [
+                (1, Ok(())),
+                (
+                    2,
+                    Err(ProcessingError::InsufficientFunds {
+                        client_id: 1,
+                        requested: dec!(50),
+                        available: dec!(10),
+                    })
+                ),
+                (1, Ok(())),
+            ]
+        );
+        assert_eq!(engine.get_account(1).unwrap().held, dec!(10));
+    }
+}
@@ -0,0 +1,11 @@
+extern crate serde;
+extern crate serde_derive;
+
+pub mod cli;
+pub mod engine;
+pub mod logging;
+pub mod models;
+pub mod pipeline;
+#[cfg(feature = "xml")]
+pub mod sources;
+pub mod stores;
@@ -0,0 +1,140 @@
+use std::{error, fmt, io};
+
+use log::{
+    kv::{self, Key, VisitSource},
+    LevelFilter, Record, SetLoggerError,
+};
+use serde_json::{Map, Value};
+
+use crate::cli::{LogDestination, LogFormat};
+
+#[derive(Debug)]
+pub enum LogInitError {
+    /// The log file could not be opened.
+    Io(io::Error),
+    /// A logger has already been installed.
+    AlreadySet(SetLoggerError),
+}
+
+impl fmt::Display for LogInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogInitError::Io(err) => write!(f, "could not open log file: {}", err),
+            LogInitError::AlreadySet(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for LogInitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LogInitError::Io(err) => Some(err),
+            LogInitError::AlreadySet(err) => Some(err),
+        }
+    }
+}
+
+/// Installs the global logger, writing records of `level` and above to
+/// `destination` in `format`. JSON lines carry `timestamp`, `level`, `target`
+/// and `message` fields, along with any key-values of the record such as
+/// `client_id` and `tx_id`. Fails rather than panics if the log file cannot be
+/// opened or a logger is already installed, leaving it to the caller whether
+/// to go on without logging.
+pub fn init_logging(
+    level: LevelFilter,
+    destination: &LogDestination,
+    format: LogFormat,
+) -> Result<(), LogInitError> {
+    let dispatch = match format {
+        LogFormat::Text => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        }),
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!("{}", json_line(message, record)))
+        }),
+    }
+    .level(level);
+    match destination {
+        LogDestination::Stderr => dispatch.chain(io::stderr()),
+        LogDestination::File(path) => {
+            dispatch.chain(fern::log_file(path).map_err(LogInitError::Io)?)
+        }
+    }
+    .apply()
+    .map_err(LogInitError::AlreadySet)
+}
+
+fn json_line(message: &fmt::Arguments, record: &Record) -> Value {
+    let mut fields = Map::new();
+    fields.insert("timestamp".into(), chrono::Local::now().to_rfc3339().into());
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), message.to_string().into());
+    // a record's key-values cannot fail to be visited here
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    Value::Object(fields)
+}
+
+// adds key-values to the JSON fields, numbers as numbers and others as text
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = match value.to_u64() {
+            Some(number) => number.into(),
+            None => value.to_string().into(),
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, process};
+
+    use log::{Level, LevelFilter, Record};
+    use serde_json::Value;
+
+    use super::{init_logging, json_line, LogInitError};
+    use crate::cli::{LogDestination, LogFormat};
+
+    #[test]
+    fn should_fail_to_log_to_unwritable_path() {
+        let path: PathBuf = env::temp_dir()
+            .join(format!("payments_engine_missing_{}", process::id()))
+            .join("output.log");
+        let result = init_logging(
+            LevelFilter::Info,
+            &LogDestination::File(path),
+            LogFormat::Text,
+        );
+        assert!(matches!(result, Err(LogInitError::Io(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn should_log_error_as_json_line() {
+        let context: &[(&str, u64)] = &[("client_id", 2), ("tx_id", 7)];
+        let message = format_args!("tx {} does not exist", 7);
+        let record = Record::builder()
+            .level(Level::Error)
+            .target("payments_engine::pipeline")
+            .key_values(&context)
+            .build();
+        let line = json_line(&message, &record).to_string();
+        let fields: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(fields["level"], "ERROR");
+        assert_eq!(fields["target"], "payments_engine::pipeline");
+        assert_eq!(fields["message"], "tx 7 does not exist");
+        assert_eq!(fields["client_id"], 2);
+        assert_eq!(fields["tx_id"], 7);
+        let timestamp = fields["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    }
+}
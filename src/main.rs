@@ -1,72 +1,66 @@
-use std::env;
+use std::{
+    env,
+    fs::File,
+    io::{self, Read},
+    process,
+    sync::atomic::AtomicBool,
+};
 
-use engine::Engine;
-use models::TxRow;
-
-extern crate serde;
-extern crate serde_derive;
-
-mod engine;
-mod models;
-mod stores;
+use payments_engine::{
+    cli::Args,
+    logging,
+    pipeline::{self, PipelineError},
+};
 
 fn main() {
-    // set logger
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Debug)
-        .chain(fern::log_file("output.log").unwrap())
-        .apply()
-        .unwrap();
+    let args = Args::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
 
-    // read transactions
-    let filepath = env::args()
-        .nth(1)
-        .expect("filepath is missing from arguments");
-
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(filepath)
-        .expect("could not read file");
+    // processing does not depend on logs, so carry on without them
+    if let Err(err) = logging::init_logging(args.log_level, &args.log_destination, args.log_format)
+    {
+        eprintln!("{}; continuing without logging", err);
+    }
 
-    // process transactions
-    let mut engine = Engine::new();
-    let result_iter = reader
-        .deserialize::<TxRow>()
-        .map(|x| x.expect("error reading file"))
-        .map(|x| engine.process_row(&x));
+    // read transactions from the file, or stdin given -
+    let input: Box<dyn Read> = match args.filepath.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        path => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("could not read {}: {}", path, err);
+                process::exit(2);
+            }
+        },
+    };
 
-    for result in result_iter {
-        // log errors
-        if result.is_err() {
-            log::error!("{}", result.unwrap_err());
+    if let Err(err) = pipeline::run_until(input, io::stdout().lock(), &args, stop_flag()) {
+        eprintln!("{}", err);
+        if let PipelineError::TooManyErrors(counts) = err {
+            for (name, count) in counts {
+                eprintln!("{}: {}", name, count);
+            }
         }
+        process::exit(1);
     }
+}
 
-    // write transactions to stdout
-    let mut writer = csv::WriterBuilder::new().from_writer(std::io::stdout());
-    writer
-        .write_record(&["client", "total", "available", "held", "locked"])
-        .expect("filed to write to file");
+// set on Ctrl-C when built with the interrupt feature, so that the accounts
+// processed so far are still written
+static STOP: AtomicBool = AtomicBool::new(false);
 
-    let account_iter = engine.get_account_iter();
-    for (id, data) in account_iter {
-        writer
-            .write_record(&[
-                id.to_string(),
-                (data.available + data.held).round_dp(4).to_string(),
-                data.available.round_dp(4).to_string(),
-                data.held.round_dp(4).to_string(),
-                engine.is_account_locked(*id).to_string(),
-            ])
-            .expect("failed to write to file");
+#[cfg(feature = "interrupt")]
+fn stop_flag() -> &'static AtomicBool {
+    if let Err(err) = ctrlc::set_handler(|| STOP.store(true, std::sync::atomic::Ordering::Relaxed))
+    {
+        eprintln!("{}; Ctrl-C will not write partial results", err);
     }
+    &STOP
+}
+
+#[cfg(not(feature = "interrupt"))]
+fn stop_flag() -> &'static AtomicBool {
+    &STOP
 }
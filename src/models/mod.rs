@@ -1,7 +1,25 @@
+use std::{fmt, hash::Hash, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{
+    de::{self, value::StrDeserializer, IntoDeserializer},
+    Deserialize, Deserializer, Serialize,
+};
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+/// Identifies a client. Implemented for every type with the required bounds,
+/// e.g. `u16` (the default) or `u64`.
+pub trait ClientId: Copy + Eq + Hash + Ord + fmt::Debug + fmt::Display {}
+
+impl<C: Copy + Eq + Hash + Ord + fmt::Debug + fmt::Display> ClientId for C {}
+
+/// Identifies a transaction. Implemented for every type with the required
+/// bounds, e.g. `u32` (the default) or `u128` for UUID-like ids.
+pub trait TxId: Copy + Eq + Hash + Ord + fmt::Debug + fmt::Display {}
+
+impl<T: Copy + Eq + Hash + Ord + fmt::Debug + fmt::Display> TxId for T {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -9,27 +27,736 @@ pub enum TransactionType {
     Resolve,
     Dispute,
     Chargeback,
+    Reversal,
+    Interest,
+    Transfer,
+    Hold,
+    Capture,
+    Cancel,
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Interest => "interest",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Hold => "hold",
+            TransactionType::Capture => "capture",
+            TransactionType::Cancel => "cancel",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize)]
-pub struct TxRow {
+pub struct TxRow<C = u16, T = u32> {
     #[serde(rename(deserialize = "type"))]
     pub tx_type: TransactionType,
     #[serde(rename(deserialize = "client"))]
-    pub client_id: u16,
+    pub client_id: C,
     #[serde(rename(deserialize = "tx"))]
-    pub tx_id: u32,
+    pub tx_id: T,
+    #[serde(default, deserialize_with = "deserialize_amount")]
     pub amount: Option<Decimal>,
+    // client credited by a transfer; unused by other types
+    #[serde(default)]
+    pub dest: Option<C>,
+    // when the transaction happened, as RFC 3339 in the input
+    #[serde(default)]
+    pub timestamp: Option<DateTime<FixedOffset>>,
+}
+
+/// A row as read from the input, before its type and amount are checked, so
+/// that a row with an unknown type or an amount that does not fit a `Decimal`
+/// can be rejected on its own instead of failing the read.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RawTxRow<C = u16, T = u32> {
+    #[serde(rename(deserialize = "type"))]
+    pub tx_type: String,
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: C,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: T,
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub dest: Option<C>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<FixedOffset>>,
+}
+
+/// Why a `RawTxRow` is not a valid `TxRow`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RowError {
+    // the type is not a known TransactionType
+    UnknownType,
+    // the amount is not a number, or too large or precise for a Decimal
+    UnparseableAmount,
+}
+
+impl<C: Copy, T: Copy> RawTxRow<C, T> {
+    /// Returns the typed row, checking the type before the amount.
+    pub fn to_row(&self) -> Result<TxRow<C, T>, RowError> {
+        let deserializer: StrDeserializer<'_, serde::de::value::Error> =
+            self.tx_type.as_str().into_deserializer();
+        let tx_type =
+            TransactionType::deserialize(deserializer).map_err(|_| RowError::UnknownType)?;
+        let amount = self
+            .amount
+            .as_deref()
+            .map(|amount| parse_amount(amount).ok_or(RowError::UnparseableAmount))
+            .transpose()?;
+        Ok(TxRow {
+            tx_type,
+            client_id: self.client_id,
+            tx_id: self.tx_id,
+            amount,
+            dest: self.dest,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+/// Parses an amount in plain or scientific notation without trailing zeros,
+/// so `10.0` reads as `10`. Amounts never go by way of an f64, so every digit
+/// a `Decimal` can hold is kept.
+fn parse_amount(amount: &str) -> Option<Decimal> {
+    Decimal::from_str(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map(|amount| amount.normalize())
+        .ok()
+}
+
+// csv hands a plain Decimal an f64 for any numeric field, losing digits
+// beyond its precision, so amounts are read from their text instead
+fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|amount| {
+            parse_amount(&amount)
+                .ok_or_else(|| de::Error::custom(format!("invalid amount {:?}", amount)))
+        })
+        .transpose()
+}
+
+/// Largest scale a minor-unit amount may be read with, as `Decimal` supports
+/// at most 28 decimal places.
+pub const MAX_MINOR_UNIT_SCALE: u32 = 28;
+
+/// A row whose amount is an integer count of minor units, e.g. cents, as sent
+/// by systems that avoid decimals altogether. The amount column may be named
+/// `amount` or `amount_cents`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MinorUnitsTxRow<C = u16, T = u32> {
+    #[serde(rename(deserialize = "type"))]
+    pub tx_type: String,
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: C,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: T,
+    #[serde(alias = "amount_cents")]
+    pub amount: Option<i64>,
+    #[serde(default)]
+    pub dest: Option<C>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<FixedOffset>>,
+}
+
+impl<C, T> MinorUnitsTxRow<C, T> {
+    /// Converts the amount to a decimal with `scale` places, so that with a
+    /// scale of 2 an amount of 1234 becomes 12.34.
+    ///
+    /// # Panics
+    ///
+    /// If `scale` exceeds `MAX_MINOR_UNIT_SCALE`.
+    pub fn into_raw_row(self, scale: u32) -> RawTxRow<C, T> {
+        RawTxRow {
+            tx_type: self.tx_type,
+            client_id: self.client_id,
+            tx_id: self.tx_id,
+            amount: self
+                .amount
+                .map(|amount| Decimal::new(amount, scale).to_string()),
+            dest: self.dest,
+            timestamp: self.timestamp,
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// A client's balances. They only change through the methods below, which
+/// keep the total in step with available and held funds.
+// rust_decimal serializes as a string, so amounts keep their exact precision
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
 pub struct AccountData {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    // most held at any point, kept when held funds are released
+    peak_held: Decimal,
+}
+
+impl AccountData {
+    /// An account with the given balances, failing if their total does not
+    /// fit a `Decimal`.
+    pub fn new(available: Decimal, held: Decimal) -> Result<Self, BalanceError> {
+        Ok(Self {
+            available,
+            held,
+            total: checked(available.checked_add(held))?,
+            peak_held: held.max(Decimal::ZERO),
+        })
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    /// Available plus held funds.
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
+    /// The most funds held at once so far.
+    pub fn peak_held(&self) -> Decimal {
+        self.peak_held
+    }
+
+    /// Credits `amount` to available funds.
+    pub fn deposit(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        let total = checked(self.total.checked_add(amount))?;
+        self.available = checked(self.available.checked_add(amount))?;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Debits `amount` from available funds, refusing to overdraw.
+    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        if self.available < amount {
+            return Err(BalanceError::InsufficientAvailable);
+        }
+        let total = checked(self.total.checked_sub(amount))?;
+        self.available = checked(self.available.checked_sub(amount))?;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Moves `amount` from available to held funds. Available may go negative
+    /// if the funds were already spent; whether that is acceptable is up to
+    /// the caller.
+    pub fn apply_hold(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        let available = checked(self.available.checked_sub(amount))?;
+        self.held = checked(self.held.checked_add(amount))?;
+        self.available = available;
+        self.peak_held = self.peak_held.max(self.held);
+        Ok(())
+    }
+
+    /// Moves `amount` from held back to available funds.
+    pub fn release_hold(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        if self.held < amount {
+            return Err(BalanceError::InsufficientHeld);
+        }
+        let held = checked(self.held.checked_sub(amount))?;
+        self.available = checked(self.available.checked_add(amount))?;
+        self.held = held;
+        Ok(())
+    }
+
+    /// Holds `amount` that is not part of available funds, e.g. a disputed
+    /// withdrawal pending its return.
+    pub fn add_held(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        let total = checked(self.total.checked_add(amount))?;
+        self.held = checked(self.held.checked_add(amount))?;
+        self.total = total;
+        self.peak_held = self.peak_held.max(self.held);
+        Ok(())
+    }
+
+    /// Removes `amount` of held funds from the account altogether.
+    pub fn remove_held(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        if self.held < amount {
+            return Err(BalanceError::InsufficientHeld);
+        }
+        let total = checked(self.total.checked_sub(amount))?;
+        self.held = checked(self.held.checked_sub(amount))?;
+        self.total = total;
+        Ok(())
+    }
+}
+
+fn checked(result: Option<Decimal>) -> Result<Decimal, BalanceError> {
+    result.ok_or(BalanceError::Overflow)
+}
+
+/// Why a balance change was refused. The balance is left as it was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceError {
+    InsufficientAvailable,
+    InsufficientHeld,
+    /// The result does not fit a `Decimal`.
+    Overflow,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    #[default]
+    Normal,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Reversed,
+    /// A hold whose funds have been captured.
+    Captured,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Transaction<C = u16> {
+    pub client_id: C,
+    pub tx_type: TransactionType,
+    pub amount: Decimal,
+    pub state: TxState,
+    /// The part of `amount` held by the current or charged back dispute,
+    /// zero otherwise.
+    #[serde(default)]
+    pub disputed_amount: Decimal,
+}
+
+impl<C> Transaction<C> {
+    /// How much the tx has added to the sum of all balances in its current
+    /// state, negative if it has taken funds out. Disputes only move funds
+    /// between available and held, except that a disputed withdrawal holds
+    /// the disputed part as a pending return.
+    pub fn balance_effect(&self) -> Decimal {
+        match (self.tx_type, self.state) {
+            (_, TxState::Reversed) => Decimal::ZERO,
+            (TransactionType::Deposit, TxState::ChargedBack) => self.amount - self.disputed_amount,
+            (TransactionType::Deposit | TransactionType::Interest, _) => self.amount,
+            (TransactionType::Withdrawal, TxState::Disputed | TxState::ChargedBack) => {
+                self.disputed_amount - self.amount
+            }
+            (TransactionType::Withdrawal, _) => -self.amount,
+            (TransactionType::Hold, TxState::Captured) => -self.amount,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// Whether tx ids are unique across the whole input or only within a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TxIdScope {
+    #[default]
+    Global,
+    PerClient,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EngineSummary {
+    pub total_deposits: Decimal,
+    pub total_withdrawals: Decimal,
+    // deposits removed by chargebacks
+    pub total_charged_back: Decimal,
+    // withdrawals credited back by chargebacks
+    pub total_returned: Decimal,
+    pub total_interest: Decimal,
+    // held funds taken out of accounts by captures
+    pub total_captured: Decimal,
+    // withdrawal fees moved to the house client
+    pub total_fees: Decimal,
+    pub disputes: usize,
+    pub resolves: usize,
+    pub chargebacks: usize,
+    pub reversals: usize,
+    // transactions cancelled right after being read
+    pub cancellations: usize,
+    pub interest_credits: usize,
+    pub transfers: usize,
+    pub holds: usize,
+    pub captures: usize,
+    // holds released back to available funds uncaptured
+    pub expired_holds: usize,
+    // accounts opened by the first row crediting a client
+    pub new_accounts: usize,
+    pub locked_accounts: usize,
+}
+
+impl EngineSummary {
+    /// Adds the totals and counts of `other` to these.
+    pub fn merge(&mut self, other: &EngineSummary) {
+        self.total_deposits += other.total_deposits;
+        self.total_withdrawals += other.total_withdrawals;
+        self.total_charged_back += other.total_charged_back;
+        self.total_returned += other.total_returned;
+        self.total_interest += other.total_interest;
+        self.total_captured += other.total_captured;
+        self.total_fees += other.total_fees;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.reversals += other.reversals;
+        self.cancellations += other.cancellations;
+        self.interest_credits += other.interest_credits;
+        self.transfers += other.transfers;
+        self.holds += other.holds;
+        self.captures += other.captures;
+        self.expired_holds += other.expired_holds;
+        self.new_accounts += other.new_accounts;
+        self.locked_accounts += other.locked_accounts;
+    }
+}
+
+/// A client's balance at one point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountSnapshot<C = u16> {
+    pub client_id: C,
     pub available: Decimal,
     pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Transaction {
+/// Where a client's account differs between two engines, see `Engine::diff`.
+/// Each field holds the (this engine, other engine) values if they differ. A
+/// missing account counts as an unlocked one without funds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountDiff<C = u16> {
+    pub client_id: C,
+    pub exists: Option<(bool, bool)>,
+    pub available: Option<(Decimal, Decimal)>,
+    pub held: Option<(Decimal, Decimal)>,
+    pub locked: Option<(bool, bool)>,
+}
+
+/// A stored transaction along with its id, see `Engine::export_transactions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TransactionSnapshot<C = u16, T = u32> {
+    pub tx_id: T,
+    pub client_id: C,
+    pub tx_type: TransactionType,
     pub amount: Decimal,
-    pub disputed: bool,
+    pub state: TxState,
+}
+
+/// One balance transition of a client caused by a processed row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditEntry<C = u16, T = u32> {
+    pub tx_id: T,
+    pub client_id: C,
+    pub kind: TransactionType,
+    pub available_before: Decimal,
+    pub available_after: Decimal,
+    pub held_before: Decimal,
+    pub held_after: Decimal,
+}
+
+/// Something noteworthy that happened to an account while processing a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EngineEvent<C = u16> {
+    /// A deposit brought the client's available funds from below zero back
+    /// to zero or more.
+    AccountRecoveredFromNegative(C),
+    /// A deposit to a locked account left it without held funds, disputes or
+    /// debt, so the lock was lifted; see `EngineBuilder::with_auto_unlock`.
+    AccountUnlocked(C),
+}
+
+/// Per-client breakdown of processed rows along with the client's balance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClientStats {
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes: usize,
+    pub locked: bool,
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{
+        AccountData, BalanceError, MinorUnitsTxRow, RawTxRow, RowError, Transaction,
+        TransactionSnapshot, TransactionType, TxRow, TxState,
+    };
+
+    fn raw_row(tx_type: &str) -> RawTxRow {
+        RawTxRow {
+            tx_type: tx_type.to_string(),
+            client_id: 1,
+            tx_id: 2,
+            amount: Some("3.00".to_string()),
+            dest: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn should_display_types_as_they_are_read() {
+        let types = [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+            TransactionType::Reversal,
+            TransactionType::Interest,
+            TransactionType::Transfer,
+            TransactionType::Hold,
+            TransactionType::Capture,
+            TransactionType::Cancel,
+        ];
+        for tx_type in types {
+            let row = raw_row(&tx_type.to_string()).to_row().unwrap();
+            assert_eq!(row.tx_type, tx_type);
+        }
+    }
+
+    #[test]
+    fn should_convert_raw_row_with_known_type() {
+        let row = raw_row("withdrawal").to_row().unwrap();
+        assert_eq!(row.tx_type, TransactionType::Withdrawal);
+        assert_eq!(row.client_id, 1);
+        assert_eq!(row.tx_id, 2);
+        assert_eq!(row.amount, Some(dec!(3.00)));
+    }
+
+    #[test]
+    fn should_not_convert_raw_row_with_unknown_type() {
+        assert_eq!(
+            raw_row("payout").to_row().unwrap_err(),
+            RowError::UnknownType
+        );
+        assert_eq!(
+            raw_row("Deposit").to_row().unwrap_err(),
+            RowError::UnknownType
+        );
+    }
+
+    #[test]
+    fn should_convert_raw_row_amount() {
+        let with_amount = |amount: Option<&str>| RawTxRow {
+            amount: amount.map(String::from),
+            ..raw_row("deposit")
+        };
+        assert_eq!(with_amount(None).to_row().unwrap().amount, None);
+        assert_eq!(
+            with_amount(Some("1.5e3")).to_row().unwrap().amount,
+            Some(dec!(1500))
+        );
+        for amount in ["1234567890123456789012345678901234567890", "ten", "1 0"] {
+            assert_eq!(
+                with_amount(Some(amount)).to_row().unwrap_err(),
+                RowError::UnparseableAmount
+            );
+        }
+        // an unknown type is reported first
+        let row = RawTxRow {
+            amount: Some("ten".to_string()),
+            ..raw_row("payout")
+        };
+        assert_eq!(row.to_row().unwrap_err(), RowError::UnknownType);
+    }
+
+    #[test]
+    fn should_keep_every_digit_of_amounts() {
+        // an f64 only holds about 16 significant digits
+        let amount = "0.1000000000000000055";
+        let row = RawTxRow {
+            amount: Some(amount.to_string()),
+            ..raw_row("deposit")
+        };
+        assert_eq!(
+            row.to_row().unwrap().amount,
+            Some(dec!(0.1000000000000000055))
+        );
+        let input = format!(
+            "type,client,tx,amount\ndeposit,1,2,{}\nwithdrawal,1,3,\n",
+            amount
+        );
+        let rows: Vec<TxRow> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows[0].amount, Some(dec!(0.1000000000000000055)));
+        assert_eq!(rows[1].amount, None);
+    }
+
+    #[test]
+    fn should_convert_minor_units_to_decimal() {
+        let row = MinorUnitsTxRow {
+            tx_type: "deposit".to_string(),
+            client_id: 1u16,
+            tx_id: 2u32,
+            amount: Some(-1234),
+            dest: None,
+            timestamp: None,
+        };
+        let amount = |raw: RawTxRow| raw.to_row().unwrap().amount;
+        assert_eq!(amount(row.clone().into_raw_row(2)), Some(dec!(-12.34)));
+        assert_eq!(amount(row.clone().into_raw_row(0)), Some(dec!(-1234)));
+        let row = MinorUnitsTxRow {
+            amount: None,
+            ..row
+        };
+        assert_eq!(row.into_raw_row(2).amount, None);
+    }
+
+    fn account(available: Decimal, held: Decimal) -> AccountData {
+        AccountData::new(available, held).unwrap()
+    }
+
+    #[test]
+    fn should_not_overflow_total() {
+        assert_eq!(account(dec!(1.50), dec!(2.25)).total(), dec!(3.75));
+        let half = Decimal::MAX / dec!(2) + dec!(1);
+        assert_eq!(
+            AccountData::new(half, half).unwrap_err(),
+            BalanceError::Overflow
+        );
+        assert_eq!(
+            account(Decimal::MAX, -Decimal::ONE).total(),
+            Decimal::MAX - Decimal::ONE
+        );
+        let mut data = account(half, half - dec!(3));
+        let total = data.total;
+        assert_eq!(data.deposit(dec!(3)), Err(BalanceError::Overflow));
+        assert_eq!(data.add_held(dec!(3)), Err(BalanceError::Overflow));
+        assert_eq!((data.available, data.total), (half, total));
+    }
+
+    #[test]
+    fn should_keep_total_in_step_with_available_and_held() {
+        let mut data = AccountData::default();
+        data.deposit(dec!(10.00)).unwrap();
+        // disputing a deposit
+        data.apply_hold(dec!(4.00)).unwrap();
+        assert_eq!(data.total(), data.available() + data.held());
+        assert_eq!(data.total(), dec!(10.00));
+        // disputing a withdrawal, then charging it back
+        data.add_held(dec!(2.00)).unwrap();
+        data.release_hold(dec!(2.00)).unwrap();
+        data.remove_held(dec!(4.00)).unwrap();
+        data.withdraw(dec!(1.00)).unwrap();
+        assert_eq!(data.total(), data.available() + data.held());
+        assert_eq!(data.total(), dec!(7.00));
+        assert_eq!(data.peak_held(), dec!(6.00));
+    }
+
+    #[test]
+    fn should_deposit_and_withdraw() {
+        let mut data = AccountData::default();
+        data.deposit(dec!(10.00)).unwrap();
+        data.withdraw(dec!(4.00)).unwrap();
+        assert_eq!(data.available, dec!(6.00));
+        assert_eq!(
+            data.withdraw(dec!(6.01)),
+            Err(BalanceError::InsufficientAvailable)
+        );
+        assert_eq!(data.available, dec!(6.00));
+    }
+
+    #[test]
+    fn should_apply_and_release_hold() {
+        let mut data = account(dec!(10.00), Decimal::ZERO);
+        data.apply_hold(dec!(15.00)).unwrap();
+        assert_eq!(data.available, dec!(-5.00));
+        assert_eq!(data.held, dec!(15.00));
+        data.release_hold(dec!(15.00)).unwrap();
+        assert_eq!(data.available, dec!(10.00));
+        assert_eq!(data.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn should_not_release_more_than_held() {
+        let mut data = account(dec!(10.00), dec!(3.00));
+        assert_eq!(
+            data.release_hold(dec!(3.01)),
+            Err(BalanceError::InsufficientHeld)
+        );
+        assert_eq!(
+            data.remove_held(dec!(3.01)),
+            Err(BalanceError::InsufficientHeld)
+        );
+        assert_eq!(data.available, dec!(10.00));
+        assert_eq!(data.held, dec!(3.00));
+    }
+
+    #[test]
+    fn should_add_and_remove_held() {
+        let mut data = account(dec!(10.00), Decimal::ZERO);
+        data.add_held(dec!(4.00)).unwrap();
+        assert_eq!(data.held, dec!(4.00));
+        data.remove_held(dec!(4.00)).unwrap();
+        assert_eq!(data.available, dec!(10.00));
+        assert_eq!(data.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn should_refuse_balance_changes_that_overflow() {
+        let mut data = account(Decimal::MAX, Decimal::ZERO);
+        assert_eq!(data.deposit(Decimal::ONE), Err(BalanceError::Overflow));
+        assert_eq!(data.withdraw(-Decimal::ONE), Err(BalanceError::Overflow));
+        assert_eq!(data.apply_hold(-Decimal::ONE), Err(BalanceError::Overflow));
+        let mut data = account(Decimal::ZERO, Decimal::MAX);
+        assert_eq!(data.add_held(Decimal::ONE), Err(BalanceError::Overflow));
+        assert_eq!(
+            data.release_hold(-Decimal::ONE),
+            Err(BalanceError::Overflow)
+        );
+        let mut data = account(Decimal::MIN, Decimal::MAX);
+        assert_eq!(data.apply_hold(Decimal::ONE), Err(BalanceError::Overflow));
+        assert_eq!(data.available, Decimal::MIN);
+        assert_eq!(data.held, Decimal::MAX);
+    }
+
+    #[test]
+    fn should_serialize_account_data_with_string_decimals() {
+        let data = account(dec!(1.2345), dec!(0.1));
+        assert_eq!(
+            serde_json::to_string(&data).unwrap(),
+            r#"{"available":"1.2345","held":"0.1","total":"1.3345","peak_held":"0.1"}"#
+        );
+    }
+
+    #[test]
+    fn should_serialize_transaction() {
+        let tx = Transaction {
+            client_id: 2,
+            tx_type: TransactionType::Deposit,
+            amount: dec!(100.0001),
+            state: TxState::ChargedBack,
+            disputed_amount: dec!(40),
+        };
+        assert_eq!(
+            serde_json::to_string(&tx).unwrap(),
+            r#"{"client_id":2,"tx_type":"deposit","amount":"100.0001","state":"charged_back","disputed_amount":"40"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&TransactionType::Withdrawal).unwrap(),
+            r#""withdrawal""#
+        );
+        let snapshot = TransactionSnapshot {
+            tx_id: 7,
+            client_id: 2,
+            tx_type: TransactionType::Withdrawal,
+            amount: dec!(3.5),
+            state: TxState::Disputed,
+        };
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            r#"{"tx_id":7,"client_id":2,"tx_type":"withdrawal","amount":"3.5","state":"disputed"}"#
+        );
+    }
 }
@@ -0,0 +1,62 @@
+use std::{
+    io::{self, Read},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// How long `Follow` waits before looking for new input again.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads `inner` like `tail -f`: at the end of the input it waits for more
+/// until `stop` is set. Only complete lines are handed out, so a row that is
+/// still being written is never parsed, and one left incomplete when stopping
+/// is dropped.
+pub(crate) struct Follow<'a, R> {
+    inner: R,
+    stop: &'a AtomicBool,
+    // bytes read from inner but not handed out yet
+    pending: Vec<u8>,
+    // length of the complete lines at the start of pending
+    complete: usize,
+}
+
+impl<'a, R: Read> Follow<'a, R> {
+    pub(crate) fn new(inner: R, stop: &'a AtomicBool) -> Self {
+        Self {
+            inner,
+            stop,
+            pending: Vec::new(),
+            complete: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Follow<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.complete > 0 {
+                let len = self.complete.min(buf.len());
+                buf[..len].copy_from_slice(&self.pending[..len]);
+                self.pending.drain(..len);
+                self.complete -= len;
+                return Ok(len);
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            let mut chunk = [0; 8192];
+            match self.inner.read(&mut chunk)? {
+                0 => thread::sleep(POLL_INTERVAL),
+                len => {
+                    self.pending.extend_from_slice(&chunk[..len]);
+                    self.complete = self
+                        .pending
+                        .iter()
+                        .rposition(|byte| *byte == b'\n')
+                        .map_or(0, |end| end + 1);
+                }
+            }
+        }
+    }
+}
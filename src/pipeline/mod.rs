@@ -0,0 +1,476 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    error, fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    cli::{AccountOrder, Args, OutputFormat, UntimedRowPolicy},
+    engine::{ConfigError, Engine, EngineBuilder, ProcessingError},
+    models::{AccountSnapshot, MinorUnitsTxRow, RawTxRow, TransactionType},
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+pub use self::follow::POLL_INTERVAL;
+
+use self::follow::Follow;
+
+mod follow;
+
+/// Number of failed rows per error name, e.g. `"TxNotFound"`.
+pub type ErrorCounts = BTreeMap<&'static str, usize>;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// More rows failed than `Args::max_errors` allows; nothing was written.
+    TooManyErrors(ErrorCounts),
+    /// `Args::fail_on_lock` is set and these clients ended locked; the output
+    /// was written regardless.
+    AccountsLocked(Vec<u16>),
+    /// `Args::summary_row` is set but the accounts add up to more than a
+    /// `Decimal` can hold; nothing was written.
+    SummaryOverflow,
+    Config(ConfigError),
+    Csv(csv::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::TooManyErrors(counts) => {
+                write!(
+                    f,
+                    "aborting after {} errors",
+                    counts.values().sum::<usize>()
+                )
+            }
+            PipelineError::AccountsLocked(clients) => {
+                let clients: Vec<String> = clients.iter().map(u16::to_string).collect();
+                write!(f, "accounts ended locked: {}", clients.join(", "))
+            }
+            PipelineError::SummaryOverflow => {
+                write!(f, "accounts add up to more than the summary row can hold")
+            }
+            PipelineError::Config(err) => write!(f, "{}", err),
+            PipelineError::Csv(err) => write!(f, "{}", err),
+            PipelineError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PipelineError::Config(err) => Some(err),
+            PipelineError::Csv(err) => Some(err),
+            PipelineError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for PipelineError {
+    fn from(err: ConfigError) -> Self {
+        PipelineError::Config(err)
+    }
+}
+
+impl From<csv::Error> for PipelineError {
+    fn from(err: csv::Error) -> Self {
+        PipelineError::Csv(err)
+    }
+}
+
+impl From<io::Error> for PipelineError {
+    fn from(err: io::Error) -> Self {
+        PipelineError::Io(err)
+    }
+}
+
+/// Reads transactions as CSV from `input`, processes them and writes the
+/// resulting accounts as CSV to `output`, or in a dry run the number of rows
+/// failing with each error. Rows that fail are logged and skipped. Only the
+/// processing options of `args` are used; the file path and logging are left
+/// to the caller.
+pub fn run<R: Read, W: Write>(input: R, output: W, args: &Args) -> Result<(), PipelineError> {
+    run_until(input, output, args, &AtomicBool::new(false))
+}
+
+/// Like `run`, but stops reading once `stop` is set, e.g. by a Ctrl-C handler,
+/// and writes the output for the rows processed until then. With
+/// `Args::follow` set, reaching the end of `input` does not stop reading;
+/// rows appended later are processed as they arrive until `stop` is set.
+pub fn run_until<'a, R: Read + 'a, W: Write>(
+    input: R,
+    output: W,
+    args: &Args,
+    stop: &'a AtomicBool,
+) -> Result<(), PipelineError> {
+    run_with_progress(input, output, io::stderr(), args, stop)
+}
+
+/// Like `run_until`, but writes the lines `Args::progress` asks for to
+/// `progress` instead of stderr.
+pub fn run_with_progress<'a, R: Read + 'a, W: Write, P: Write>(
+    input: R,
+    output: W,
+    mut progress: P,
+    args: &Args,
+    stop: &'a AtomicBool,
+) -> Result<(), PipelineError> {
+    let input: Box<dyn Read + 'a> = match args.follow {
+        true => Box::new(Follow::new(input, stop)),
+        false => Box::new(input),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(args.trim)
+        .delimiter(args.delimiter)
+        .has_headers(args.has_headers)
+        .from_reader(input);
+
+    // process transactions
+    let config = args.engine_config()?;
+    let mut builder = EngineBuilder::new()
+        .config(&config)
+        .with_amount_scale_policy(args.amount_scale_policy(&config))
+        .with_idempotent(args.idempotent);
+    if let Some(rows) = args.hold_expiry {
+        builder = builder.with_hold_expiry(rows);
+    }
+    if let Some(path) = &args.record {
+        builder = builder.record_to(File::create(path)?);
+    }
+    let mut engine = builder.build();
+    let mut error_counts = ErrorCounts::new();
+    let error_total = Cell::new(0);
+    let mut count_error = |name: &'static str| {
+        *error_counts.entry(name).or_default() += 1;
+        error_total.set(error_total.get() + 1);
+        args.max_errors.is_some_and(|max| error_total.get() > max)
+    };
+
+    let records: Box<dyn Iterator<Item = csv::Result<RawTxRow>>> = match args.minor_units {
+        None => Box::new(reader.deserialize()),
+        Some(scale) => Box::new(
+            reader
+                .deserialize::<MinorUnitsTxRow>()
+                .map(move |record| record.map(|row| row.into_raw_row(scale))),
+        ),
+    };
+    let records = records.map(|record| match args.decimal_comma {
+        true => record.map(with_decimal_point),
+        false => record,
+    });
+    let mut error_output = match &args.error_output {
+        Some(path) => {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record(["tx_id", "client_id", "error"])?;
+            Some(writer)
+        }
+        None => None,
+    };
+    let mut deferred = Vec::new();
+    let mut rows_read: u64 = 0;
+    for record in records {
+        if stop.load(Ordering::Relaxed) {
+            log::warn!("interrupted, writing the accounts processed so far");
+            break;
+        }
+        let aborting = match record {
+            // process rows within the time window, logging errors
+            Ok(row) => match args.in_time_window(row.timestamp) {
+                Some(true) => match engine.try_raw_row(&row) {
+                    Err(err) if args.defer_unknown && refers_to_unseen_tx(&row, &err) => {
+                        log::debug!("deferring tx {} until the end of the file", row.tx_id);
+                        deferred.push(row);
+                        false
+                    }
+                    Ok(()) => {
+                        engine.record_raw_row(&row);
+                        false
+                    }
+                    Err(err) => {
+                        engine.record_raw_row(&row);
+                        log::error!(client_id = row.client_id, tx_id = row.tx_id; "{}", err);
+                        write_error(&mut error_output, Some((row.tx_id, row.client_id)), &err)?;
+                        count_error(err.name())
+                    }
+                },
+                Some(false) => {
+                    log::debug!("skipping tx {} outside the time window", row.tx_id);
+                    false
+                }
+                None => match args.untimed {
+                    UntimedRowPolicy::Error => {
+                        let reason = format!("tx {} has no timestamp", row.tx_id);
+                        log::error!(client_id = row.client_id, tx_id = row.tx_id; "{}", reason);
+                        write_error(&mut error_output, Some((row.tx_id, row.client_id)), &reason)?;
+                        count_error("MissingTimestamp")
+                    }
+                    UntimedRowPolicy::Skip => {
+                        log::debug!("skipping tx {} without a timestamp", row.tx_id);
+                        false
+                    }
+                },
+            },
+            // skip rows that cannot be parsed, noting where they are; a
+            // leading BOM is already stripped by the csv reader
+            Err(err) => {
+                let line = err.position().map_or(0, |pos| pos.line());
+                match err.kind() {
+                    csv::ErrorKind::Utf8 { .. } => {
+                        log::error!(line; "skipping row on line {} that is not valid UTF-8: {}", line, err);
+                        write_error(&mut error_output, None, &err)?;
+                        count_error("InvalidUtf8")
+                    }
+                    _ => {
+                        log::error!(line; "skipping malformed row on line {}: {}", line, err);
+                        write_error(&mut error_output, None, &err)?;
+                        count_error("MalformedRow")
+                    }
+                }
+            }
+        };
+        if aborting {
+            return Err(PipelineError::TooManyErrors(error_counts));
+        }
+        rows_read += 1;
+        if args
+            .progress
+            .is_some_and(|every| rows_read.is_multiple_of(every.get()))
+        {
+            // progress is only informative, so failing to report it is not
+            // worth stopping for
+            let _ = writeln!(
+                progress,
+                "processed {} rows, {} errors",
+                with_separators(rows_read),
+                with_separators(error_total.get() as u64)
+            );
+        }
+    }
+
+    // retry deferred rows until a pass makes no progress, failing the rows
+    // still referring to an unseen tx after the last pass; a deferred row is
+    // recorded once its outcome is final
+    for pass in 1..=DEFER_PASSES {
+        let pending = deferred.len();
+        for row in std::mem::take(&mut deferred) {
+            let aborting = match engine.try_raw_row(&row) {
+                Err(err) if pass < DEFER_PASSES && refers_to_unseen_tx(&row, &err) => {
+                    deferred.push(row);
+                    false
+                }
+                Ok(()) => {
+                    engine.record_raw_row(&row);
+                    false
+                }
+                Err(err) => {
+                    engine.record_raw_row(&row);
+                    log::error!(client_id = row.client_id, tx_id = row.tx_id; "{}", err);
+                    write_error(&mut error_output, Some((row.tx_id, row.client_id)), &err)?;
+                    count_error(err.name())
+                }
+            };
+            if aborting {
+                return Err(PipelineError::TooManyErrors(error_counts));
+            }
+        }
+        if deferred.is_empty() || deferred.len() == pending {
+            break;
+        }
+    }
+    for row in deferred {
+        engine.record_raw_row(&row);
+        let err = ProcessingError::<u16, u32>::TxNotFound(row.tx_id);
+        log::error!(client_id = row.client_id, tx_id = row.tx_id; "{}", err);
+        write_error(&mut error_output, Some((row.tx_id, row.client_id)), &err)?;
+        if count_error(err.name()) {
+            return Err(PipelineError::TooManyErrors(error_counts));
+        }
+    }
+    if let Some(writer) = error_output.as_mut() {
+        writer.flush()?;
+    }
+    log::info!("summary: {:?}", engine.summary());
+
+    if args.output == OutputFormat::Ndjson {
+        write_ndjson(output, &engine, args, error_counts)?;
+        return check_locked(&engine, args);
+    }
+
+    let mut writer = csv::WriterBuilder::new().from_writer(output);
+
+    if args.dry_run {
+        // the engine only served as shadow state for validating the file, so
+        // report what would have failed rather than the resulting balances
+        writer.write_record(["error", "count"])?;
+        for (name, count) in error_counts {
+            writer.write_record([name, &count.to_string()])?;
+        }
+        writer.flush()?;
+        return check_locked(&engine, args);
+    }
+
+    let sums = match args.summary_row {
+        true => Some(sum_accounts(&engine, args).ok_or(PipelineError::SummaryOverflow)?),
+        false => None,
+    };
+    writer.write_record(["client", "total", "available", "held", "locked"])?;
+    for account in accounts(&engine, args) {
+        writer.write_record(&[
+            account.client_id.to_string(),
+            account.total.to_string(),
+            account.available.to_string(),
+            account.held.to_string(),
+            account.locked.to_string(),
+        ])?;
+    }
+    if let Some((total, available, held)) = sums {
+        writer.write_record(&[
+            "TOTAL".to_string(),
+            total.to_string(),
+            available.to_string(),
+            held.to_string(),
+            String::new(),
+        ])?;
+    }
+    writer.flush()?;
+    check_locked(&engine, args)
+}
+
+// appends a failed row to the --error-output file, if any; a row that could
+// not be parsed has no ids to write
+fn write_error<W: Write>(
+    writer: &mut Option<csv::Writer<W>>,
+    ids: Option<(u32, u16)>,
+    reason: &dyn fmt::Display,
+) -> csv::Result<()> {
+    let (tx_id, client_id) = match ids {
+        Some((tx_id, client_id)) => (tx_id.to_string(), client_id.to_string()),
+        None => (String::new(), String::new()),
+    };
+    match writer {
+        Some(writer) => writer.write_record(&[tx_id, client_id, reason.to_string()]),
+        None => Ok(()),
+    }
+}
+
+// formats 1234567 as 1,234,567
+fn with_separators(n: u64) -> String {
+    let digits = n.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+// turns an amount like 1,23 into 1.23 before it is parsed
+fn with_decimal_point(row: RawTxRow) -> RawTxRow {
+    RawTxRow {
+        amount: row.amount.map(|amount| amount.replace(',', ".")),
+        ..row
+    }
+}
+
+/// How often rows deferred by `Args::defer_unknown` are retried at most.
+pub const DEFER_PASSES: usize = 3;
+
+// whether a dispute, resolve or chargeback failed as its tx has not been read
+fn refers_to_unseen_tx(row: &RawTxRow, err: &ProcessingError) -> bool {
+    matches!(err, ProcessingError::TxNotFound(_))
+        && row.to_row().is_ok_and(|row| {
+            matches!(
+                row.tx_type,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            )
+        })
+}
+
+/// Sums the written total, available and held funds of every account, or
+/// None if they overflow.
+fn sum_accounts(engine: &Engine, args: &Args) -> Option<(Decimal, Decimal, Decimal)> {
+    accounts(engine, args).try_fold(
+        (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO),
+        |(total, available, held), account| {
+            Some((
+                total.checked_add(account.total)?,
+                available.checked_add(account.available)?,
+                held.checked_add(account.held)?,
+            ))
+        },
+    )
+}
+
+/// The accounts in the requested order, rounded as they are written, leaving
+/// out unlocked accounts without funds if `Args::nonzero_only` is set.
+fn accounts<'a>(engine: &'a Engine, args: &Args) -> impl Iterator<Item = AccountSnapshot> + 'a {
+    let nonzero_only = args.nonzero_only;
+    let accounts: Box<dyn Iterator<Item = _>> = match args.order {
+        AccountOrder::ClientId => {
+            Box::new(engine.get_account_iter().map(|(id, data)| (*id, *data)))
+        }
+        AccountOrder::FirstSeen => Box::new(engine.get_account_iter_first_seen()),
+    };
+    accounts
+        .map(|(client_id, data)| AccountSnapshot {
+            client_id,
+            available: engine.round(data.available()),
+            held: engine.round(data.held()),
+            total: engine.round(data.total()),
+            locked: engine.is_account_locked(client_id),
+        })
+        .filter(move |account| {
+            !nonzero_only
+                || account.locked
+                || !(account.available.is_zero()
+                    && account.held.is_zero()
+                    && account.total.is_zero())
+        })
+}
+
+#[derive(Serialize)]
+struct ErrorCount {
+    error: &'static str,
+    count: usize,
+}
+
+/// Writes one JSON object per line: an account, or in a dry run an error name
+/// with its count.
+fn write_ndjson<W: Write>(
+    output: W,
+    engine: &Engine,
+    args: &Args,
+    error_counts: ErrorCounts,
+) -> io::Result<()> {
+    let mut output = BufWriter::new(output);
+    if args.dry_run {
+        for (error, count) in error_counts {
+            serde_json::to_writer(&mut output, &ErrorCount { error, count })?;
+            writeln!(output)?;
+        }
+    } else {
+        for account in accounts(engine, args) {
+            serde_json::to_writer(&mut output, &account)?;
+            writeln!(output)?;
+        }
+    }
+    output.flush()
+}
+
+fn check_locked(engine: &Engine, args: &Args) -> Result<(), PipelineError> {
+    let locked = engine.locked_accounts();
+    if args.fail_on_lock && !locked.is_empty() {
+        return Err(PipelineError::AccountsLocked(locked));
+    }
+    Ok(())
+}
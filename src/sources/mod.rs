@@ -0,0 +1,3 @@
+mod xml;
+
+pub use xml::{XmlError, XmlSource};
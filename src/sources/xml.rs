@@ -0,0 +1,251 @@
+use std::{collections::HashMap, error, fmt, io::BufRead, marker::PhantomData, str::FromStr};
+
+use quick_xml::{events::Event, Reader};
+
+use crate::models::RawTxRow;
+
+// element names of the fields of a transaction, as in the CSV header
+const FIELDS: [&str; 5] = ["type", "client", "tx", "amount", "dest"];
+
+/// Reads rows from XML with one element per transaction, e.g.
+///
+/// ```xml
+/// <transactions>
+///   <transaction>
+///     <type>deposit</type>
+///     <client>1</client>
+///     <tx>1</tx>
+///     <amount>1.5</amount>
+///   </transaction>
+/// </transactions>
+/// ```
+///
+/// Namespace prefixes and elements other than the fields of a transaction
+/// are ignored, so the rows may be embedded in a larger document. Iterating
+/// yields the rows for `RawTxRow::to_row` (or `Engine::process_raw_row`) to
+/// check; a transaction that cannot be read fails on its own and reading
+/// carries on after it.
+pub struct XmlSource<R, C = u16, T = u32> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    _ids: PhantomData<(C, T)>,
+}
+
+impl<R: BufRead, C: FromStr, T: FromStr> XmlSource<R, C, T> {
+    pub fn new(input: R) -> Self {
+        let mut reader = Reader::from_reader(input);
+        reader.config_mut().trim_text(true);
+        Self {
+            reader,
+            buf: Vec::new(),
+            _ids: PhantomData,
+        }
+    }
+
+    fn read_row(&mut self) -> Result<Option<RawTxRow<C, T>>, XmlError> {
+        // field values of the transaction being read, if inside one
+        let mut values: Option<HashMap<&'static str, String>> = None;
+        let mut field = None;
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(element) => match (element.local_name().as_ref(), &values) {
+                    (b"transaction", _) => values = Some(HashMap::new()),
+                    (name, Some(_)) => {
+                        field = FIELDS.into_iter().find(|field| field.as_bytes() == name)
+                    }
+                    _ => {}
+                },
+                Event::Text(text) => {
+                    if let (Some(values), Some(field)) = (values.as_mut(), field) {
+                        values.insert(field, text.unescape()?.into_owned());
+                    }
+                }
+                Event::End(element) => {
+                    field = None;
+                    if element.local_name().as_ref() == b"transaction" {
+                        if let Some(values) = values.take() {
+                            let position = self.reader.buffer_position();
+                            return to_row(values, position).map(Some);
+                        }
+                    }
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead, C: FromStr, T: FromStr> Iterator for XmlSource<R, C, T> {
+    type Item = Result<RawTxRow<C, T>, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_row().transpose()
+    }
+}
+
+fn to_row<C: FromStr, T: FromStr>(
+    mut values: HashMap<&'static str, String>,
+    position: u64,
+) -> Result<RawTxRow<C, T>, XmlError> {
+    let mut required = |field: &'static str| {
+        values
+            .remove(field)
+            .ok_or(XmlError::MissingField { field, position })
+    };
+    let tx_type = required("type")?;
+    let client_id = parse("client", required("client")?, position)?;
+    let tx_id = parse("tx", required("tx")?, position)?;
+    let dest = match values.remove("dest") {
+        Some(dest) => Some(parse("dest", dest, position)?),
+        None => None,
+    };
+    Ok(RawTxRow {
+        tx_type,
+        client_id,
+        tx_id,
+        amount: values.remove("amount"),
+        dest,
+        timestamp: None,
+    })
+}
+
+fn parse<V: FromStr>(field: &'static str, value: String, position: u64) -> Result<V, XmlError> {
+    value.parse().map_err(|_| XmlError::InvalidField {
+        field,
+        value,
+        position,
+    })
+}
+
+#[derive(Debug)]
+pub enum XmlError {
+    Parse(quick_xml::Error),
+    // a transaction lacks a required element; position is the byte offset of
+    // the end of the transaction
+    MissingField {
+        field: &'static str,
+        position: u64,
+    },
+    // an id does not fit the id type
+    InvalidField {
+        field: &'static str,
+        value: String,
+        position: u64,
+    },
+}
+
+impl From<quick_xml::Error> for XmlError {
+    fn from(err: quick_xml::Error) -> Self {
+        XmlError::Parse(err)
+    }
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::Parse(err) => write!(f, "malformed XML: {}", err),
+            XmlError::MissingField { field, position } => {
+                write!(
+                    f,
+                    "transaction ending at byte {} has no {}",
+                    position, field
+                )
+            }
+            XmlError::InvalidField {
+                field,
+                value,
+                position,
+            } => write!(
+                f,
+                "transaction ending at byte {} has an invalid {}: {:?}",
+                position, field, value
+            ),
+        }
+    }
+}
+
+impl error::Error for XmlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            XmlError::Parse(err) => Some(err),
+            XmlError::MissingField { .. } | XmlError::InvalidField { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, models::RawTxRow, sources::XmlError};
+
+    use super::XmlSource;
+
+    fn process(source: XmlSource<&[u8]>) -> Engine {
+        let mut engine = Engine::new();
+        for row in source {
+            let _ = engine.process_raw_row(&row.unwrap());
+        }
+        engine
+    }
+
+    #[test]
+    fn should_produce_same_balances_as_csv() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <Document xmlns:tx="urn:example:transactions">
+              <tx:transactions>
+                <tx:transaction>
+                  <tx:type>deposit</tx:type><tx:client>1</tx:client>
+                  <tx:tx>1</tx:tx><tx:amount>10.5</tx:amount>
+                  <tx:reference>ignored</tx:reference>
+                </tx:transaction>
+                <tx:transaction>
+                  <tx:type>deposit</tx:type><tx:client>2</tx:client>
+                  <tx:tx>2</tx:tx><tx:amount>3</tx:amount>
+                </tx:transaction>
+                <tx:transaction>
+                  <tx:type>withdrawal</tx:type><tx:client>1</tx:client>
+                  <tx:tx>3</tx:tx><tx:amount>0.25</tx:amount>
+                </tx:transaction>
+                <tx:transaction>
+                  <tx:type>dispute</tx:type><tx:client>2</tx:client><tx:tx>2</tx:tx>
+                </tx:transaction>
+              </tx:transactions>
+            </Document>"#;
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,10.5\n\
+                   deposit,2,2,3\n\
+                   withdrawal,1,3,0.25\n\
+                   dispute,2,2,\n";
+
+        let from_xml = process(XmlSource::new(xml.as_bytes()));
+        let mut from_csv = Engine::new();
+        for row in csv::Reader::from_reader(csv.as_bytes()).into_deserialize::<RawTxRow>() {
+            let _ = from_csv.process_raw_row(&row.unwrap());
+        }
+        assert_eq!(from_xml.get_account_iter().count(), 2);
+        for (id, _) in from_csv.get_account_iter() {
+            assert_eq!(from_xml.get_account(*id), from_csv.get_account(*id));
+        }
+        assert_eq!(from_xml.summary(), from_csv.summary());
+    }
+
+    #[test]
+    fn should_fail_transaction_without_client() {
+        let xml = "<transactions>\
+                   <transaction><type>deposit</type><tx>1</tx></transaction>\
+                   <transaction><type>deposit</type><client>1</client><tx>2</tx></transaction>\
+                   </transactions>";
+        let mut source: XmlSource<_> = XmlSource::new(xml.as_bytes());
+        assert!(matches!(
+            source.next(),
+            Some(Err(XmlError::MissingField {
+                field: "client",
+                ..
+            }))
+        ));
+        let row: RawTxRow = source.next().unwrap().unwrap();
+        assert_eq!((row.client_id, row.tx_id, row.amount), (1, 2, None));
+        assert!(source.next().is_none());
+    }
+}
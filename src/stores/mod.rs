@@ -1,108 +1,587 @@
-use std::collections::{hash_map::Iter, HashMap, HashSet};
+use std::collections::{btree_map, hash_map::Values, BTreeMap, HashMap, HashSet, VecDeque};
 
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use crate::models::{AccountData, Transaction};
+use crate::models::{
+    AccountData, ClientId, Transaction, TransactionType, TxId, TxIdScope, TxState,
+};
 
-#[derive(Debug)]
-pub struct TransactionStore {
-    // maps tx_id to tx_amount
-    transactions: HashMap<u32, Decimal>,
-    // holds ids of disputed txs
-    disputed_transactions: HashSet<u32>,
+// spare capacity tolerated before the maps are shrunk after evictions
+const SHRINK_SLACK: usize = 1024;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "C: Serialize, T: Serialize",
+    deserialize = "C: ClientId + Deserialize<'de>, T: TxId + Deserialize<'de>"
+))]
+pub struct TransactionStore<C = u16, T = u32> {
+    // maps (client_id, tx_id) to tx data; client_id is None unless ids are
+    // scoped per client
+    #[serde(with = "as_pairs")]
+    transactions: HashMap<(Option<C>, T), Transaction<C>>,
+    scope: TxIdScope,
+    // number of rows a tx is kept for unless it gets disputed; None keeps
+    // every tx forever
+    retention: Option<u64>,
+    // rows seen so far, advanced by tick()
+    clock: u64,
+    // (clock at insertion, key) of txs that may be evicted, oldest first
+    eviction_queue: VecDeque<(u64, (Option<C>, T))>,
+    // key of the tx inserted last, unless it has been removed since
+    #[serde(default)]
+    latest: Option<(Option<C>, T)>,
+    // summed balance effect of the txs evicted so far
+    evicted_effect: Decimal,
+}
+
+impl<C: ClientId, T: TxId> Default for TransactionStore<C, T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl TransactionStore {
+impl<C: ClientId, T: TxId> TransactionStore<C, T> {
     pub fn new() -> Self {
         Self {
             transactions: HashMap::new(),
-            disputed_transactions: HashSet::new(),
+            scope: TxIdScope::Global,
+            retention: None,
+            clock: 0,
+            eviction_queue: VecDeque::new(),
+            latest: None,
+            evicted_effect: Decimal::ZERO,
+        }
+    }
+
+    pub fn set_scope(&mut self, scope: TxIdScope) {
+        self.scope = scope;
+    }
+
+    pub fn set_retention(&mut self, rows: u64) {
+        self.retention = Some(rows);
+    }
+
+    /// Advances the store by one row, evicting txs that fell out of the
+    /// retention window without ever being disputed.
+    pub fn tick(&mut self) {
+        self.clock += 1;
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return,
+        };
+        let mut evicted = false;
+        while let Some((inserted_at, key)) = self.eviction_queue.front().copied() {
+            if inserted_at.saturating_add(retention) > self.clock {
+                break;
+            }
+            self.eviction_queue.pop_front();
+            if let Some(tx) = self.transactions.get(&key) {
+                if matches!(
+                    tx.state,
+                    TxState::Normal | TxState::Reversed | TxState::Captured
+                ) {
+                    self.evicted_effect += tx.balance_effect();
+                    self.transactions.remove(&key);
+                    evicted = true;
+                    log::debug!("evicted tx_id {}", key.1);
+                }
+            }
+        }
+        if evicted {
+            self.shrink();
         }
     }
 
-    pub fn find_by_id(&self, id: &u32) -> Option<Transaction> {
-        match self.transactions.contains_key(id) {
-            true => Some(Transaction {
-                amount: self.transactions[id],
-                disputed: self.disputed_transactions.contains(id),
-            }),
-            false => None,
+    /// Hands back memory once the maps hold far less than they have room for,
+    /// e.g. after a burst of txs has been evicted.
+    fn shrink(&mut self) {
+        if self.transactions.capacity() > 2 * self.transactions.len() + SHRINK_SLACK {
+            self.transactions.shrink_to_fit();
+        }
+        if self.eviction_queue.capacity() > 2 * self.eviction_queue.len() + SHRINK_SLACK {
+            self.eviction_queue.shrink_to_fit();
         }
     }
 
-    pub fn insert_tx(&mut self, id: u32, amount: Decimal) -> Result<(), DataError> {
-        match self.transactions.contains_key(&id) {
-            true => Err(DataError::AlreadyExists),
-            false => {
-                self.transactions.insert(id, amount);
-                log::info!("inserted tx (id: {}, amount: {})", id, amount);
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    fn key(&self, client_id: C, tx_id: T) -> (Option<C>, T) {
+        match self.scope {
+            TxIdScope::Global => (None, tx_id),
+            TxIdScope::PerClient => (Some(client_id), tx_id),
+        }
+    }
+
+    pub fn find_by_id(&self, client_id: C, tx_id: T) -> Option<Transaction<C>> {
+        self.transactions.get(&self.key(client_id, tx_id)).copied()
+    }
+
+    pub fn insert_tx(
+        &mut self,
+        client_id: C,
+        tx_id: T,
+        tx_type: TransactionType,
+        amount: Decimal,
+    ) -> Result<(), DataError> {
+        let key = self.key(client_id, tx_id);
+        match self.transactions.get(&key).map(|tx| tx.state) {
+            Some(TxState::Disputed) => Err(DataError::AlreadyDisputed),
+            Some(_) => Err(DataError::AlreadyExists),
+            None => {
+                self.transactions.insert(
+                    key,
+                    Transaction {
+                        client_id,
+                        tx_type,
+                        amount,
+                        state: TxState::Normal,
+                        disputed_amount: Decimal::ZERO,
+                    },
+                );
+                if self.retention.is_some() {
+                    self.eviction_queue.push_back((self.clock, key));
+                }
+                self.latest = Some(key);
+                log::info!(
+                    "inserted tx (id: {}, client: {}, amount: {})",
+                    tx_id,
+                    client_id,
+                    amount
+                );
                 Ok(())
             }
         }
     }
 
-    pub fn dispute_transaction(&mut self, id: u32) {
-        self.disputed_transactions.insert(id);
-        log::info!("disputed tx_id {}", id);
+    /// Whether the tx is the one inserted last.
+    pub fn is_latest(&self, client_id: C, tx_id: T) -> bool {
+        self.latest == Some(self.key(client_id, tx_id))
     }
 
-    pub fn resolve_transaction(&mut self, id: &u32) {
-        if self.disputed_transactions.contains(id) {
-            self.disputed_transactions.remove(id);
-            log::info!("resolved tx_id {}", id)
+    /// Forgets the tx inserted last, as if it had never been inserted, so its
+    /// id may be used again.
+    pub fn remove_latest(&mut self) -> Option<Transaction<C>> {
+        let key = self.latest.take()?;
+        if self
+            .eviction_queue
+            .back()
+            .is_some_and(|(_, queued)| *queued == key)
+        {
+            self.eviction_queue.pop_back();
+        }
+        log::info!("removed tx_id {}", key.1);
+        self.transactions.remove(&key)
+    }
+
+    /// Summed balance effect of every tx stored or evicted, see
+    /// `Transaction::balance_effect`, or None if it does not fit a `Decimal`.
+    pub fn balance_effect(&self) -> Option<Decimal> {
+        self.transactions
+            .values()
+            .map(Transaction::balance_effect)
+            .try_fold(self.evicted_effect, Decimal::checked_add)
+    }
+
+    /// Moves every tx of `other` into this store, keyed by this store's scope
+    /// and aged as they were in `other`. Fails with the first tx id both
+    /// stores know, leaving this store untouched.
+    pub fn merge(&mut self, other: Self) -> Result<(), T> {
+        let rekey = |key: (Option<C>, T), tx: &Transaction<C>| self.key(tx.client_id, key.1);
+        if let Some((key, _)) = other
+            .transactions
+            .iter()
+            .find(|(key, tx)| self.transactions.contains_key(&rekey(**key, tx)))
+        {
+            return Err(key.1);
+        }
+        if self.retention.is_some() {
+            let queued: Vec<_> = match other.retention {
+                // keep each tx's age relative to the end of its own input
+                Some(_) => other
+                    .eviction_queue
+                    .iter()
+                    .filter_map(|(inserted_at, key)| {
+                        let tx = other.transactions.get(key)?;
+                        let age = other.clock - inserted_at;
+                        Some((self.clock.saturating_sub(age), rekey(*key, tx)))
+                    })
+                    .collect(),
+                None => other
+                    .transactions
+                    .iter()
+                    .map(|(key, tx)| (self.clock, rekey(*key, tx)))
+                    .collect(),
+            };
+            self.eviction_queue.extend(queued);
+            self.eviction_queue
+                .make_contiguous()
+                .sort_by_key(|(inserted_at, _)| *inserted_at);
+        }
+        for (key, tx) in other.transactions {
+            let key = self.key(tx.client_id, key.1);
+            self.transactions.insert(key, tx);
         }
+        self.evicted_effect += other.evicted_effect;
+        Ok(())
     }
+
+    pub fn find_all(&self) -> Values<'_, (Option<C>, T), Transaction<C>> {
+        self.transactions.values()
+    }
+
+    /// Iterates over the stored transactions along with their ids.
+    pub fn iter(&self) -> impl Iterator<Item = (T, &Transaction<C>)> + '_ {
+        self.transactions
+            .iter()
+            .map(|((_, tx_id), tx)| (*tx_id, tx))
+    }
+
+    /// Whether the tx is under dispute, or None if it is unknown. With ids
+    /// scoped per client, whether any client's tx with that id is.
+    pub fn is_disputed(&self, tx_id: T) -> Option<bool> {
+        match self.scope {
+            TxIdScope::Global => self
+                .transactions
+                .get(&(None, tx_id))
+                .map(|tx| tx.state == TxState::Disputed),
+            TxIdScope::PerClient => self
+                .transactions
+                .iter()
+                .filter(|((_, id), _)| *id == tx_id)
+                .map(|(_, tx)| tx.state == TxState::Disputed)
+                .reduce(|any, disputed| any || disputed),
+        }
+    }
+
+    pub fn disputed_iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.transactions
+            .iter()
+            .filter(|(_, tx)| tx.state == TxState::Disputed)
+            .map(|((_, tx_id), _)| *tx_id)
+    }
+
+    /// Marks the tx disputed, holding `amount` of it.
+    pub fn dispute_transaction(&mut self, client_id: C, tx_id: T, amount: Decimal) {
+        let key = self.key(client_id, tx_id);
+        if let Some(tx) = self.transactions.get_mut(&key) {
+            tx.state = TxState::Disputed;
+            tx.disputed_amount = amount;
+            log::info!("disputed tx_id {}", tx_id);
+        }
+    }
+
+    pub fn resolve_transaction(&mut self, client_id: C, tx_id: T) {
+        let key = self.key(client_id, tx_id);
+        if let Some(tx) = self.transactions.get_mut(&key) {
+            if tx.state == TxState::Disputed {
+                tx.state = TxState::Resolved;
+                tx.disputed_amount = Decimal::ZERO;
+                log::info!("resolved tx_id {}", tx_id)
+            }
+        }
+    }
+
+    pub fn reverse_transaction(&mut self, client_id: C, tx_id: T) {
+        let key = self.key(client_id, tx_id);
+        if let Some(tx) = self.transactions.get_mut(&key) {
+            tx.state = TxState::Reversed;
+            log::info!("reversed tx_id {}", tx_id)
+        }
+    }
+
+    /// Ends every dispute on `client_id`'s transactions, marking them resolved.
+    pub fn clear_disputes(&mut self, client_id: C) {
+        for (key, tx) in self.transactions.iter_mut() {
+            if tx.client_id == client_id && tx.state == TxState::Disputed {
+                tx.state = TxState::Resolved;
+                tx.disputed_amount = Decimal::ZERO;
+                log::info!("cleared dispute on tx_id {}", key.1);
+            }
+        }
+    }
+
+    /// Ends the dispute, recording `amount` as the part charged back.
+    pub fn chargeback_transaction(&mut self, client_id: C, tx_id: T, amount: Decimal) {
+        let key = self.key(client_id, tx_id);
+        if let Some(tx) = self.transactions.get_mut(&key) {
+            if tx.state == TxState::Disputed {
+                tx.state = TxState::ChargedBack;
+                tx.disputed_amount = amount;
+                log::info!("charged back tx_id {}", tx_id)
+            }
+        }
+    }
+
+    /// Marks the hold as captured, or counts `amount` against the evicted
+    /// txs if the hold has been evicted while pending, its id possibly taken
+    /// by another client since.
+    pub fn capture_transaction(&mut self, client_id: C, tx_id: T, amount: Decimal) {
+        let key = self.key(client_id, tx_id);
+        match self.transactions.get_mut(&key) {
+            Some(tx)
+                if tx.client_id == client_id
+                    && tx.tx_type == TransactionType::Hold
+                    && tx.state == TxState::Normal =>
+            {
+                tx.state = TxState::Captured;
+            }
+            _ => self.evicted_effect -= amount,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "C: Serialize",
+    deserialize = "C: ClientId + Deserialize<'de>"
+))]
+pub struct AccountStore<C = u16> {
+    // maps client_id to account data, ordered so that output is deterministic
+    #[serde(with = "as_pairs")]
+    accounts: BTreeMap<C, AccountData>,
 }
 
-#[derive(Debug)]
-pub struct AccountStore {
-    // maps client_id to account data
-    accounts: HashMap<u16, AccountData>,
+impl<C: ClientId> Default for AccountStore<C> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl AccountStore {
+impl<C: ClientId> AccountStore<C> {
     pub fn new() -> Self {
         Self {
-            accounts: HashMap::new(),
+            accounts: BTreeMap::new(),
         }
     }
 
-    pub fn find_by_id(&mut self, id: &u16) -> Option<AccountData> {
-        self.accounts.get(id).map(|x| *x)
+    pub fn find_by_id(&self, id: &C) -> Option<AccountData> {
+        self.accounts.get(id).copied()
     }
 
-    pub fn add_or_update_account(&mut self, id: &u16, data: &AccountData) {
+    pub fn add_or_update_account(&mut self, id: &C, data: &AccountData) {
         self.accounts.insert(*id, *data);
         log::info!("saved account (id: {}, data: {:?})", id, data);
     }
 
-    pub fn find_all(&self) -> Iter<u16, AccountData> {
+    pub fn remove_account(&mut self, id: &C) -> Option<AccountData> {
+        self.accounts.remove(id)
+    }
+
+    /// Iterates over the accounts in ascending client id order.
+    pub fn find_all(&self) -> btree_map::Iter<'_, C, AccountData> {
         self.accounts.iter()
     }
+
+    /// Moves every account of `other` into this store, replacing accounts with
+    /// the same id.
+    pub fn merge(&mut self, other: Self) {
+        self.accounts.extend(other.accounts);
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "C: Serialize, T: Serialize",
+    deserialize = "C: ClientId + Deserialize<'de>, T: TxId + Deserialize<'de>"
+))]
+pub struct HoldStore<C = u16, T = u32> {
+    // maps (client_id, tx_id) of pending holds to (clock when placed, amount)
+    #[serde(with = "as_pairs")]
+    holds: HashMap<(C, T), (u64, Decimal)>,
+    // number of rows a hold lasts unless captured; None keeps holds until
+    // they are captured
+    expiry: Option<u64>,
+    // rows seen so far, advanced by tick()
+    clock: u64,
+    // (clock when placed, key) of holds, oldest first; holds captured in the
+    // meantime are skipped once they reach the front
+    expiry_queue: VecDeque<(u64, (C, T))>,
+}
+
+impl<C: ClientId, T: TxId> Default for HoldStore<C, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: ClientId, T: TxId> HoldStore<C, T> {
+    pub fn new() -> Self {
+        Self {
+            holds: HashMap::new(),
+            expiry: None,
+            clock: 0,
+            expiry_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn set_expiry(&mut self, rows: u64) {
+        self.expiry = Some(rows);
+    }
+
+    /// Advances the store by one row, returning the (client_id, tx_id, amount)
+    /// of the holds that expired uncaptured. They are no longer pending.
+    pub fn tick(&mut self) -> Vec<(C, T, Decimal)> {
+        self.clock += 1;
+        let mut expired = Vec::new();
+        let expiry = match self.expiry {
+            Some(expiry) => expiry,
+            None => return expired,
+        };
+        while let Some((placed_at, key)) = self.expiry_queue.front().copied() {
+            if placed_at.saturating_add(expiry) > self.clock {
+                break;
+            }
+            self.expiry_queue.pop_front();
+            // a hold placed again under the same ids has an entry of its own
+            if self.holds.get(&key).is_some_and(|(at, _)| *at == placed_at) {
+                if let Some((_, amount)) = self.holds.remove(&key) {
+                    expired.push((key.0, key.1, amount));
+                }
+            }
+        }
+        expired
+    }
+
+    pub fn place(&mut self, client_id: C, tx_id: T, amount: Decimal) {
+        self.holds.insert((client_id, tx_id), (self.clock, amount));
+        self.expiry_queue
+            .push_back((self.clock, (client_id, tx_id)));
+        log::info!("placed hold tx_id {} of {}", tx_id, amount);
+    }
+
+    /// Amount of the pending hold placed by `client_id` as `tx_id`.
+    pub fn find_by_id(&self, client_id: C, tx_id: T) -> Option<Decimal> {
+        self.holds
+            .get(&(client_id, tx_id))
+            .map(|(_, amount)| *amount)
+    }
+
+    /// Ends the pending hold, returning its amount.
+    pub fn capture(&mut self, client_id: C, tx_id: T) -> Option<Decimal> {
+        let amount = self
+            .holds
+            .remove(&(client_id, tx_id))
+            .map(|(_, amount)| amount);
+        if amount.is_some() {
+            log::info!("captured hold tx_id {}", tx_id);
+        }
+        amount
+    }
+
+    /// Drops every pending hold of `client_id` without releasing it.
+    pub fn clear_client(&mut self, client_id: C) {
+        self.holds.retain(|(id, _), _| *id != client_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.holds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.holds.is_empty()
+    }
+
+    /// Moves every pending hold of `other` into this store, aged as they were
+    /// in `other`.
+    pub fn merge(&mut self, other: Self) {
+        for (key, (placed_at, amount)) in other.holds {
+            let age = other.clock - placed_at;
+            let placed_at = self.clock.saturating_sub(age);
+            self.holds.insert(key, (placed_at, amount));
+            self.expiry_queue.push_back((placed_at, key));
+        }
+        self.expiry_queue
+            .make_contiguous()
+            .sort_by_key(|(placed_at, _)| *placed_at);
+    }
 }
 
-#[derive(Debug)]
-pub struct LockedAccountStore {
-    locked_accounts: HashSet<u16>,
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "C: Serialize",
+    deserialize = "C: ClientId + Deserialize<'de>"
+))]
+pub struct LockedAccountStore<C = u16> {
+    locked_accounts: HashSet<C>,
+}
+
+impl<C: ClientId> Default for LockedAccountStore<C> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl LockedAccountStore {
+impl<C: ClientId> LockedAccountStore<C> {
     pub fn new() -> Self {
         Self {
             locked_accounts: HashSet::new(),
         }
     }
 
-    pub fn lock_account(&mut self, id: u16) {
+    pub fn lock_account(&mut self, id: C) {
         self.locked_accounts.insert(id);
         log::info!("locked account {}", id);
     }
 
-    pub fn is_account_locked(&self, id: &u16) -> bool {
+    pub fn unlock_account(&mut self, id: &C) {
+        if self.locked_accounts.remove(id) {
+            log::info!("unlocked account {}", id);
+        }
+    }
+
+    pub fn is_account_locked(&self, id: &C) -> bool {
         self.locked_accounts.contains(id)
     }
+
+    /// Iterates over the locked accounts in no particular order.
+    pub fn locked_iter(&self) -> impl Iterator<Item = C> + '_ {
+        self.locked_accounts.iter().copied()
+    }
+
+    pub fn count(&self) -> usize {
+        self.locked_accounts.len()
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.locked_accounts.extend(other.locked_accounts);
+    }
+}
+
+// (de)serializes maps as lists of (key, value) pairs, as formats like JSON
+// only allow string keys
+mod as_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, S, M, K, V>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, D, M, K, V>(deserializer: D) -> Result<M, D::Error>
+    where
+        D: Deserializer<'de>,
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+    {
+        Vec::<(K, V)>::deserialize(deserializer).map(|pairs| pairs.into_iter().collect())
+    }
 }
 
 pub enum DataError {
     AlreadyExists,
+    // the existing entry is under dispute
+    AlreadyDisputed,
 }
@@ -0,0 +1,522 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{self, Command, Output},
+};
+
+fn write_input(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("payments_engine_{}_{}.csv", name, process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn run(input: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--log-level", "error", "--log-file", "-"])
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap()
+}
+
+const ERRONEOUS_INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 10.0
+withdrawal, 2, 2, 1.0
+withdrawal, 1, 3, 50.0
+dispute, 1, 9,
+withdrawal, 3, 4, 1.0
+deposit, 1, 5, 5.0
+";
+
+#[test]
+fn should_abort_once_max_errors_exceeded() {
+    let input = write_input("max_errors_exceeded", ERRONEOUS_INPUT);
+    let output = run(&input, &["--max-errors", "2"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("aborting after 3 errors"), "{}", stderr);
+    assert!(stderr.contains("AccountNotFound: 1"), "{}", stderr);
+    assert!(stderr.contains("InsufficientFunds: 1"), "{}", stderr);
+    assert!(stderr.contains("TxNotFound: 1"), "{}", stderr);
+}
+
+#[test]
+fn should_report_missing_input_file() {
+    let input = env::temp_dir().join(format!("payments_engine_missing_{}.csv", process::id()));
+    let output = run(&input, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(&format!("could not read {}", input.display())),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+#[cfg(not(feature = "interrupt"))]
+fn should_refuse_follow_without_interrupt_feature() {
+    let input = write_input("follow", "type, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+    let output = run(&input, &["--follow"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "flag --follow requires building with the interrupt feature\n"
+    );
+}
+
+#[test]
+fn should_complete_within_max_errors() {
+    let input = write_input("max_errors_within", ERRONEOUS_INPUT);
+    let output = run(&input, &["--max-errors", "4"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,15,15,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_log_errors_as_json_lines() {
+    let input = write_input("json_logs", ERRONEOUS_INPUT);
+    let output = run(&input, &["--log-format", "json"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4, "{}", stderr);
+    let line = &lines[1];
+    assert_eq!(line["level"], "ERROR");
+    assert_eq!(line["target"], "payments_engine::pipeline");
+    assert_eq!(
+        line["message"],
+        "client 1 has insufficient available funds: 50 requested, 10 available"
+    );
+    assert_eq!(line["client_id"], 1);
+    assert_eq!(line["tx_id"], 3);
+    assert!(line["timestamp"].is_string());
+}
+
+#[test]
+fn should_process_without_logging_when_log_file_unwritable() {
+    let input = write_input(
+        "unwritable_log",
+        "type, client, tx, amount\ndeposit, 1, 1, 2.5\n",
+    );
+    let log_file = env::temp_dir()
+        .join(format!("payments_engine_no_such_dir_{}", process::id()))
+        .join("output.log");
+    let output = run(&input, &["--log-file", log_file.to_str().unwrap()]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("continuing without logging"), "{}", stderr);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,2.5,2.5,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_skip_malformed_rows_and_report_their_line() {
+    let input = write_input(
+        "malformed_row",
+        "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, abc, 2, 5.0
+deposit, 1, 3, 5.0
+",
+    );
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("skipping malformed row on line 3"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,15,15,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_skip_rows_with_unknown_type() {
+    let input = write_input(
+        "unknown_type",
+        "type, client, tx, amount
+deposit, 1, 1, 10.0
+payout, 1, 2, 5.0
+deposit, 1, 3, 5.0
+",
+    );
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("tx 2 has unknown type payout"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,15,15,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_read_transfer_destination() {
+    let input = write_input(
+        "transfer",
+        "type, client, tx, amount, dest
+deposit, 1, 1, 10.0,
+transfer, 1, 2, 4.0, 2
+",
+    );
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        [
+            "1,6,6,0.0000,false",
+            "2,4,4,0.0000,false",
+            "client,total,available,held,locked",
+        ]
+    );
+}
+
+#[test]
+fn should_read_input_without_header() {
+    let rows = "deposit, 1, 1, 10.0
+deposit, 2, 2, 3.0
+withdrawal, 1, 3, 4.0
+dispute, 2, 2,
+";
+    let with_header = write_input(
+        "with_header",
+        &format!("type, client, tx, amount\n{}", rows),
+    );
+    let without_header = write_input("without_header", rows);
+    let expected = run(&with_header, &[]);
+    let output = run(&without_header, &["--no-header"]);
+    assert!(output.status.success());
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut expected: Vec<String> = String::from_utf8(expected.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut actual: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    expected.sort_unstable();
+    actual.sort_unstable();
+    assert_eq!(actual, expected);
+    assert_eq!(actual.len(), 3);
+}
+
+#[test]
+fn should_round_output_with_selected_strategy() {
+    let input = write_input(
+        "rounding",
+        "type, client, tx, amount\ndeposit, 1, 1, 1.23445\n",
+    );
+    let half_even = run(&input, &[]);
+    let half_up = run(&input, &["--rounding", "half-up"]);
+    assert_eq!(
+        String::from_utf8(half_even.stdout).unwrap(),
+        "client,total,available,held,locked\n1,1.2344,1.2344,0.0000,false\n"
+    );
+    assert_eq!(
+        String::from_utf8(half_up.stdout).unwrap(),
+        "client,total,available,held,locked\n1,1.2345,1.2345,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_replay_recorded_run() {
+    let input = write_input("record_source", ERRONEOUS_INPUT);
+    let recording =
+        env::temp_dir().join(format!("payments_engine_recording_{}.csv", process::id()));
+    let original = run(&input, &["--record", recording.to_str().unwrap()]);
+    let replay = run(&recording, &[]);
+    assert!(original.status.success());
+    assert_eq!(replay.stdout, original.stdout);
+    let dry_run = run(&input, &["--dry-run"]);
+    let replayed_dry_run = run(&recording, &["--dry-run"]);
+    assert_eq!(
+        String::from_utf8(replayed_dry_run.stdout).unwrap(),
+        String::from_utf8(dry_run.stdout).unwrap()
+    );
+}
+
+#[test]
+fn should_replay_recorded_run_within_time_window() {
+    let input = write_input(
+        "record_window_source",
+        "type, client, tx, amount, dest, timestamp
+deposit, 1, 1, 10.0, , 2024-01-01T09:00:00Z
+deposit, 1, 2, 5.0, , 2024-01-02T09:00:00+02:00
+withdrawal, 1, 3, 2.0, , 2024-01-02T10:00:00Z
+dispute, 1, 2, , , 2024-01-02T11:00:00Z
+deposit, 2, 4, 1.0, , 2024-01-03T09:00:00Z
+",
+    );
+    let recording = env::temp_dir().join(format!(
+        "payments_engine_window_recording_{}.csv",
+        process::id()
+    ));
+    let window = [
+        "--from",
+        "2024-01-02T00:00:00Z",
+        "--to",
+        "2024-01-03T00:00:00Z",
+    ];
+    let record = [&window[..], &["--record", recording.to_str().unwrap()]].concat();
+    let original = run(&input, &record);
+    let replay = run(&recording, &window);
+    fs::remove_file(&recording).unwrap();
+    assert!(original.status.success());
+    assert!(replay.status.success());
+    assert_eq!(replay.stdout, original.stdout);
+    assert_eq!(
+        String::from_utf8(original.stdout).unwrap(),
+        "client,total,available,held,locked\n1,3,-2,5,false\n"
+    );
+}
+
+#[test]
+fn should_refuse_row_overflowing_total() {
+    let input = write_input(
+        "overflow",
+        "type, client, tx, amount
+deposit, 1, 1, 50000000000000000000000000000.0
+dispute, 1, 1,
+interest, 1, 2, 50000000000000000000000000000.0
+",
+    );
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n\
+         1,50000000000000000000000000000,0.0000,50000000000000000000000000000,false\n"
+    );
+    let output = run(&input, &["--dry-run"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "error,count\nAmountOverflow,1\n"
+    );
+}
+
+#[test]
+fn should_write_accounts_in_ascending_client_order() {
+    let rows: String = (1..=50)
+        .rev()
+        .map(|client| format!("deposit, {}, {}, 1.0\n", client * 7 % 50 + 1, client))
+        .collect();
+    let input = write_input("sorted", &format!("type, client, tx, amount\n{}", rows));
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    let clients: Vec<u16> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(clients, (1..=50).collect::<Vec<_>>());
+}
+
+#[test]
+fn should_write_accounts_in_first_seen_order() {
+    let input = write_input(
+        "first_seen",
+        "type, client, tx, amount
+deposit, 5, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 5, 3, 3.0
+deposit, 3, 4, 4.0
+",
+    );
+    let output = run(&input, &["--order", "first-seen"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked
+\
+         5,4,4,0.0000,false\n\
+         2,2,2,0.0000,false\n\
+         3,4,4,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_read_amounts_in_minor_units() {
+    let input = write_input(
+        "minor_units",
+        "type, client, tx, amount_cents
+deposit, 1, 1, 1050
+withdrawal, 1, 2, 25
+deposit, 2, 3, 7
+",
+    );
+    let output = run(&input, &["--minor-units", "2"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,10.25,10.25,0.0000,false\n2,0.07,0.07,0.0000,false\n"
+    );
+    let decimal = write_input(
+        "minor_units_decimal",
+        "type, client, tx, amount\ndeposit, 1, 1, 10.50\n",
+    );
+    let output = run(&decimal, &["--minor-units", "2"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("skipping malformed row on line 2"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn should_trim_per_selected_mode() {
+    let padded_field = write_input(
+        "trim_field",
+        "type,client,tx,amount\ndeposit,1,1, 10.0\ndeposit,1,2,1 0.0\n",
+    );
+    let padded_header = write_input("trim_header", "type,client, tx,amount\ndeposit,1,1,10.0\n");
+    let balance = "client,total,available,held,locked\n1,10,10,0.0000,false\n";
+    let empty = "client,total,available,held,locked\n";
+    for (mode, field_output, header_output) in [
+        ("all", balance, balance),
+        ("fields", balance, empty),
+        ("headers", empty, balance),
+        ("none", empty, empty),
+    ] {
+        let output = run(&padded_field, &["--trim", mode]);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            field_output,
+            "{}",
+            mode
+        );
+        // spaces within a value are never trimmed
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("tx 2 has an amount that is not a supported number"),
+            "{}",
+            stderr
+        );
+        let output = run(&padded_header, &["--trim", mode]);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            header_output,
+            "{}",
+            mode
+        );
+    }
+}
+
+#[test]
+fn should_skip_rows_with_unparseable_amount() {
+    let input = write_input(
+        "unparseable_amount",
+        "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 1, 2, 1234567890123456789012345678901234567890
+deposit, 1, 3, 5.0
+",
+    );
+    let output = run(&input, &["--dry-run"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "error,count\nAmountUnparseable,1\n"
+    );
+    let output = run(&input, &[]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,15,15,0.0000,false\n"
+    );
+}
+
+const TIMESTAMPED_INPUT: &str = "type, client, tx, amount, dest, timestamp
+deposit, 1, 1, 10.0, , 2023-12-31T23:59:59Z
+deposit, 1, 2, 5.0, , 2024-01-01T00:00:00Z
+withdrawal, 1, 3, 2.0, , 2024-01-15T12:00:00+02:00
+deposit, 1, 4, 1.0, ,
+deposit, 1, 5, 7.0, , 2024-02-01T00:00:00Z
+";
+
+#[test]
+fn should_process_only_rows_within_time_window() {
+    let input = write_input("time_window", TIMESTAMPED_INPUT);
+    let window = [
+        "--from",
+        "2024-01-01T00:00:00Z",
+        "--to",
+        "2024-02-01T00:00:00Z",
+    ];
+    let output = run(&input, &[&window[..], &["--untimed", "skip"]].concat());
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,3,3,0.0000,false\n"
+    );
+
+    let output = run(&input, &[&window[..], &["--dry-run"]].concat());
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "error,count\nMissingTimestamp,1\n"
+    );
+}
+
+#[test]
+fn should_process_every_row_without_time_window() {
+    let input = write_input("no_time_window", TIMESTAMPED_INPUT);
+    let output = run(&input, &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,21,21,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_fail_on_lock_after_writing_output() {
+    let input = write_input(
+        "fail_on_lock",
+        "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 2, 2, 5.0
+dispute, 2, 2,
+chargeback, 2, 2,
+",
+    );
+    let output = run(&input, &["--fail-on-lock"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,10,10,0.0000,false\n2,0.0000,0.0000,0.0000,true\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("accounts ended locked: 2"), "{}", stderr);
+
+    assert!(run(&input, &[]).status.success());
+}
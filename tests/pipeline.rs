@@ -0,0 +1,488 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    num::NonZeroU64,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use payments_engine::{
+    cli::{AmountScale, Args, OutputFormat},
+    pipeline::{self, PipelineError, POLL_INTERVAL},
+};
+use rust_decimal::Decimal;
+
+const INPUT: &str = "type, client, tx, amount
+deposit, 2, 1, 10.0
+deposit, 1, 2, 2.5
+withdrawal, 2, 3, 4.0
+dispute, 1, 2,
+withdrawal, 1, 4, 1.0
+dispute, 3, 9,
+";
+
+fn run(input: &str, args: &Args) -> Result<String, PipelineError> {
+    let mut output = Vec::new();
+    pipeline::run(input.as_bytes(), &mut output, args)?;
+    Ok(String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn should_process_csv_in_memory() {
+    assert_eq!(
+        run(INPUT, &Args::default()).unwrap(),
+        "client,total,available,held,locked\n\
+         1,2.5,0.0000,2.5,false\n\
+         2,6,6,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_count_errors_in_memory() {
+    let args = Args {
+        dry_run: true,
+        ..Args::default()
+    };
+    assert_eq!(
+        run(INPUT, &args).unwrap(),
+        "error,count\nInsufficientFunds,1\nTxNotFound,1\n"
+    );
+    let args = Args {
+        max_errors: Some(1),
+        ..Args::default()
+    };
+    match run(INPUT, &args) {
+        Err(PipelineError::TooManyErrors(counts)) => {
+            assert_eq!(counts.values().sum::<usize>(), 2);
+        }
+        result => panic!("expected too many errors, got {:?}", result),
+    }
+}
+
+#[test]
+fn should_retry_deferred_dispute_after_its_deposit() {
+    let input = "type, client, tx, amount
+dispute, 1, 2,
+deposit, 1, 1, 3.0
+deposit, 1, 2, 2.0
+resolve, 1, 7,
+";
+    let args = Args {
+        dry_run: true,
+        ..Args::default()
+    };
+    assert_eq!(run(input, &args).unwrap(), "error,count\nTxNotFound,2\n");
+    let args = Args {
+        defer_unknown: true,
+        ..args
+    };
+    assert_eq!(run(input, &args).unwrap(), "error,count\nTxNotFound,1\n");
+    let args = Args {
+        dry_run: false,
+        ..args
+    };
+    assert_eq!(
+        run(input, &args).unwrap(),
+        "client,total,available,held,locked\n1,5,3,2,false\n"
+    );
+}
+
+#[test]
+fn should_record_deferred_row_once_it_is_resolved() {
+    let path = env::temp_dir().join(format!("payments_engine_deferred_{}.csv", process::id()));
+    let input = "type, client, tx, amount
+dispute, 1, 2,
+deposit, 1, 1, 3.0
+resolve, 1, 7,
+deposit, 1, 2, 2.0
+";
+    let args = Args {
+        defer_unknown: true,
+        record: Some(path.clone()),
+        ..Args::default()
+    };
+    run(input, &args).unwrap();
+    let recording = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    // each row once, deferred ones where they took effect
+    assert_eq!(
+        recording,
+        "type,client,tx,amount,dest,timestamp
+deposit,1,1,3.0,,
+deposit,1,2,2.0,,
+dispute,1,2,,,
+resolve,1,7,,,
+"
+    );
+}
+
+#[test]
+fn should_ignore_rows_of_a_file_sent_twice_when_idempotent() {
+    let input = "type, client, tx, amount
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 1.0
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 1.0
+deposit, 1, 1, 4.0
+";
+    let args = Args {
+        dry_run: true,
+        ..Args::default()
+    };
+    assert_eq!(run(input, &args).unwrap(), "error,count\nDuplicateTx,3\n");
+    let args = Args {
+        idempotent: true,
+        ..args
+    };
+    assert_eq!(run(input, &args).unwrap(), "error,count\nDuplicateTx,1\n");
+    let args = Args {
+        dry_run: false,
+        ..args
+    };
+    assert_eq!(
+        run(input, &args).unwrap(),
+        "client,total,available,held,locked\n1,2,2,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_reject_amounts_finer_than_config_precision() {
+    let config = env::temp_dir().join(format!("payments_engine_scale_{}.toml", process::id()));
+    fs::write(&config, "precision = 2\n").unwrap();
+    let args = Args {
+        config: Some(config.clone()),
+        amount_scale: AmountScale::Reject,
+        dry_run: true,
+        ..Args::default()
+    };
+    let output = run(
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 1.25\n\
+         deposit, 1, 2, 1.125\n",
+        &args,
+    );
+    fs::remove_file(&config).unwrap();
+    assert_eq!(output.unwrap(), "error,count\nAmountTooPrecise,1\n");
+}
+
+#[test]
+fn should_ignore_byte_order_mark() {
+    let input = format!("\u{feff}{}", INPUT);
+    assert_eq!(
+        run(&input, &Args::default()).unwrap(),
+        run(INPUT, &Args::default()).unwrap()
+    );
+    let args = Args {
+        has_headers: false,
+        ..Args::default()
+    };
+    assert_eq!(
+        run("\u{feff}deposit, 1, 1, 2.0\n", &args).unwrap(),
+        "client,total,available,held,locked\n1,2,2,0.0000,false\n"
+    );
+}
+
+#[test]
+fn should_count_rows_with_invalid_utf8() {
+    let input = b"type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, \xff\xfe, 2, 1.0\n";
+    let args = Args {
+        dry_run: true,
+        ..Args::default()
+    };
+    let mut output = Vec::new();
+    pipeline::run(&input[..], &mut output, &args).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "error,count\nInvalidUtf8,1\n"
+    );
+}
+
+#[test]
+fn should_report_progress_every_n_rows() {
+    let mut input = String::from("type, client, tx, amount\n");
+    for tx in 1..=2500 {
+        // every hundredth row is a withdrawal the client cannot afford
+        let tx_type = if tx % 100 == 0 {
+            "withdrawal, 2"
+        } else {
+            "deposit, 1"
+        };
+        input.push_str(&format!("{}, {}, 1.0\n", tx_type, tx));
+    }
+    let args = Args {
+        progress: NonZeroU64::new(1000),
+        ..Args::default()
+    };
+    let mut output = Vec::new();
+    let mut progress = Vec::new();
+    pipeline::run_with_progress(
+        input.as_bytes(),
+        &mut output,
+        &mut progress,
+        &args,
+        &AtomicBool::new(false),
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(progress).unwrap(),
+        "processed 1,000 rows, 10 errors\nprocessed 2,000 rows, 20 errors\n"
+    );
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("client,total,available,held,locked\n1,2475,"));
+}
+
+#[test]
+fn should_write_failed_rows_to_error_output() {
+    let path = env::temp_dir().join(format!("payments_engine_errors_{}.csv", process::id()));
+    let input = b"type, client, tx, amount
+deposit, 1, 1, 5.0
+withdrawal, 1, 2, 8.0
+deposit, 1, 1, 1.0
+dispute, 3, 9,
+refund, 1, 4, 1.0
+not, a, row
+deposit, \xff, 5, 1.0
+resolve, 1, 1,
+";
+    let args = Args {
+        error_output: Some(path.clone()),
+        ..Args::default()
+    };
+    let mut output = Vec::new();
+    pipeline::run(&input[..], &mut output, &args).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,total,available,held,locked\n1,5,5,0.0000,false\n"
+    );
+    let errors = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        errors,
+        "tx_id,client_id,error
+2,1,\"client 1 has insufficient available funds: 8 requested, 5 available\"
+1,1,tx 1 has already been processed
+9,3,tx 9 does not exist
+4,1,tx 4 has unknown type refund
+,,\"CSV error: record 6 (line: 7, byte: 118): found record with 3 fields, but the previous record has 4 fields\"
+,,\"CSV parse error: record 7 (line 8, field: 1, byte: 130): invalid utf-8: invalid UTF-8 in field 1 near byte index 0\"
+1,1,tx 1 is not under dispute
+"
+    );
+}
+
+#[test]
+fn should_read_semicolon_file_with_decimal_commas() {
+    let input = "type; client; tx; amount
+deposit; 1; 1; 1,25
+deposit; 2; 2; 3
+withdrawal; 1; 3; 0,5
+";
+    let args = Args {
+        delimiter: b';',
+        decimal_comma: true,
+        ..Args::default()
+    };
+    assert_eq!(
+        run(input, &args).unwrap(),
+        "client,total,available,held,locked\n\
+         1,0.75,0.75,0.0000,false\n\
+         2,3,3,0.0000,false\n"
+    );
+    // without the option the amounts cannot be parsed
+    let args = Args {
+        decimal_comma: false,
+        dry_run: true,
+        ..args
+    };
+    assert_eq!(
+        run(input, &args).unwrap(),
+        "error,count\nAmountUnparseable,2\n"
+    );
+}
+
+#[test]
+fn should_leave_out_unlocked_accounts_without_funds() {
+    let input = "type, client, tx, amount
+deposit, 1, 1, 5.0
+withdrawal, 1, 2, 5.0
+deposit, 2, 3, 3.0
+deposit, 3, 4, 4.0
+dispute, 3, 4,
+chargeback, 3, 4,
+deposit, 4, 5, 1.0
+dispute, 4, 5,
+";
+    let args = Args {
+        nonzero_only: true,
+        ..Args::default()
+    };
+    assert_eq!(
+        run(input, &args).unwrap(),
+        "client,total,available,held,locked\n\
+         2,3,3,0.0000,false\n\
+         3,0.0000,0.0000,0.0000,true\n\
+         4,1,0.0000,1,false\n"
+    );
+    let output = run(input, &Args::default()).unwrap();
+    assert!(output.contains("\n1,0.0000,0.0000,0.0000,false\n"));
+}
+
+#[test]
+fn should_append_summary_row_summing_accounts() {
+    let args = Args {
+        summary_row: true,
+        ..Args::default()
+    };
+    let output = run(INPUT, &args).unwrap();
+    let mut rows: Vec<Vec<&str>> = output
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').collect())
+        .collect();
+    let summary = rows.pop().unwrap();
+    assert_eq!(summary, ["TOTAL", "8.5", "6", "2.5", ""]);
+    for column in 1..4 {
+        let sum: Decimal = rows
+            .iter()
+            .map(|row| row[column].parse::<Decimal>().unwrap())
+            .sum();
+        assert_eq!(summary[column].parse::<Decimal>().unwrap(), sum);
+    }
+}
+
+#[test]
+fn should_write_one_json_object_per_line() {
+    let args = Args {
+        output: OutputFormat::Ndjson,
+        ..Args::default()
+    };
+    let accounts: Vec<serde_json::Value> = run(INPUT, &args)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        accounts,
+        [
+            serde_json::json!({
+                "client_id": 1,
+                "available": "0.0000",
+                "held": "2.5",
+                "total": "2.5",
+                "locked": false,
+            }),
+            serde_json::json!({
+                "client_id": 2,
+                "available": "6",
+                "held": "0.0000",
+                "total": "6",
+                "locked": false,
+            }),
+        ]
+    );
+
+    let args = Args {
+        dry_run: true,
+        ..args
+    };
+    let counts: Vec<serde_json::Value> = run(INPUT, &args)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            serde_json::json!({ "error": "InsufficientFunds", "count": 1 }),
+            serde_json::json!({ "error": "TxNotFound", "count": 1 }),
+        ]
+    );
+}
+
+#[test]
+fn should_process_rows_appended_to_followed_file() {
+    let path = env::temp_dir().join(format!("payments_engine_follow_{}.csv", process::id()));
+    let mut file = File::create(&path).unwrap();
+    file.write_all(b"type, client, tx, amount\ndeposit, 1, 1, 5.0\n")
+        .unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let follower = {
+        let (path, stop) = (path.clone(), stop.clone());
+        thread::spawn(move || {
+            let args = Args {
+                follow: true,
+                ..Args::default()
+            };
+            let mut output = Vec::new();
+            pipeline::run_until(File::open(path).unwrap(), &mut output, &args, &stop).unwrap();
+            String::from_utf8(output).unwrap()
+        })
+    };
+    // the row is only complete once its newline is written; a row that is
+    // still incomplete when stopping is left out
+    let pause = || thread::sleep(POLL_INTERVAL * 3);
+    for part in [
+        "deposit, 2, 2, 1",
+        "0.0\n",
+        "withdrawal, 1, 3, 2.0\n",
+        "deposit, 1, 4, 7",
+    ] {
+        pause();
+        file.write_all(part.as_bytes()).unwrap();
+    }
+    pause();
+    stop.store(true, Ordering::Relaxed);
+    let output = follower.join().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        output,
+        "client,total,available,held,locked\n\
+         1,3,3,0.0000,false\n\
+         2,10,10,0.0000,false\n"
+    );
+}
+
+// hands out the input in two reads, setting the flag on the second one as if
+// Ctrl-C was pressed halfway through
+struct InterruptedInput<'a> {
+    parts: Vec<&'a [u8]>,
+    stop: &'a AtomicBool,
+}
+
+impl Read for InterruptedInput<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.parts.is_empty() {
+            return Ok(0);
+        }
+        if self.parts.len() == 1 {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        let part = self.parts.remove(0);
+        buf[..part.len()].copy_from_slice(part);
+        Ok(part.len())
+    }
+}
+
+#[test]
+fn should_write_partial_output_once_stopped() {
+    let (head, tail) = INPUT.split_at(INPUT.find("withdrawal").unwrap());
+    let stop = AtomicBool::new(false);
+    let input = InterruptedInput {
+        parts: vec![head.as_bytes(), tail.as_bytes()],
+        stop: &stop,
+    };
+    let mut output = Vec::new();
+    pipeline::run_until(input, &mut output, &Args::default(), &stop).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,total,available,held,locked\n\
+         1,2.5,2.5,0.0000,false\n\
+         2,10,10,0.0000,false\n"
+    );
+}
@@ -0,0 +1,118 @@
+use payments_engine::{
+    engine::{Engine, EngineBuilder},
+    models::{TransactionType, TxRow},
+};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+const TX_TYPES: [TransactionType; 11] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Resolve,
+    TransactionType::Dispute,
+    TransactionType::Chargeback,
+    TransactionType::Reversal,
+    TransactionType::Interest,
+    TransactionType::Transfer,
+    TransactionType::Hold,
+    TransactionType::Capture,
+    TransactionType::Cancel,
+];
+
+// few clients and tx ids, so that disputes and captures find their targets
+fn row(amount: impl Strategy<Value = Decimal>) -> impl Strategy<Value = TxRow> {
+    (
+        prop::sample::select(&TX_TYPES[..]),
+        0u16..4,
+        0u32..32,
+        prop::option::weighted(0.9, amount),
+        prop::option::of(0u16..4),
+    )
+        .prop_map(|(tx_type, client_id, tx_id, amount, dest)| TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            dest,
+            timestamp: None,
+        })
+}
+
+// a withdrawal larger than any balance the other rows can build up, which
+// fails, followed by a dispute and a chargeback of it
+fn failed_withdrawal() -> impl Strategy<Value = Vec<TxRow>> {
+    (0u16..4, 32u32..64).prop_map(|(client_id, tx_id)| {
+        [
+            (TransactionType::Withdrawal, Some(Decimal::MAX)),
+            (TransactionType::Dispute, None),
+            (TransactionType::Chargeback, None),
+        ]
+        .into_iter()
+        .map(|(tx_type, amount)| TxRow {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            dest: None,
+            timestamp: None,
+        })
+        .collect()
+    })
+}
+
+fn small_amount() -> impl Strategy<Value = Decimal> {
+    (1i64..1_000_000).prop_map(|cents| Decimal::new(cents, 2))
+}
+
+// spans the whole Decimal range, signs and scales included
+fn any_amount() -> impl Strategy<Value = Decimal> {
+    (
+        any::<u32>(),
+        any::<u32>(),
+        any::<u32>(),
+        any::<bool>(),
+        0u32..=28,
+    )
+        .prop_map(|(lo, mid, hi, negative, scale)| {
+            Decimal::from_parts(lo, mid, hi, negative, scale)
+        })
+}
+
+proptest! {
+    #[test]
+    fn should_keep_invariants_on_any_sequence(
+        rows in prop::collection::vec(row(small_amount()), 0..200)
+    ) {
+        let mut engine = EngineBuilder::new().with_hold_expiry(10).build();
+        for row in &rows {
+            let _ = engine.process_row(row);
+            prop_assert_eq!(engine.verify_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn should_keep_invariants_around_failed_withdrawals(
+        blocks in prop::collection::vec(
+            (prop::collection::vec(row(small_amount()), 0..20), failed_withdrawal()),
+            1..10,
+        )
+    ) {
+        let mut engine = EngineBuilder::new().with_tx_retention(20).build();
+        for (rows, failed) in &blocks {
+            for row in rows.iter().chain(failed) {
+                let _ = engine.process_row(row);
+                prop_assert_eq!(engine.verify_invariants(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn should_not_panic_on_any_amount(
+        rows in prop::collection::vec(row(any_amount()), 0..200)
+    ) {
+        let mut engine = Engine::new();
+        for row in &rows {
+            let _ = engine.process_row(row);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs;
+
+use payments_engine::{
+    engine::{Engine, EngineBuilder},
+    models::{TransactionType, TxRow},
+};
+use rust_decimal::Decimal;
+
+const RETENTION: u64 = 10_000;
+
+/// Generates rows on the fly so that arbitrarily large inputs never have to be
+/// materialized: deposits spread over 1000 clients, every third row
+/// withdrawing part of the previous deposit.
+struct RowGenerator {
+    next: u32,
+    len: u32,
+}
+
+impl RowGenerator {
+    fn new(len: u32) -> Self {
+        Self { next: 0, len }
+    }
+}
+
+impl Iterator for RowGenerator {
+    type Item = TxRow;
+
+    fn next(&mut self) -> Option<TxRow> {
+        if self.next == self.len {
+            return None;
+        }
+        let tx_id = self.next;
+        self.next += 1;
+        let withdrawal = tx_id % 3 == 2;
+        Some(TxRow {
+            tx_type: match withdrawal {
+                true => TransactionType::Withdrawal,
+                false => TransactionType::Deposit,
+            },
+            client_id: ((tx_id - withdrawal as u32) % 1000) as u16,
+            tx_id,
+            amount: Some(Decimal::new(if withdrawal { 50 } else { 100 }, 2)),
+            dest: None,
+            timestamp: None,
+        })
+    }
+}
+
+/// Resident memory of this process in KiB, as reported by the kernel.
+fn resident_kib() -> u64 {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+        .unwrap()
+}
+
+fn process(engine: &mut Engine, rows: impl Iterator<Item = TxRow>) {
+    for row in rows {
+        engine.process_row(&row).unwrap();
+    }
+}
+
+#[test]
+fn should_keep_tx_store_bounded_with_retention() {
+    let mut engine = EngineBuilder::new().with_tx_retention(RETENTION).build();
+    process(&mut engine, RowGenerator::new(100_000));
+    assert!(engine.stored_tx_count() <= RETENTION as usize);
+    assert_eq!(engine.summary().total_deposits, Decimal::new(66_667, 0));
+}
+
+// 10M rows take a while without optimizations; run with
+// `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+#[cfg(target_os = "linux")]
+fn should_stream_ten_million_rows_in_bounded_memory() {
+    let mut engine = EngineBuilder::new().with_tx_retention(RETENTION).build();
+    let mut rows = RowGenerator::new(10_000_000);
+    // let the maps reach their steady-state size before measuring
+    process(&mut engine, rows.by_ref().take(1_000_000));
+    let baseline = resident_kib();
+    process(&mut engine, rows);
+    let growth = resident_kib().saturating_sub(baseline);
+    assert!(engine.stored_tx_count() <= RETENTION as usize);
+    assert!(growth < 16 * 1024, "resident memory grew by {} KiB", growth);
+}